@@ -0,0 +1,91 @@
+//! Compares the broad phase implementations on the stress test levels - and keeping each between ticks
+//! (only updating entities that moved) against rebuilding it every tick.
+//! Run with `cargo bench --bench broad_phase` (add `--no-default-features` to skip building the SDL front end).
+
+use bwb::game_logic::update_world;
+use bwb::levels::{LevelId, LevelSet};
+use bwb::world::layer_geoms;
+use bwb::BroadPhaseKind;
+use std::time::{Duration, Instant};
+
+/// Levels to run, and how many ticks of each (-1 has ~100k entities, so is much slower)
+const LEVELS: [(LevelId, u32); 2] = [(99, 500), (-1, 20)];
+const DT: i32 = 10;
+
+/// Brute force is far too slow for the stress levels
+const KINDS: [BroadPhaseKind; 3] = [
+    BroadPhaseKind::Grid,
+    BroadPhaseKind::SweepAndPrune,
+    BroadPhaseKind::AabbTree,
+];
+
+struct Timings {
+    /// Mean time for a whole tick (all systems)
+    tick: Duration,
+    /// Mean time to update the broad phase alone, with each tick's geometries
+    broad_phase: Duration,
+    /// Mean number of entities updated per tick
+    updated: f64,
+}
+
+/// Runs the level for the given number of ticks, optionally discarding the broad phase before each one
+fn run(
+    levels: &LevelSet,
+    level: LevelId,
+    ticks: u32,
+    kind: BroadPhaseKind,
+    rebuild: bool,
+) -> Timings {
+    let (mut world, _obj_factory) = levels.init(level, levels.seed(level));
+    world.broad_phase = kind.create();
+    let mut tick = Duration::default();
+    let mut broad_phase_time = Duration::default();
+    let mut broad_phase = kind.create();
+    let mut updated = 0;
+    for _ in 0..ticks {
+        if rebuild {
+            world.broad_phase = kind.create();
+            broad_phase = kind.create();
+        }
+        let start = Instant::now();
+        update_world(&mut world, DT);
+        tick += start.elapsed();
+
+        let layers = layer_geoms(&world.entities, &world.geometries);
+        let start = Instant::now();
+        broad_phase.update(&layers);
+        broad_phase_time += start.elapsed();
+        updated += broad_phase.updated();
+    }
+    Timings {
+        tick: tick / ticks,
+        broad_phase: broad_phase_time / ticks,
+        updated: updated as f64 / ticks as f64,
+    }
+}
+
+fn main() {
+    let levels = LevelSet::builtin();
+    println!("Ticks of {} ms. Times are means per tick.", DT);
+    println!(
+        "{:>6} | {:>6} | {:>15} | {:>10} | {:>12} | {:>12} | {:>10}",
+        "level", "ticks", "broad phase", "mode", "tick (us)", "broad (us)", "updated"
+    );
+    for (level, ticks) in LEVELS.iter() {
+        for kind in KINDS.iter() {
+            for (mode, rebuild) in [("rebuild", true), ("persistent", false)].iter() {
+                let timings = run(&levels, *level, *ticks, *kind, *rebuild);
+                println!(
+                    "{:>6} | {:>6} | {:>15} | {:>10} | {:>12.1} | {:>12.1} | {:>10.1}",
+                    level,
+                    ticks,
+                    format!("{:?}", kind),
+                    mode,
+                    timings.tick.as_secs_f64() * 1e6,
+                    timings.broad_phase.as_secs_f64() * 1e6,
+                    timings.updated
+                );
+            }
+        }
+    }
+}
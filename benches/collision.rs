@@ -0,0 +1,278 @@
+//! Benchmarks the collision system's parts, and the whole tick, with criterion: across object counts (levels 1 to -1),
+//! single- vs multi-threaded (rayon), and spatial grid bin sizes either side of the one `calc_bin_size` picks.
+//! Criterion reports each as it goes, then a summary table is printed at the end.
+//! Run with `cargo bench --no-default-features --bench collision`, optionally with a filter, e.g. `-- build_map`.
+
+use bwb::broad_phase::{calc_bin_size, BroadPhase, SpatialGrid};
+use bwb::game_logic::{collision_system, update_world};
+use bwb::levels::{LevelId, LevelSet};
+use bwb::world::{layer_geoms, GRID_HEIGHT, GRID_WIDTH};
+use bwb::{is_collision, EntityId, EntityKind, Vertex};
+use criterion::{black_box, Bencher, Criterion};
+use rayon::ThreadPool;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Levels to run, from the fewest objects to the most (-1 has ~100k)
+const LEVELS: [LevelId; 4] = [1, 4, 99, -1];
+/// Bin sizes to compare, as multiples of the calculated one
+const BIN_SCALES: [f32; 4] = [0.5, 1.0, 2.0, 4.0];
+const DT: i32 = 10;
+
+/// A benchmark's configuration, and its total time over all iterations
+struct Row {
+    level: LevelId,
+    objects: usize,
+    benchmark: &'static str,
+    threads: Option<usize>,
+    bin_size: Option<i32>,
+    iterations: u64,
+    total: Duration,
+}
+
+/// Results for the summary table, in the order run
+static RESULTS: Mutex<Vec<Row>> = Mutex::new(Vec::new());
+
+/// What's being measured, for the summary table
+#[derive(Clone, Copy)]
+struct Config {
+    level: LevelId,
+    objects: usize,
+    benchmark: &'static str,
+    /// The number of threads, for those run in a sized thread pool
+    threads: Option<usize>,
+    bin_size: Option<i32>,
+}
+
+impl Config {
+    fn id(&self) -> String {
+        let mut id = format!("level {}", self.level);
+        if let Some(threads) = self.threads {
+            id += &format!("/{} threads", threads);
+        }
+        if let Some(bin_size) = self.bin_size {
+            id += &format!("/bin {}", bin_size);
+        }
+        id
+    }
+
+    fn record(&self, iterations: u64, elapsed: Duration) {
+        let mut results = RESULTS.lock().unwrap();
+        let existing = results.iter_mut().find(|row| {
+            (row.level, row.benchmark, row.threads, row.bin_size)
+                == (self.level, self.benchmark, self.threads, self.bin_size)
+        });
+        match existing {
+            Some(row) => {
+                row.iterations += iterations;
+                row.total += elapsed;
+            }
+            None => results.push(Row {
+                level: self.level,
+                objects: self.objects,
+                benchmark: self.benchmark,
+                threads: self.threads,
+                bin_size: self.bin_size,
+                iterations,
+                total: elapsed,
+            }),
+        }
+    }
+}
+
+/// Times the routine (in the thread pool, if given), recording the total for the summary table
+fn measure(
+    bencher: &mut Bencher,
+    config: Config,
+    pool: Option<&ThreadPool>,
+    mut routine: impl FnMut() + Send,
+) {
+    bencher.iter_custom(|iterations| {
+        let mut run = || {
+            let start = Instant::now();
+            for _ in 0..iterations {
+                routine();
+            }
+            start.elapsed()
+        };
+        let elapsed = match pool {
+            Some(pool) => pool.install(run),
+            None => run(),
+        };
+        config.record(iterations, elapsed);
+        elapsed
+    });
+}
+
+/// Thread pools to compare: a single thread, and one per CPU (if there's more than one)
+fn thread_pools() -> Vec<ThreadPool> {
+    let mut counts = vec![1, rayon::current_num_threads()];
+    counts.dedup();
+    counts
+        .into_iter()
+        .map(|threads| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+        })
+        .collect()
+}
+
+fn bench_level(criterion: &mut Criterion, levels: &LevelSet, level: LevelId, pools: &[ThreadPool]) {
+    let (mut world, _obj_factory) = levels.init(level, levels.seed(level));
+    let objects = world.geometries.len();
+    let collision_system = collision_system();
+    let calculated_bin_size = calc_bin_size(
+        &layer_geoms(&world.entities, &world.geometries),
+        (GRID_WIDTH as i32, GRID_HEIGHT as i32),
+    );
+    let bin_sizes: Vec<i32> = BIN_SCALES
+        .iter()
+        .map(|scale| (calculated_bin_size as f32 * scale) as i32)
+        .collect();
+    let config = |benchmark, threads, bin_size| Config {
+        level,
+        objects,
+        benchmark,
+        threads,
+        bin_size,
+    };
+    // The big levels take a while per iteration, so take fewer samples
+    let (sample_size, measurement_time) = if objects > 10_000 {
+        (10, Duration::from_secs(5))
+    } else {
+        (20, Duration::from_secs(2))
+    };
+
+    {
+        let layers = layer_geoms(&world.entities, &world.geometries);
+
+        let mut group = criterion.benchmark_group("build_map");
+        group
+            .sample_size(sample_size)
+            .measurement_time(measurement_time);
+        for pool in pools {
+            for bin_size in bin_sizes.iter() {
+                let config = config(
+                    "build_map",
+                    Some(pool.current_num_threads()),
+                    Some(*bin_size),
+                );
+                group.bench_function(config.id(), |b| {
+                    measure(b, config, Some(pool), || {
+                        let mut grid = SpatialGrid::with_bin_size(*bin_size);
+                        grid.update(&layers);
+                        black_box(grid);
+                    })
+                });
+            }
+        }
+        group.finish();
+
+        let mut group = criterion.benchmark_group("detect_collisions");
+        group
+            .sample_size(sample_size)
+            .measurement_time(measurement_time);
+        for pool in pools {
+            for bin_size in bin_sizes.iter() {
+                let mut grid = SpatialGrid::with_bin_size(*bin_size);
+                grid.update(&layers);
+                let config = config(
+                    "detect_collisions",
+                    Some(pool.current_num_threads()),
+                    Some(*bin_size),
+                );
+                group.bench_function(config.id(), |b| {
+                    measure(b, config, Some(pool), || {
+                        black_box(collision_system.detect(&grid, &layers));
+                    })
+                });
+            }
+        }
+        group.finish();
+
+        // The narrow phase alone, on every candidate pair (as polygons, as circles are approximated anyway)
+        let mut grid = SpatialGrid::new();
+        grid.update(&layers);
+        let polygon = |kind: &EntityKind, id: &EntityId| layers[kind][id].to_polygon();
+        let pairs: Vec<(Vec<Vertex>, Vec<Vertex>)> = [
+            (EntityKind::Baddie, EntityKind::Wall),
+            (EntityKind::Baddie, EntityKind::Cannon),
+            (EntityKind::Baddie, EntityKind::Baddie),
+            (EntityKind::Wall, EntityKind::Wall),
+        ]
+        .iter()
+        .flat_map(|(left, right)| {
+            grid.candidate_pairs(*left, *right, false)
+                .into_iter()
+                .map(|(left_id, right_id)| (polygon(left, &left_id), polygon(right, &right_id)))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+        let mut group = criterion.benchmark_group("is_collision");
+        group
+            .sample_size(sample_size)
+            .measurement_time(measurement_time);
+        let config = config("is_collision", None, None);
+        group.bench_function(format!("{}/{} pairs", config.id(), pairs.len()), |b| {
+            measure(b, config, None, || {
+                for (left, right) in pairs.iter() {
+                    black_box(is_collision(left, right));
+                }
+            })
+        });
+        group.finish();
+    }
+
+    // The whole tick, carrying on from the same world each iteration (like the game)
+    let mut group = criterion.benchmark_group("update_world");
+    group
+        .sample_size(sample_size)
+        .measurement_time(measurement_time);
+    for pool in pools {
+        let config = config("update_world", Some(pool.current_num_threads()), None);
+        group.bench_function(config.id(), |b| {
+            measure(b, config, Some(pool), || {
+                black_box(update_world(&mut world, DT));
+            })
+        });
+    }
+    group.finish();
+}
+
+fn print_table() {
+    let results = RESULTS.lock().unwrap();
+    println!();
+    println!(
+        "Means per iteration, over warm-up and measurement. Ticks of {} ms.",
+        DT
+    );
+    println!(
+        "{:>6} | {:>8} | {:>17} | {:>7} | {:>8} | {:>14}",
+        "level", "objects", "benchmark", "threads", "bin size", "mean (us)"
+    );
+    for row in results.iter() {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+        println!(
+            "{:>6} | {:>8} | {:>17} | {:>7} | {:>8} | {:>14.1}",
+            row.level,
+            row.objects,
+            row.benchmark,
+            optional(row.threads.map(|threads| threads.to_string())),
+            optional(row.bin_size.map(|bin_size| bin_size.to_string())),
+            row.total.as_secs_f64() * 1e6 / row.iterations as f64
+        );
+    }
+}
+
+fn main() {
+    let mut criterion = Criterion::default().without_plots().configure_from_args();
+    let levels = LevelSet::builtin();
+    let pools = thread_pools();
+    for level in LEVELS.iter() {
+        bench_level(&mut criterion, &levels, *level, &pools);
+    }
+    criterion.final_summary();
+    print_table();
+}
@@ -0,0 +1,1504 @@
+//! Broad phase collision detection - cheaply finds the pairs of objects that might be colliding,
+//! so that only those need testing properly (the narrow phase, see [`crate::collision_system`]).
+//!
+//! There are several implementations of [`BroadPhase`], selectable per level (see [`BroadPhaseKind`]):
+//! * [`SpatialGrid`] - a spatial hash (uniform grid of bins). Best when objects are of similar sizes.
+//! * [`SweepAndPrune`] - bounding boxes kept sorted along the x axis, and swept for overlaps.
+//!   Cheap to keep up to date, as objects only move a little each step.
+//! * [`AabbTree`] - a dynamic bounding volume hierarchy. Copes with objects of very different sizes.
+//! * [`BruteForce`] - every pair. The reference the others are tested against.
+//!
+//! Each lives across steps (in the [`crate::World`]), and is updated incrementally.
+
+use crate::entity::{EntityId, EntityKind};
+use crate::geometry::{Aabb, Geometry, Vector, Vertex};
+use crate::helpers::flat_map_collect;
+use crate::world::{GeomLayers, GRID_HEIGHT, GRID_WIDTH};
+use itertools::Itertools;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Pairs of entities that might be colliding
+pub type CandidatePairs = HashSet<(EntityId, EntityId)>;
+
+/// Finds the pairs of objects that might be colliding
+pub trait BroadPhase: Send + Sync {
+    /// Brings the structure up to date with the given geometries, by kind (layer).
+    /// Objects no longer present are removed.
+    fn update(&mut self, layers: &GeomLayers);
+
+    /// Pairs of objects from layers `left` and `right` that might be colliding, as (left ID, right ID).
+    /// Includes at least every pair whose bounding boxes overlap, as of the last update.
+    /// Within a single layer, each pair is only given once, as (lower ID, higher ID).
+    /// The work is spread across rayon's threads if `parallel` (worthwhile when there are many objects).
+    fn candidate_pairs(
+        &self,
+        left: EntityKind,
+        right: EntityKind,
+        parallel: bool,
+    ) -> CandidatePairs;
+
+    /// Objects from layer `kind` that might overlap the given box, each given once.
+    /// Includes at least every object whose bounding box overlaps it, as of the last update.
+    fn query(&self, kind: EntityKind, aabb: &Aabb) -> Vec<EntityId>;
+
+    /// Number of objects whose entries had to change in the last update
+    fn updated(&self) -> usize;
+
+    /// Occupancy of the bins, for broad phases that are a grid - e.g. for a debug view
+    fn grid_occupancy(&self) -> Option<GridOccupancy> {
+        None
+    }
+}
+
+/// Occupancy of a grid's bins, as of the last update
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GridOccupancy {
+    /// Size of the (square) bins
+    pub bin_size: i32,
+    /// Number of objects in each occupied bin, over all layers, by (column, row)
+    pub counts: BTreeMap<(i32, i32), usize>,
+}
+
+/// The broad phase implementations
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BroadPhaseKind {
+    /// [`SpatialGrid`]
+    #[default]
+    Grid,
+    /// [`SweepAndPrune`]
+    SweepAndPrune,
+    /// [`AabbTree`]
+    AabbTree,
+    /// [`BruteForce`]
+    BruteForce,
+}
+
+impl BroadPhaseKind {
+    /// All the implementations
+    pub const ALL: [BroadPhaseKind; 4] = [
+        BroadPhaseKind::Grid,
+        BroadPhaseKind::SweepAndPrune,
+        BroadPhaseKind::AabbTree,
+        BroadPhaseKind::BruteForce,
+    ];
+
+    /// Creates an empty broad phase of this kind
+    pub fn create(self) -> Box<dyn BroadPhase> {
+        match self {
+            BroadPhaseKind::Grid => Box::new(SpatialGrid::new()),
+            BroadPhaseKind::SweepAndPrune => Box::new(SweepAndPrune::new()),
+            BroadPhaseKind::AabbTree => Box::new(AabbTree::new()),
+            BroadPhaseKind::BruteForce => Box::new(BruteForce::new()),
+        }
+    }
+}
+
+/// Puts a pair found in layers `left` and `right` the right way round.
+/// Within a single layer, that's (lower ID, higher ID) - and an object doesn't pair with itself.
+fn ordered_pair(same_layer: bool, left: EntityId, right: EntityId) -> Option<(EntityId, EntityId)> {
+    if !same_layer || left < right {
+        Some((left, right))
+    } else if right < left {
+        Some((right, left))
+    } else {
+        None
+    }
+}
+
+// Spatial grid
+
+/// Bin -> objects in it, with their bounding boxes
+type SpatialMap = HashMap<i32, HashMap<EntityId, Aabb>>;
+/// Object -> the bins it occupies, and its bounding box
+type SpatialIndex = HashMap<EntityId, (Cells, Aabb)>;
+
+/// Size of the game's world, which grids cover unless given another (see [`SpatialGrid::with_world_size`])
+const DEFAULT_WORLD_SIZE: Vector = (GRID_WIDTH as i32, GRID_HEIGHT as i32);
+
+/// Number of grid columns and rows over a world of the given size.
+/// Coordinates on the far edges are within the world, so need an extra one of each.
+fn grid_dims(grid_bin_size: i32, world_size: Vector) -> (i32, i32) {
+    (
+        world_size.0 / grid_bin_size + 1,
+        world_size.1 / grid_bin_size + 1,
+    )
+}
+
+/// Grid cell (column, row) containing the vertex.
+/// Clamped to the world, so that objects hanging over the edge share the edge cells with their neighbours.
+fn calc_cell(vertex: &Vertex, grid_bin_size: i32, world_size: Vector) -> (i32, i32) {
+    let (columns, rows) = grid_dims(grid_bin_size, world_size);
+    let (vx, vy) = vertex;
+    (
+        (vx / grid_bin_size).max(0).min(columns - 1),
+        (vy / grid_bin_size).max(0).min(rows - 1),
+    )
+}
+
+/// Block of grid cells occupied by an object (inclusive)
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Cells {
+    min: (i32, i32),
+    max: (i32, i32),
+    columns: i32,
+}
+
+impl Cells {
+    /// Indices of the bins in the block
+    fn bins(self) -> impl Iterator<Item = i32> {
+        let Cells { min, max, columns } = self;
+        (min.1..=max.1)
+            .flat_map(move |row| (min.0..=max.0).map(move |column| column + row * columns))
+    }
+}
+
+/// Spatial hash. Calculates the cells of a regular grid that the given bounding box occupies,
+/// so objects of any size (relative to the bins) are covered.
+fn calc_cells(aabb: &Aabb, grid_bin_size: i32, world_size: Vector) -> Cells {
+    Cells {
+        min: calc_cell(&aabb.min, grid_bin_size, world_size),
+        max: calc_cell(&aabb.max, grid_bin_size, world_size),
+        columns: grid_dims(grid_bin_size, world_size).0,
+    }
+}
+
+/// Spatial hash of one layer: map of bin -> objects, and the associated index of object -> bins
+#[derive(Default)]
+struct SpatialHash {
+    map: SpatialMap,
+    index: SpatialIndex,
+}
+
+impl SpatialHash {
+    /// Bins the object, or re-bins it if it has moved into different bins.
+    /// Returns false if it was already in the right bins (its bounding box is still brought up to date).
+    fn update(
+        &mut self,
+        id: EntityId,
+        geometry: &Geometry,
+        grid_bin_size: i32,
+        world_size: Vector,
+    ) -> bool {
+        let aabb = geometry.aabb();
+        let cells = calc_cells(&aabb, grid_bin_size, world_size);
+        match self.index.get_mut(&id) {
+            Some((old_cells, old_aabb)) if *old_cells == cells => {
+                if *old_aabb != aabb {
+                    *old_aabb = aabb;
+                    for bin in cells.bins() {
+                        self.map.get_mut(&bin).unwrap().insert(id, aabb);
+                    }
+                }
+                return false;
+            }
+            _ => self.remove(id),
+        }
+        for bin in cells.bins() {
+            self.map.entry(bin).or_default().insert(id, aabb);
+        }
+        self.index.insert(id, (cells, aabb));
+        true
+    }
+
+    /// Removes the object from all the bins it occupies
+    fn remove(&mut self, id: EntityId) {
+        if let Some((cells, _)) = self.index.remove(&id) {
+            for bin in cells.bins() {
+                let ids = self.map.get_mut(&bin).unwrap();
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.map.remove(&bin);
+                }
+            }
+        }
+    }
+}
+
+/// Most bins across the world, so that the grid doesn't get too fine (~100 x 100 bins, for a square world)
+const MAX_BINS_ACROSS: i32 = 100;
+/// Number of objects per bin to aim for, were they spread evenly
+const OBJECTS_PER_BIN: f32 = 4.0;
+
+/// Calculates grid bin size from the objects' sizes (diameters, i.e. the radius circumscribed by rotation) and density.
+/// Bins fit a typical object - the 90th percentile, so that a few very big objects (e.g. long walls) don't make every bin
+/// big, they just occupy more bins. Sparse objects get bigger bins, so that there are fewer to go through.
+/// Down to a minimum size (to avoid diminishing perf), and up to the world size.
+pub fn calc_bin_size(layers: &GeomLayers, world_size: Vector) -> i32 {
+    let world_span = world_size.0.max(world_size.1);
+    // Compares squares, and only computes a single sqrt at the end
+    let mut diameters_sqr: Vec<i32> = layers
+        .values()
+        .flat_map(|geoms| geoms.values())
+        .map(|geom| geom.diameter_sqr())
+        .collect();
+    if diameters_sqr.is_empty() {
+        return world_span;
+    }
+    let count = diameters_sqr.len();
+    let typical_sqr = *diameters_sqr.select_nth_unstable(count * 9 / 10).1;
+    let typical = (typical_sqr as f32).sqrt() as i32;
+    let sparse =
+        (world_size.0 as f32 * world_size.1 as f32 * OBJECTS_PER_BIN / count as f32).sqrt() as i32;
+    typical
+        .max(sparse)
+        .max(world_span / MAX_BINS_ACROSS)
+        .min(world_span)
+}
+
+/// Spatial hash of each layer. Only objects which have moved into different bins need updating.
+pub struct SpatialGrid {
+    layers: HashMap<EntityKind, SpatialHash>,
+    /// Bin size for spatial hashmap (square grid). 0 until the first update.
+    /// 10000 / 1000 => 10 * 10 grid
+    grid_bin_size: i32,
+    /// Number of objects (re)binned by the last update
+    rebinned: usize,
+    /// Bin size to always use, rather than calculating it from the objects
+    fixed_bin_size: Option<i32>,
+    /// Size of the world the grid covers (width, height). Objects beyond it share the edge bins.
+    world_size: Vector,
+}
+
+impl Default for SpatialGrid {
+    fn default() -> Self {
+        Self {
+            layers: HashMap::new(),
+            grid_bin_size: 0,
+            rebinned: 0,
+            fixed_bin_size: None,
+            world_size: DEFAULT_WORLD_SIZE,
+        }
+    }
+}
+
+impl SpatialGrid {
+    /// Creates an empty grid
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty grid which always uses the given bin size, e.g. for comparing sizes.
+    /// Bins smaller than the objects still work, but each object occupies more of them.
+    pub fn with_bin_size(bin_size: i32) -> Self {
+        assert!(bin_size > 0, "bin size must be positive");
+        Self {
+            fixed_bin_size: Some(bin_size),
+            ..Self::default()
+        }
+    }
+
+    /// The (new) grid, covering a world of the given size (width, height) rather than the game's, e.g. a non-square one
+    pub fn with_world_size(self, world_size: Vector) -> Self {
+        assert!(
+            world_size.0 > 0 && world_size.1 > 0,
+            "world size must be positive"
+        );
+        Self { world_size, ..self }
+    }
+
+    /// Current bin size
+    pub fn bin_size(&self) -> i32 {
+        self.grid_bin_size
+    }
+}
+
+impl BroadPhase for SpatialGrid {
+    /// Brings the spatial hash up to date. Everything is rebuilt if the bin size has to change.
+    fn update(&mut self, layers: &GeomLayers) {
+        let world_size = self.world_size;
+        let grid_bin_size = self
+            .fixed_bin_size
+            .unwrap_or_else(|| calc_bin_size(layers, world_size));
+        // Don't bother resizing the bins (rebinning everything) for small changes
+        if grid_bin_size > self.grid_bin_size * 2 || grid_bin_size * 2 < self.grid_bin_size {
+            self.layers.clear();
+            self.grid_bin_size = grid_bin_size;
+        }
+
+        self.rebinned = 0;
+        self.layers.retain(|kind, _| layers.contains_key(kind));
+        for (kind, geoms) in layers {
+            let hash = self.layers.entry(*kind).or_default();
+            for (id, geometry) in geoms {
+                if hash.update(*id, geometry, self.grid_bin_size, world_size) {
+                    self.rebinned += 1;
+                }
+            }
+            // Everything present is now indexed, so any extra objects have been removed
+            if hash.index.len() > geoms.len() {
+                let removed: Vec<EntityId> = hash
+                    .index
+                    .keys()
+                    .filter(|id| !geoms.contains_key(id))
+                    .cloned()
+                    .collect();
+                for id in removed {
+                    hash.remove(id);
+                }
+            }
+        }
+    }
+
+    /// Pairs of objects sharing a bin, whose bounding boxes overlap. Only bins occupied by both layers are visited.
+    /// Objects may share several bins, so each pair is only taken from the first bin both of them occupy
+    /// (the one containing the overlap's minimum corner).
+    fn candidate_pairs(
+        &self,
+        left: EntityKind,
+        right: EntityKind,
+        parallel: bool,
+    ) -> CandidatePairs {
+        let (left_map, right_map) = match (self.layers.get(&left), self.layers.get(&right)) {
+            (Some(left), Some(right)) => (&left.map, &right.map),
+            _ => return CandidatePairs::new(),
+        };
+        // Looked up from whichever layer occupies fewer bins
+        let shared_bins: Vec<_> = if left_map.len() <= right_map.len() {
+            left_map
+                .iter()
+                .filter_map(|(bin, left_objects)| Some((*bin, left_objects, right_map.get(bin)?)))
+                .collect()
+        } else {
+            right_map
+                .iter()
+                .filter_map(|(bin, right_objects)| Some((*bin, left_map.get(bin)?, right_objects)))
+                .collect()
+        };
+        let same_layer = left == right;
+        let (grid_bin_size, world_size) = (self.grid_bin_size, self.world_size);
+        let columns = grid_dims(grid_bin_size, world_size).0;
+        flat_map_collect(
+            shared_bins,
+            parallel,
+            |(bin, left_objects, right_objects)| {
+                left_objects
+                    .iter()
+                    .cartesian_product(right_objects)
+                    .filter_map(move |((left_id, left_aabb), (right_id, right_aabb))| {
+                        if !left_aabb.overlaps(right_aabb) {
+                            return None;
+                        }
+                        let overlap_min = (
+                            left_aabb.min.0.max(right_aabb.min.0),
+                            left_aabb.min.1.max(right_aabb.min.1),
+                        );
+                        let (column, row) = calc_cell(&overlap_min, grid_bin_size, world_size);
+                        if column + row * columns != bin {
+                            return None;
+                        }
+                        ordered_pair(same_layer, *left_id, *right_id)
+                    })
+            },
+        )
+    }
+
+    /// Objects in the box's bins whose bounding boxes overlap it
+    fn query(&self, kind: EntityKind, aabb: &Aabb) -> Vec<EntityId> {
+        let map = match self.layers.get(&kind) {
+            Some(hash) => &hash.map,
+            None => return vec![],
+        };
+        calc_cells(aabb, self.grid_bin_size, self.world_size)
+            .bins()
+            .filter_map(|bin| map.get(&bin))
+            .flat_map(|objects| objects.iter())
+            .filter(|(_, object_aabb)| object_aabb.overlaps(aabb))
+            .map(|(id, _)| *id)
+            .unique()
+            .collect()
+    }
+
+    fn updated(&self) -> usize {
+        self.rebinned
+    }
+
+    fn grid_occupancy(&self) -> Option<GridOccupancy> {
+        let columns = grid_dims(self.grid_bin_size, self.world_size).0;
+        let mut counts = BTreeMap::new();
+        for (bin, objects) in self.layers.values().flat_map(|hash| hash.map.iter()) {
+            *counts.entry((bin % columns, bin / columns)).or_default() += objects.len();
+        }
+        Some(GridOccupancy {
+            bin_size: self.grid_bin_size,
+            counts,
+        })
+    }
+}
+
+// Sweep and prune
+
+/// Bounding boxes of a layer's objects, sorted by left edge
+type SortedBoxes = Vec<(EntityId, Aabb)>;
+
+/// Pairs of overlapping boxes from `a` and `b`, where the box from `b` starts within the x range of the box from `a`.
+/// (Overlapping pairs where the box from `a` starts within the box from `b` are found by swapping them around.)
+fn sweep(
+    a: &[(EntityId, Aabb)],
+    b: &[(EntityId, Aabb)],
+    parallel: bool,
+) -> Vec<(EntityId, EntityId)> {
+    flat_map_collect(a, parallel, |(a_id, a_box)| {
+        let start = b.partition_point(|(_, b_box)| b_box.min.0 < a_box.min.0);
+        b[start..]
+            .iter()
+            .take_while(move |(_, b_box)| b_box.min.0 <= a_box.max.0)
+            .filter(move |(_, b_box)| a_box.overlaps(b_box))
+            .map(move |(b_id, _)| (*a_id, *b_id))
+    })
+}
+
+/// Sort and sweep - each layer's bounding boxes, sorted along the x axis.
+/// Overlaps are found by sweeping along the sorted boxes, only comparing those whose x ranges overlap.
+#[derive(Default)]
+pub struct SweepAndPrune {
+    layers: HashMap<EntityKind, SortedBoxes>,
+    /// Width of each layer's widest box, which bounds how far back a box overlapping a given one can start
+    widest: HashMap<EntityKind, i32>,
+    /// Number of boxes that changed in the last update
+    updated: usize,
+}
+
+impl SweepAndPrune {
+    /// Creates an empty sweep and prune
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl BroadPhase for SweepAndPrune {
+    fn update(&mut self, layers: &GeomLayers) {
+        self.updated = 0;
+        self.layers.retain(|kind, _| layers.contains_key(kind));
+        self.widest.retain(|kind, _| layers.contains_key(kind));
+        for (kind, geoms) in layers {
+            let boxes = self.layers.entry(*kind).or_default();
+            let mut present = HashSet::with_capacity(boxes.len());
+            let updated = &mut self.updated;
+            boxes.retain_mut(|(id, aabb)| match geoms.get(id) {
+                Some(geometry) => {
+                    let new_aabb = geometry.aabb();
+                    if new_aabb != *aabb {
+                        *aabb = new_aabb;
+                        *updated += 1;
+                    }
+                    present.insert(*id);
+                    true
+                }
+                None => false,
+            });
+            for (id, geometry) in geoms {
+                if !present.contains(id) {
+                    boxes.push((*id, geometry.aabb()));
+                    self.updated += 1;
+                }
+            }
+            // Objects only move a little each step, so the boxes are nearly sorted already - which the standard
+            // library's (stable) sort takes advantage of
+            boxes.sort_by_key(|(_, aabb)| aabb.min.0);
+            let widest = boxes.iter().map(|(_, aabb)| aabb.max.0 - aabb.min.0).max();
+            self.widest.insert(*kind, widest.unwrap_or(0));
+        }
+    }
+
+    fn candidate_pairs(
+        &self,
+        left: EntityKind,
+        right: EntityKind,
+        parallel: bool,
+    ) -> CandidatePairs {
+        let (left_boxes, right_boxes) = match (self.layers.get(&left), self.layers.get(&right)) {
+            (Some(left), Some(right)) => (left, right),
+            _ => return CandidatePairs::new(),
+        };
+        if left == right {
+            sweep(left_boxes, left_boxes, parallel)
+                .into_iter()
+                .filter_map(|(a, b)| ordered_pair(true, a, b))
+                .collect()
+        } else {
+            sweep(left_boxes, right_boxes, parallel)
+                .into_iter()
+                .chain(
+                    sweep(right_boxes, left_boxes, parallel)
+                        .into_iter()
+                        .map(|(right_id, left_id)| (left_id, right_id)),
+                )
+                .collect()
+        }
+    }
+
+    fn query(&self, kind: EntityKind, aabb: &Aabb) -> Vec<EntityId> {
+        let boxes = match self.layers.get(&kind) {
+            Some(boxes) => boxes,
+            None => return vec![],
+        };
+        let earliest = aabb.min.0 - self.widest.get(&kind).unwrap();
+        let start = boxes.partition_point(|(_, other)| other.min.0 < earliest);
+        boxes[start..]
+            .iter()
+            .take_while(|(_, other)| other.min.0 <= aabb.max.0)
+            .filter(|(_, other)| other.overlaps(aabb))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    fn updated(&self) -> usize {
+        self.updated
+    }
+}
+
+// Dynamic AABB tree
+
+/// Boxes in the tree are fattened by this fraction of their size,
+/// so that objects can move a little before needing to be reinserted
+const FAT_MARGIN_DIVISOR: i32 = 4;
+
+fn fatten(aabb: &Aabb) -> Aabb {
+    let size = (aabb.max.0 - aabb.min.0).max(aabb.max.1 - aabb.min.1);
+    aabb.grow(size / FAT_MARGIN_DIVISOR + 1)
+}
+
+#[derive(Clone, Copy)]
+enum Content {
+    Leaf(EntityId),
+    Branch(usize, usize),
+}
+
+struct Node {
+    /// Bounds of everything below this node (for a leaf, the fattened bounds of its object)
+    aabb: Aabb,
+    parent: Option<usize>,
+    /// Leaves are 0, branches are 1 more than their tallest child
+    height: u32,
+    content: Content,
+}
+
+/// Bounding volume hierarchy of one layer. Branches are balanced with rotations, as in Box2D's `b2DynamicTree`.
+#[derive(Default)]
+struct Tree {
+    /// Node storage - nodes refer to each other by index
+    nodes: Vec<Node>,
+    /// Indices of unused nodes
+    free: Vec<usize>,
+    root: Option<usize>,
+    /// Leaf node of each object
+    leaves: HashMap<EntityId, usize>,
+}
+
+impl Tree {
+    fn allocate(&mut self, node: Node) -> usize {
+        match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn children(&self, index: usize) -> (usize, usize) {
+        match self.nodes[index].content {
+            Content::Branch(child1, child2) => (child1, child2),
+            Content::Leaf(_) => panic!("Leaf node has no children"),
+        }
+    }
+
+    fn replace_child(&mut self, parent: usize, old: usize, new: usize) {
+        let (child1, child2) = self.children(parent);
+        self.nodes[parent].content = if child1 == old {
+            Content::Branch(new, child2)
+        } else {
+            Content::Branch(child1, new)
+        };
+    }
+
+    /// Recalculates a branch's bounds and height from its children
+    fn fit(&mut self, index: usize) {
+        let (child1, child2) = self.children(index);
+        let (node1, node2) = (&self.nodes[child1], &self.nodes[child2]);
+        let aabb = node1.aabb.union(&node2.aabb);
+        let height = 1 + node1.height.max(node2.height);
+        let node = &mut self.nodes[index];
+        node.aabb = aabb;
+        node.height = height;
+    }
+
+    /// Adds or moves the object. Returns false if it's still within its fattened box, so nothing was changed.
+    fn update(&mut self, id: EntityId, aabb: &Aabb) -> bool {
+        if let Some(leaf) = self.leaves.get(&id) {
+            if self.nodes[*leaf].aabb.contains(aabb) {
+                return false;
+            }
+            self.remove(id);
+        }
+        let leaf = self.allocate(Node {
+            aabb: fatten(aabb),
+            parent: None,
+            height: 0,
+            content: Content::Leaf(id),
+        });
+        self.insert_leaf(leaf);
+        self.leaves.insert(id, leaf);
+        true
+    }
+
+    fn remove(&mut self, id: EntityId) {
+        if let Some(leaf) = self.leaves.remove(&id) {
+            self.remove_leaf(leaf);
+        }
+    }
+
+    /// Inserts the leaf next to the node it would enlarge the least (by perimeter), as in Box2D
+    fn insert_leaf(&mut self, leaf: usize) {
+        let root = match self.root {
+            Some(root) => root,
+            None => {
+                self.root = Some(leaf);
+                return;
+            }
+        };
+        let aabb = self.nodes[leaf].aabb;
+        let mut sibling = root;
+        while let Content::Branch(child1, child2) = self.nodes[sibling].content {
+            let node_aabb = self.nodes[sibling].aabb;
+            let combined = node_aabb.union(&aabb).perimeter();
+            // Cost of pairing the leaf with this node
+            let cost = 2 * combined;
+            // Cost of enlarging this node, which pushing the leaf further down would also incur
+            let inheritance = 2 * (combined - node_aabb.perimeter());
+            let child_cost = |child: usize| {
+                let child = &self.nodes[child];
+                let enlarged = child.aabb.union(&aabb).perimeter();
+                match child.content {
+                    Content::Leaf(_) => enlarged + inheritance,
+                    Content::Branch(..) => enlarged - child.aabb.perimeter() + inheritance,
+                }
+            };
+            let (cost1, cost2) = (child_cost(child1), child_cost(child2));
+            if cost < cost1 && cost < cost2 {
+                break;
+            }
+            sibling = if cost1 < cost2 { child1 } else { child2 };
+        }
+
+        let old_parent = self.nodes[sibling].parent;
+        let new_parent = self.allocate(Node {
+            aabb,
+            parent: old_parent,
+            height: 0,
+            content: Content::Branch(sibling, leaf),
+        });
+        self.nodes[sibling].parent = Some(new_parent);
+        self.nodes[leaf].parent = Some(new_parent);
+        match old_parent {
+            Some(old_parent) => self.replace_child(old_parent, sibling, new_parent),
+            None => self.root = Some(new_parent),
+        }
+        self.refit(Some(new_parent));
+    }
+
+    /// Removes the leaf, replacing its parent with its sibling
+    fn remove_leaf(&mut self, leaf: usize) {
+        self.free.push(leaf);
+        let parent = match self.nodes[leaf].parent {
+            Some(parent) => parent,
+            None => {
+                self.root = None;
+                return;
+            }
+        };
+        let (child1, child2) = self.children(parent);
+        let sibling = if child1 == leaf { child2 } else { child1 };
+        let grandparent = self.nodes[parent].parent;
+        self.nodes[sibling].parent = grandparent;
+        self.free.push(parent);
+        match grandparent {
+            Some(grandparent) => {
+                self.replace_child(grandparent, parent, sibling);
+                self.refit(Some(grandparent));
+            }
+            None => self.root = Some(sibling),
+        }
+    }
+
+    /// Refits (and rebalances) the branches from the given one up to the root
+    fn refit(&mut self, mut index: Option<usize>) {
+        while let Some(branch) = index {
+            let branch = self.balance(branch);
+            self.fit(branch);
+            index = self.nodes[branch].parent;
+        }
+    }
+
+    /// If one of the branch's children is more than 1 taller than the other, rotates it up into the branch's place.
+    /// Returns the node now in the branch's place.
+    fn balance(&mut self, branch: usize) -> usize {
+        let (child1, child2) = self.children(branch);
+        let imbalance = self.nodes[child2].height as i32 - self.nodes[child1].height as i32;
+        if imbalance > 1 {
+            self.rotate_up(branch, child1, child2)
+        } else if imbalance < -1 {
+            self.rotate_up(branch, child2, child1)
+        } else {
+            branch
+        }
+    }
+
+    /// Rotates `tall`, a child of `branch`, up into its place. `branch` keeps its other child, `short`,
+    /// and takes the shorter of `tall`'s children. Returns `tall`.
+    fn rotate_up(&mut self, branch: usize, short: usize, tall: usize) -> usize {
+        let (grandchild1, grandchild2) = self.children(tall);
+        let (kept, moved) = if self.nodes[grandchild1].height > self.nodes[grandchild2].height {
+            (grandchild1, grandchild2)
+        } else {
+            (grandchild2, grandchild1)
+        };
+        let parent = self.nodes[branch].parent;
+        self.nodes[tall].parent = parent;
+        match parent {
+            Some(parent) => self.replace_child(parent, branch, tall),
+            None => self.root = Some(tall),
+        }
+        self.nodes[branch].parent = Some(tall);
+        self.nodes[tall].content = Content::Branch(branch, kept);
+        self.nodes[branch].content = Content::Branch(short, moved);
+        self.nodes[moved].parent = Some(branch);
+        self.fit(branch);
+        self.fit(tall);
+        tall
+    }
+
+    /// Calls `found` with each object whose (fattened) box overlaps the given one
+    fn query(&self, aabb: &Aabb, mut found: impl FnMut(EntityId)) {
+        let mut stack: Vec<usize> = self.root.into_iter().collect();
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.aabb.overlaps(aabb) {
+                match node.content {
+                    Content::Leaf(id) => found(id),
+                    Content::Branch(child1, child2) => {
+                        stack.push(child1);
+                        stack.push(child2);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Dynamic AABB tree of each layer. Objects are only reinserted when they move out of their fattened boxes.
+#[derive(Default)]
+pub struct AabbTree {
+    layers: HashMap<EntityKind, Tree>,
+    /// Number of objects (re)inserted by the last update
+    reinserted: usize,
+}
+
+impl AabbTree {
+    /// Creates an empty tree
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl BroadPhase for AabbTree {
+    fn update(&mut self, layers: &GeomLayers) {
+        self.reinserted = 0;
+        self.layers.retain(|kind, _| layers.contains_key(kind));
+        for (kind, geoms) in layers {
+            let tree = self.layers.entry(*kind).or_default();
+            for (id, geometry) in geoms {
+                if tree.update(*id, &geometry.aabb()) {
+                    self.reinserted += 1;
+                }
+            }
+            if tree.leaves.len() > geoms.len() {
+                let removed: Vec<EntityId> = tree
+                    .leaves
+                    .keys()
+                    .filter(|id| !geoms.contains_key(id))
+                    .cloned()
+                    .collect();
+                for id in removed {
+                    tree.remove(id);
+                }
+            }
+        }
+    }
+
+    /// Pairs of objects whose fattened boxes overlap
+    fn candidate_pairs(
+        &self,
+        left: EntityKind,
+        right: EntityKind,
+        parallel: bool,
+    ) -> CandidatePairs {
+        let (left_tree, right_tree) = match (self.layers.get(&left), self.layers.get(&right)) {
+            (Some(left), Some(right)) => (left, right),
+            _ => return CandidatePairs::new(),
+        };
+        let same_layer = left == right;
+        flat_map_collect(&left_tree.leaves, parallel, |(left_id, leaf)| {
+            let mut pairs = vec![];
+            right_tree.query(&left_tree.nodes[*leaf].aabb, |right_id| {
+                pairs.extend(ordered_pair(same_layer, *left_id, right_id))
+            });
+            pairs
+        })
+    }
+
+    /// Objects whose fattened boxes overlap the box
+    fn query(&self, kind: EntityKind, aabb: &Aabb) -> Vec<EntityId> {
+        let mut found = vec![];
+        if let Some(tree) = self.layers.get(&kind) {
+            tree.query(aabb, |id| found.push(id));
+        }
+        found
+    }
+
+    fn updated(&self) -> usize {
+        self.reinserted
+    }
+}
+
+// Brute force
+
+/// Every pair of objects is a candidate. Only practical for small worlds - it's the reference for testing the others.
+#[derive(Default)]
+pub struct BruteForce {
+    layers: HashMap<EntityKind, Vec<EntityId>>,
+}
+
+impl BruteForce {
+    /// Creates an empty brute force broad phase
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl BroadPhase for BruteForce {
+    fn update(&mut self, layers: &GeomLayers) {
+        self.layers = layers
+            .iter()
+            .map(|(kind, geoms)| (*kind, geoms.keys().cloned().collect()))
+            .collect();
+    }
+
+    /// Always on the calling thread
+    fn candidate_pairs(
+        &self,
+        left: EntityKind,
+        right: EntityKind,
+        _parallel: bool,
+    ) -> CandidatePairs {
+        let (left_ids, right_ids) = match (self.layers.get(&left), self.layers.get(&right)) {
+            (Some(left), Some(right)) => (left, right),
+            _ => return CandidatePairs::new(),
+        };
+        left_ids
+            .iter()
+            .cartesian_product(right_ids)
+            .filter_map(|(left_id, right_id)| ordered_pair(left == right, *left_id, *right_id))
+            .collect()
+    }
+
+    /// Everything in the layer
+    fn query(&self, kind: EntityKind, _aabb: &Aabb) -> Vec<EntityId> {
+        self.layers.get(&kind).cloned().unwrap_or_default()
+    }
+
+    /// Everything, every update
+    fn updated(&self) -> usize {
+        self.layers.values().map(|ids| ids.len()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::EntityAllocator;
+    use crate::fixed::{Real, Scalar};
+    use crate::geometry::rotate;
+    use crate::rng::Pcg32;
+    use crate::world::{GeomRefMap, ObjectFactory};
+    use std::iter::FromIterator;
+
+    fn grid_hash(vertices: &[Vertex], grid_bin_size: i32) -> Cells {
+        calc_cells(&Aabb::of(vertices), grid_bin_size, DEFAULT_WORLD_SIZE)
+    }
+
+    type Bins = HashSet<i32>;
+
+    #[test]
+    fn grid_hash_single() {
+        let vertex = (1000, 2000);
+        let expected = Bins::from_iter([23].iter().cloned());
+        let actual: Bins = grid_hash(&[vertex, vertex, vertex, vertex, vertex], 1000)
+            .bins()
+            .collect();
+
+        assert_eq!(expected, actual);
+    }
+
+    /// Tilted box spanning multiple bins
+    #[test]
+    fn grid_hash_box() {
+        let vertices = [
+            (1000, 2000), // bin 23
+            (1770, 2200), // bin 23
+            (1550, 2900), // bin 23
+            (780, 2770),  // bin 22
+            (1000, 2000), // bin 23
+        ];
+        let expected = Bins::from_iter([22, 23].iter().cloned());
+        let actual: Bins = grid_hash(&vertices, 1000).bins().collect();
+
+        assert_eq!(expected, actual);
+    }
+
+    /// Box much bigger than a bin - covers the bins inside it, not just those of its vertices
+    #[test]
+    fn grid_hash_big_box() {
+        let vertices = [
+            (500, 500),
+            (3500, 500),
+            (3500, 2500),
+            (500, 2500),
+            (500, 500),
+        ];
+        let expected =
+            Bins::from_iter([0, 1, 2, 3, 11, 12, 13, 14, 22, 23, 24, 25].iter().cloned());
+        let actual: Bins = grid_hash(&vertices, 1000).bins().collect();
+
+        assert_eq!(expected, actual);
+    }
+
+    /// Box hanging over the edges of the world - clamped to the edge bins
+    #[test]
+    fn grid_hash_clamped_to_world() {
+        let far = GRID_WIDTH as i32 + 500;
+        let vertices = [
+            (-500, -500),
+            (far, -500),
+            (far, 500),
+            (-500, 500),
+            (-500, -500),
+        ];
+        let expected = Bins::from_iter(0..11);
+        let actual: Bins = grid_hash(&vertices, 1000).bins().collect();
+
+        assert_eq!(expected, actual);
+    }
+
+    /// 2 walls with some occupying some bins in common - build map and index
+    #[test]
+    fn spatial_hash_2walls_some_common_bins() {
+        // Arrange - 2 walls in bin 12 (grid is 11 bins wide)
+        let obj_factory = ObjectFactory::new(1000);
+        let (wall1, _, wall1_geom, _) = obj_factory.make_wall((1200, 1200));
+        let w1_bins_expected = Bins::from_iter([0, 1, 11, 12].iter().cloned());
+        let (wall2, _, wall2_geom, _) = obj_factory.make_wall((1700, 1700));
+        let w2_bins_expected = Bins::from_iter([12, 13, 23, 24].iter().cloned());
+        let walls_geoms: GeomRefMap =
+            [(wall1.get_id(), &wall1_geom), (wall2.get_id(), &wall2_geom)]
+                .iter()
+                .cloned()
+                .collect();
+        let expected = HashSet::from_iter([wall1.get_id(), wall2.get_id()].iter().cloned());
+        // Act
+        let mut hash = SpatialHash::default();
+        for (id, geom) in walls_geoms.iter() {
+            hash.update(*id, geom, 1000, DEFAULT_WORLD_SIZE);
+        }
+
+        // Assert - map
+        let ids: HashSet<EntityId> = hash.map.get(&12).unwrap().keys().cloned().collect();
+        assert_eq!(ids, expected);
+
+        // Assert - index
+        let bins = |id| hash.index.get(&id).unwrap().0.bins().collect::<Bins>();
+        assert_eq!(bins(wall1.get_id()), w1_bins_expected);
+        assert_eq!(bins(wall2.get_id()), w2_bins_expected);
+    }
+
+    fn wall_and_baddie_layers<'a>(
+        walls: &[(EntityId, &'a Geometry)],
+        baddies: &[(EntityId, &'a Geometry)],
+    ) -> GeomLayers<'a> {
+        [
+            (EntityKind::Wall, walls.iter().cloned().collect()),
+            (EntityKind::Baddie, baddies.iter().cloned().collect()),
+        ]
+        .iter()
+        .cloned()
+        .collect()
+    }
+
+    #[test]
+    fn grid_updates_incrementally() {
+        // Arrange - a static wall, a baddie that moves a short way, and one that moves into different bins.
+        // A fixed bin size, as so few objects would otherwise share one big bin.
+        let obj_factory = ObjectFactory::new(1000);
+        let (wall, _, wall_geom, _) = obj_factory.make_wall((1200, 1200));
+        let (baddie1, _, baddie1_geom, _) = obj_factory.make_baddie((5500, 5500), (0, 0), 0.0);
+        let (baddie2, _, baddie2_geom, _) = obj_factory.make_baddie((7500, 7500), (0, 0), 0.0);
+        let (_, _, baddie1_moved, _) = obj_factory.make_baddie((5510, 5500), (0, 0), 0.0);
+        let (_, _, baddie2_moved, _) = obj_factory.make_baddie((3500, 7500), (0, 0), 0.0);
+        let walls = [(wall.get_id(), &wall_geom)];
+        let mut broad_phase = SpatialGrid::with_bin_size(1000);
+        broad_phase.update(&wall_and_baddie_layers(
+            &walls,
+            &[
+                (baddie1.get_id(), &baddie1_geom),
+                (baddie2.get_id(), &baddie2_geom),
+            ],
+        ));
+        assert_eq!(broad_phase.updated(), 3);
+        let bin_size = broad_phase.bin_size();
+
+        // Act - move both baddies
+        broad_phase.update(&wall_and_baddie_layers(
+            &walls,
+            &[
+                (baddie1.get_id(), &baddie1_moved),
+                (baddie2.get_id(), &baddie2_moved),
+            ],
+        ));
+
+        // Assert - only the baddie that changed bins was rebinned
+        assert_eq!(broad_phase.bin_size(), bin_size);
+        assert_eq!(broad_phase.updated(), 1);
+        let baddies = broad_phase.layers.get(&EntityKind::Baddie).unwrap();
+        assert_eq!(
+            baddies
+                .index
+                .get(&baddie2.get_id())
+                .map(|(cells, _)| *cells),
+            Some(calc_cells(
+                &baddie2_moved.aabb(),
+                bin_size,
+                DEFAULT_WORLD_SIZE
+            ))
+        );
+        for bin in calc_cells(&baddie2_geom.aabb(), bin_size, DEFAULT_WORLD_SIZE).bins() {
+            assert!(!baddies.map.contains_key(&bin));
+        }
+
+        // Act - remove the second baddie
+        broad_phase.update(&wall_and_baddie_layers(
+            &walls,
+            &[(baddie1.get_id(), &baddie1_moved)],
+        ));
+
+        // Assert - it's gone from the map and index
+        assert_eq!(broad_phase.updated(), 0);
+        let baddies = broad_phase.layers.get(&EntityKind::Baddie).unwrap();
+        assert!(!baddies.index.contains_key(&baddie2.get_id()));
+        assert!(baddies
+            .map
+            .values()
+            .all(|objects| !objects.contains_key(&baddie2.get_id())));
+    }
+
+    /// Wall much longer than the bins, hit in the middle (away from its vertices)
+    #[test]
+    fn grid_long_wall_small_bins() {
+        // Arrange - a fixed bin size, as it would otherwise be sized to fit the wall
+        let obj_factory = ObjectFactory::new(1000);
+        let wall_id = obj_factory.make_wall((0, 0)).0.get_id();
+        let wall_geom = Geometry::Polygon(vec![
+            (1000, 4900),
+            (9000, 4900),
+            (9000, 5100),
+            (1000, 5100),
+            (1000, 4900),
+        ]);
+        let (baddie, _, baddie_geom, _) = obj_factory.make_baddie((5000, 5400), (0, 0), 0.0);
+        let layers =
+            wall_and_baddie_layers(&[(wall_id, &wall_geom)], &[(baddie.get_id(), &baddie_geom)]);
+        let mut broad_phase = SpatialGrid::with_bin_size(1000);
+        broad_phase.update(&layers);
+        assert_eq!(broad_phase.bin_size(), 1000);
+
+        // Act
+        let pairs = broad_phase.candidate_pairs(EntityKind::Baddie, EntityKind::Wall, false);
+
+        // Assert
+        assert_eq!(
+            pairs.into_iter().collect::<Vec<_>>(),
+            vec![(baddie.get_id(), wall_id)]
+        );
+    }
+
+    #[test]
+    fn grid_occupancy_counts_objects_per_bin() {
+        // Arrange - a wall and a baddie sharing a bin, and another baddie on its own
+        let obj_factory = ObjectFactory::new(400);
+        let (wall, _, wall_geom, _) = obj_factory.make_wall((1500, 1500));
+        let (baddie1, _, baddie1_geom, _) = obj_factory.make_baddie((1400, 1600), (0, 0), 0.0);
+        let (baddie2, _, baddie2_geom, _) = obj_factory.make_baddie((8500, 500), (0, 0), 0.0);
+        let mut broad_phase = SpatialGrid::with_bin_size(1000);
+        broad_phase.update(&wall_and_baddie_layers(
+            &[(wall.get_id(), &wall_geom)],
+            &[
+                (baddie1.get_id(), &baddie1_geom),
+                (baddie2.get_id(), &baddie2_geom),
+            ],
+        ));
+
+        // Act
+        let occupancy = broad_phase.grid_occupancy().unwrap();
+
+        // Assert
+        assert_eq!(occupancy.bin_size, 1000);
+        assert_eq!(
+            occupancy.counts.into_iter().collect::<Vec<_>>(),
+            vec![((1, 1), 2), ((8, 0), 1)]
+        );
+        assert_eq!(BruteForce::new().grid_occupancy(), None);
+    }
+
+    #[test]
+    fn calc_bin_size_from_sizes_and_density() {
+        // Arrange - lots of small baddies, and a few long walls
+        let obj_factory = ObjectFactory::new(1000);
+        let mut allocator = EntityAllocator::new();
+        let long_wall = Geometry::Polygon(vec![(0, 0), (9000, 0), (9000, 50), (0, 50), (0, 0)]);
+        let baddies: Vec<(EntityId, Geometry)> = (0..2000)
+            .map(|i| {
+                let center = ((i % 40) * 200 + 500, (i / 40) * 150 + 500);
+                (
+                    allocator.allocate(),
+                    obj_factory.make_baddie(center, (0, 0), 0.0).2,
+                )
+            })
+            .collect();
+        let walls: Vec<(EntityId, Geometry)> = (0..10)
+            .map(|_| (allocator.allocate(), long_wall.clone()))
+            .collect();
+        fn as_refs(objects: &[(EntityId, Geometry)]) -> Vec<(EntityId, &Geometry)> {
+            objects.iter().map(|(id, geom)| (*id, geom)).collect()
+        }
+        let baddie_diameter = (baddies[0].1.diameter_sqr() as f32).sqrt() as i32;
+
+        // Act
+        let sparse_layers = wall_and_baddie_layers(&as_refs(&walls), &as_refs(&baddies[..100]));
+        let dense = calc_bin_size(
+            &wall_and_baddie_layers(&as_refs(&walls), &as_refs(&baddies)),
+            DEFAULT_WORLD_SIZE,
+        );
+        let sparse = calc_bin_size(&sparse_layers, DEFAULT_WORLD_SIZE);
+        let sparse_wide = calc_bin_size(&sparse_layers, (40000, 10000));
+        let empty = calc_bin_size(&GeomLayers::new(), DEFAULT_WORLD_SIZE);
+        let empty_wide = calc_bin_size(&GeomLayers::new(), (40000, 10000));
+
+        // Assert - sized for the baddies rather than the walls, and bigger when there are few objects for the area
+        assert_eq!(
+            dense,
+            baddie_diameter.max(GRID_WIDTH as i32 / MAX_BINS_ACROSS)
+        );
+        assert_eq!(sparse, (1e8f32 * OBJECTS_PER_BIN / 110.0).sqrt() as i32);
+        assert_eq!(
+            sparse_wide,
+            (4e8f32 * OBJECTS_PER_BIN / 110.0).sqrt() as i32
+        );
+        assert_eq!(empty, GRID_WIDTH as i32);
+        assert_eq!(empty_wide, 40000);
+    }
+
+    #[test]
+    fn sweep_and_prune_pairs_overlapping_boxes() {
+        // Arrange - a wall, a baddie overlapping it, one whose x range overlaps it but y doesn't, and one far away
+        let obj_factory = ObjectFactory::new(1000);
+        let (wall, _, wall_geom, _) = obj_factory.make_wall((5000, 5000));
+        let (baddie1, _, baddie1_geom, _) = obj_factory.make_baddie((5800, 5000), (0, 0), 0.0);
+        let (baddie2, _, baddie2_geom, _) = obj_factory.make_baddie((5000, 7000), (0, 0), 0.0);
+        let (baddie3, _, baddie3_geom, _) = obj_factory.make_baddie((500, 500), (0, 0), 0.0);
+        let layers = wall_and_baddie_layers(
+            &[(wall.get_id(), &wall_geom)],
+            &[
+                (baddie1.get_id(), &baddie1_geom),
+                (baddie2.get_id(), &baddie2_geom),
+                (baddie3.get_id(), &baddie3_geom),
+            ],
+        );
+        let mut broad_phase = SweepAndPrune::new();
+
+        // Act
+        broad_phase.update(&layers);
+
+        // Assert
+        let pairs = broad_phase.candidate_pairs(EntityKind::Baddie, EntityKind::Wall, false);
+        assert_eq!(
+            pairs.into_iter().collect::<Vec<_>>(),
+            vec![(baddie1.get_id(), wall.get_id())]
+        );
+        let boxes = broad_phase.layers.get(&EntityKind::Baddie).unwrap();
+        assert!(boxes
+            .windows(2)
+            .all(|pair| pair[0].1.min.0 <= pair[1].1.min.0));
+    }
+
+    #[test]
+    fn aabb_tree_only_reinserts_objects_leaving_fat_boxes() {
+        // Arrange - lots of baddies, so the tree has some depth
+        let obj_factory = ObjectFactory::new(1000);
+        let baddies: Vec<_> = (0..50)
+            .map(|i| obj_factory.make_baddie((200 * i, 100 * (i % 7)), (0, 0), 0.0))
+            .collect();
+        let geoms = |offset: i32| -> Vec<(EntityId, Geometry)> {
+            baddies
+                .iter()
+                .map(|(entity, _, geom, _)| (entity.get_id(), geom.translate((offset, 0))))
+                .collect()
+        };
+        let layers_of = |geoms: &[(EntityId, Geometry)]| -> HashMap<EntityId, Geometry> {
+            geoms.iter().cloned().collect()
+        };
+        let start = layers_of(&geoms(0));
+        let mut broad_phase = AabbTree::new();
+        broad_phase.update(&single_layer(&start));
+        assert_eq!(broad_phase.updated(), 50);
+
+        // Act - move a little, then a lot
+        let nudged = layers_of(&geoms(2));
+        broad_phase.update(&single_layer(&nudged));
+        let nudge_updated = broad_phase.updated();
+        let moved = layers_of(&geoms(500));
+        broad_phase.update(&single_layer(&moved));
+
+        // Assert - and the tree is balanced
+        assert_eq!(nudge_updated, 0);
+        assert_eq!(broad_phase.updated(), 50);
+        let tree = broad_phase.layers.get(&EntityKind::Baddie).unwrap();
+        assert!(tree.nodes[tree.root.unwrap()].height <= 12);
+    }
+
+    fn single_layer(geoms: &HashMap<EntityId, Geometry>) -> GeomLayers<'_> {
+        let layer: GeomRefMap = geoms.iter().map(|(id, geom)| (*id, geom)).collect();
+        [(EntityKind::Baddie, layer)].iter().cloned().collect()
+    }
+
+    /// An object in a randomised world
+    struct RandomObject {
+        kind: EntityKind,
+        center: (i32, i32),
+        size: i32,
+        angle: f32,
+        vel: (i32, i32),
+        round: bool,
+    }
+
+    impl RandomObject {
+        fn new(rng: &mut Pcg32, kinds: &[EntityKind]) -> Self {
+            // Mostly small objects, but some much bigger than grid bins (or the world), some over the edges
+            let size = match rng.gen_range(0, 9) {
+                0 => rng.gen_range(2000, 12000),
+                1..=3 => rng.gen_range(300, 2000),
+                _ => rng.gen_range(5, 300),
+            };
+            Self {
+                kind: kinds[rng.gen_range(0, kinds.len() as i32 - 1) as usize],
+                center: (rng.gen_range(-500, 10500), rng.gen_range(-500, 10500)),
+                size,
+                angle: rng.gen_range(0, 628) as f32 / 100.0,
+                vel: (rng.gen_range(-300, 300), rng.gen_range(-300, 300)),
+                round: rng.gen_range(0, 3) == 0,
+            }
+        }
+
+        fn geometry(&self) -> Geometry {
+            if self.round {
+                return Geometry::Circle {
+                    center: self.center,
+                    radius: self.size / 2,
+                };
+            }
+            let (cx, cy) = self.center;
+            let (half_w, half_h) = (self.size / 2, self.size / 6 + 1);
+            let mut vertices = vec![
+                (cx - half_w, cy - half_h),
+                (cx + half_w, cy - half_h),
+                (cx + half_w, cy + half_h),
+                (cx - half_w, cy + half_h),
+                (cx - half_w, cy - half_h),
+            ];
+            for v in vertices.iter_mut() {
+                rotate(v, &self.center, Real::from_f32(self.angle));
+            }
+            Geometry::Polygon(vertices)
+        }
+    }
+
+    /// Colliding pairs, according to the given broad phase's candidates
+    fn collisions(
+        broad_phase: &dyn BroadPhase,
+        layers: &GeomLayers,
+        kind: (EntityKind, EntityKind),
+        parallel: bool,
+    ) -> Vec<(EntityId, EntityId)> {
+        broad_phase
+            .candidate_pairs(kind.0, kind.1, parallel)
+            .into_iter()
+            .filter(|(left, right)| {
+                let (left, right) = (layers[&kind.0][left], layers[&kind.1][right]);
+                // Bounding boxes first, as it's much quicker (for brute force)
+                left.aabb().overlaps(&right.aabb()) && left.collides(right)
+            })
+            .sorted()
+            .collect()
+    }
+
+    /// Grids over non-square worlds find the same collisions as brute force, with bins spanning each world
+    #[test]
+    fn grid_in_non_square_world_matches_brute_force() {
+        let kinds = [EntityKind::Wall, EntityKind::Baddie, EntityKind::Bullet];
+        for (seed, world_size) in [(20000, 5000), (3000, 12000)].iter().enumerate() {
+            // Arrange - random objects over the world (and its edges)
+            let mut rng = Pcg32::new(seed as u64);
+            let mut allocator = EntityAllocator::new();
+            let geoms: HashMap<EntityId, Geometry> = (0..200)
+                .map(|_| {
+                    let mut object = RandomObject::new(&mut rng, &kinds);
+                    object.center = (
+                        rng.gen_range(-500, world_size.0 + 500),
+                        rng.gen_range(-500, world_size.1 + 500),
+                    );
+                    (allocator.allocate(), object.geometry())
+                })
+                .collect();
+            let layers = single_layer(&geoms);
+            let mut brute_force = BruteForce::new();
+            let mut grid = SpatialGrid::new().with_world_size(*world_size);
+            let mut small_bins = SpatialGrid::with_bin_size(500).with_world_size(*world_size);
+
+            // Act
+            brute_force.update(&layers);
+            grid.update(&layers);
+            small_bins.update(&layers);
+
+            // Assert
+            let kind = (EntityKind::Baddie, EntityKind::Baddie);
+            let expected = collisions(&brute_force, &layers, kind, false);
+            assert!(!expected.is_empty());
+            assert_eq!(collisions(&grid, &layers, kind, false), expected);
+            assert_eq!(collisions(&small_bins, &layers, kind, true), expected);
+            // The far bins are at the world's edges, rather than a square world's
+            let occupancy = small_bins.grid_occupancy().unwrap();
+            let columns = occupancy.counts.keys().map(|(column, _)| *column).max();
+            let rows = occupancy.counts.keys().map(|(_, row)| *row).max();
+            assert_eq!(columns, Some(world_size.0 / 500));
+            assert_eq!(rows, Some(world_size.1 / 500));
+        }
+    }
+
+    /// Each implementation finds the same collisions (and query results) as brute force,
+    /// in random worlds that change over several updates
+    #[test]
+    fn broad_phases_match_brute_force() {
+        let kinds = [EntityKind::Wall, EntityKind::Baddie, EntityKind::Bullet];
+        let collision_kinds = [
+            (EntityKind::Baddie, EntityKind::Wall),
+            (EntityKind::Bullet, EntityKind::Baddie),
+            (EntityKind::Baddie, EntityKind::Baddie),
+        ];
+        for seed in 0..3 {
+            // Arrange
+            let mut rng = Pcg32::new(seed);
+            let mut allocator = EntityAllocator::new();
+            let mut objects: HashMap<EntityId, RandomObject> = (0..200)
+                .map(|_| (allocator.allocate(), RandomObject::new(&mut rng, &kinds)))
+                .collect();
+            let mut broad_phases: Vec<(BroadPhaseKind, Box<dyn BroadPhase>)> = BroadPhaseKind::ALL
+                .iter()
+                .map(|kind| (*kind, kind.create()))
+                .collect();
+
+            for step in 0..8 {
+                // Act
+                let geoms: HashMap<EntityId, Geometry> = objects
+                    .iter()
+                    .map(|(id, object)| (*id, object.geometry()))
+                    .collect();
+                let mut layers = GeomLayers::new();
+                for (id, object) in objects.iter() {
+                    layers
+                        .entry(object.kind)
+                        .or_default()
+                        .insert(*id, &geoms[id]);
+                }
+                for (_, broad_phase) in broad_phases.iter_mut() {
+                    broad_phase.update(&layers);
+                }
+
+                // Assert
+                let reference = BroadPhaseKind::BruteForce;
+                let (_, brute_force) = broad_phases
+                    .iter()
+                    .find(|(kind, _)| *kind == reference)
+                    .unwrap();
+                for kind in collision_kinds.iter() {
+                    let expected = collisions(brute_force.as_ref(), &layers, *kind, false);
+                    assert!(!expected.is_empty());
+                    for (broad_phase_kind, broad_phase) in broad_phases.iter() {
+                        for parallel in [false, true] {
+                            assert_eq!(
+                                collisions(broad_phase.as_ref(), &layers, *kind, parallel),
+                                expected,
+                                "{:?} differs from {:?} - seed {}, step {}, {:?}, parallel {}",
+                                broad_phase_kind,
+                                reference,
+                                seed,
+                                step,
+                                kind,
+                                parallel
+                            );
+                        }
+                    }
+                }
+                for _ in 0..10 {
+                    let query = RandomObject::new(&mut rng, &kinds);
+                    let aabb = query.geometry().aabb();
+                    let overlapping = |broad_phase: &dyn BroadPhase| {
+                        let found = broad_phase.query(query.kind, &aabb);
+                        assert_eq!(found.iter().unique().count(), found.len());
+                        found
+                            .into_iter()
+                            .filter(|id| layers[&query.kind][id].aabb().overlaps(&aabb))
+                            .sorted()
+                            .collect::<Vec<_>>()
+                    };
+                    let expected = overlapping(brute_force.as_ref());
+                    for (broad_phase_kind, broad_phase) in broad_phases.iter() {
+                        assert_eq!(
+                            overlapping(broad_phase.as_ref()),
+                            expected,
+                            "{:?} query differs from {:?} - seed {}, step {}",
+                            broad_phase_kind,
+                            reference,
+                            seed,
+                            step
+                        );
+                    }
+                }
+
+                // Move everything, and replace some objects
+                for object in objects.values_mut() {
+                    object.center.0 += object.vel.0;
+                    object.center.1 += object.vel.1;
+                }
+                let removed: Vec<EntityId> = objects.keys().cloned().sorted().take(10).collect();
+                for id in removed {
+                    objects.remove(&id);
+                    allocator.free(id);
+                    objects.insert(allocator.allocate(), RandomObject::new(&mut rng, &kinds));
+                }
+            }
+        }
+    }
+}
@@ -1,453 +1,674 @@
-use crate::entity::EntityId;
-use crate::geometry::{box_side_len_sqr, is_collision, Geometry, Vertex};
-use crate::world::{GeomRefMap, GRID_HEIGHT, GRID_WIDTH};
+//! Collision detection - the broad phase (see [`crate::broad_phase`]) finds candidate pairs,
+//! which are then tested with separating axis (narrow phase).
+//!
+//! Geometries are grouped into layers, one per [`EntityKind`]. Only the pairs of layers that are registered
+//! are tested against each other, so new kinds of collision can be added without touching this module.
+//! Collisions are reported as a stream of [`CollisionEvent`]s, in a deterministic order, for game logic to respond to.
+//!
+//! The world may wrap around at its edges (see [`CollisionSystem::wrap_around`]), in which case objects hanging over
+//! an edge also collide with those at the opposite edge.
+
+use crate::broad_phase::{BroadPhase, GridOccupancy};
+use crate::entity::{EntityId, EntityKind};
+use crate::geometry::{Contact, Vector};
+use crate::helpers::flat_map_collect;
+use crate::world::{GeomLayers, GeomRefMap};
 use itertools::Itertools;
-use rayon::prelude::*;
-use std::collections::{HashMap, HashSet};
-use std::iter::FromIterator;
-
-/// Represents the different pairs of entity-kinds' collisions we're interested in observing
-#[derive(PartialEq, Hash, Eq)]
-pub enum CollisionKind {
-    BaddieWall,
-    BulletWall,
-    BulletBaddie,
-    BaddieCannon,
+use std::collections::HashMap;
+
+/// A pair of entity kinds (layers) whose collisions we're interested in observing, e.g. (bullet, baddie).
+/// Events give the colliding entities' IDs in the same order.
+pub type CollisionKind = (EntityKind, EntityKind);
+
+/// A detected collision between two entities
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CollisionEvent {
+    /// Kinds of the entities, as registered
+    pub kind: CollisionKind,
+    /// First entity, of kind `kind.0`
+    pub left: EntityId,
+    /// Second entity, of kind `kind.1`
+    pub right: EntityId,
+    /// Contact manifold, with the normal pointing from the first entity towards the second
+    pub contact: Contact,
 }
 
-/// Collision handler - called when the collision of the supplied entity kinds is detected.
-pub type CollisionHandler<'a> = Box<dyn 'a + FnMut(EntityId, EntityId) -> ()>;
-
-/// Collision handlers for each entity-kind pair
-pub type CollisionHandlers<'a> = HashMap<CollisionKind, CollisionHandler<'a>>;
-
-/// Colliding object pairs
-type CollisionPairs = HashSet<(EntityId, EntityId)>;
-
-// Detected collisions for each entity-kind pair
-type Collisions = HashMap<CollisionKind, CollisionPairs>;
-type Bins = HashSet<i32>;
-type SpatialMap = HashMap<i32, HashSet<EntityId>>;
-type SpatialIndex = HashMap<EntityId, Bins>;
-
-fn calc_bin_count(grid_bin_size: i32) -> i32 {
-    ((GRID_WIDTH as i32 / grid_bin_size) + 1) * ((GRID_HEIGHT as i32 / grid_bin_size) + 1)
-}
-
-fn calc_bin(vertex: &Vertex, grid_bin_size: i32) -> i32 {
-    // Assume grid is square, or this calc won't work
-    assert!(GRID_WIDTH == GRID_HEIGHT);
-    let (vx, vy) = vertex;
-    let bx = vx / grid_bin_size;
-    let by = vy / grid_bin_size;
-    bx + by * GRID_WIDTH as i32 / grid_bin_size
+/// Numbers of pairs found for a kind of collision
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PairCounts {
+    /// Kinds of the entities, as registered
+    pub kind: CollisionKind,
+    /// Pairs the broad phase gave as possibly colliding (not counting those across the world edges)
+    pub candidates: usize,
+    /// Pairs that were colliding
+    pub collisions: usize,
 }
 
-/// Spatial hash. Calculates the indices of a regular grid that the given geometry occupies.
-/// Important - this implementation only works if shape size < bin size.
-fn grid_hash(vertices: &Geometry, grid_bin_size: i32) -> Bins {
-    let mut bins = Bins::new();
-    for v in vertices {
-        bins.insert(calc_bin(v, grid_bin_size));
-    }
-    bins
+/// What the broad and narrow phases found, e.g. for a debug overlay
+#[derive(Clone, Debug, PartialEq)]
+pub struct CollisionDebug {
+    /// Occupancy of the broad phase's bins, if it's a grid
+    pub grid: Option<GridOccupancy>,
+    /// Counts for each registered kind of collision, in registration order
+    pub pairs: Vec<PairCounts>,
 }
 
-/// Build map of bin -> object list, and associated index
-fn build_map(geometries: &GeomRefMap, grid_bin_size: i32) -> (SpatialMap, SpatialIndex) {
-    let mut object_map = SpatialMap::new();
-    let mut object_index = SpatialIndex::new();
-    for geometry in geometries {
-        let (id, vertices) = geometry;
-        let grid_bins = grid_hash(vertices, grid_bin_size);
-        for bin in grid_bins.iter() {
-            object_map
-                .entry(*bin)
-                .and_modify(|e| {
-                    e.insert(*id);
-                })
-                .or_insert(HashSet::from_iter([*id].iter().cloned()));
-        }
-
-        let existing = object_index.insert(*id, grid_bins);
-        if let Some(_) = existing {
-            panic!("Unexpected duplicate object.");
+/// Colliding object pairs, and their contact manifold
+type CollisionPairs = HashMap<(EntityId, EntityId), Contact>;
+
+/// Candidate pairs across the edges of a world of the given size that wraps around.
+/// Each object hanging over an edge is moved to where the overhanging part appears, and paired with the objects there.
+/// Gives the offset to move the right object by, so that it meets the left one.
+fn wrapped_candidate_pairs(
+    broad_phase: &dyn BroadPhase,
+    left_geoms: &GeomRefMap,
+    right_geoms: &GeomRefMap,
+    kind: CollisionKind,
+    size: Vector,
+    parallel: bool,
+) -> Vec<((EntityId, EntityId), Vector)> {
+    let same_layer = kind.0 == kind.1;
+    // Images of objects from one layer, paired with the objects from the other layer that they overlap
+    let images =
+        |geoms: &GeomRefMap, other_kind: EntityKind| -> Vec<(EntityId, EntityId, Vector)> {
+            flat_map_collect(geoms, parallel, |(id, geometry)| {
+                let aabb = geometry.aabb();
+                aabb.wrap_offsets(size)
+                    .into_iter()
+                    .flat_map(|offset| {
+                        let found = broad_phase.query(other_kind, &aabb.translate(offset));
+                        found
+                            .into_iter()
+                            .map(move |other_id| (*id, other_id, offset))
+                    })
+                    .collect::<Vec<_>>()
+            })
+        };
+    let mut pairs: Vec<((EntityId, EntityId), Vector)> = vec![];
+    // Left object moved by the offset meets the right one, so the right one is moved the opposite way
+    for (left_id, right_id, (dx, dy)) in images(left_geoms, kind.1) {
+        if !same_layer || left_id < right_id {
+            pairs.push(((left_id, right_id), (-dx, -dy)));
+        } else if right_id < left_id {
+            pairs.push(((right_id, left_id), (dx, dy)));
         }
     }
-    (object_map, object_index)
-}
-
-// const empty_set: &HashSet::<EntityId> = &HashSet::<EntityId>::default();
-// fn empty() -> &'static HashSet<EntityId> {
-//     &empty_set
-// }
-
-// Accumulate collision pairs
-fn add_collisions(
-    collisions_acc: &mut Collisions,
-    kind: &CollisionKind,
-    left: &(&SpatialMap, &GeomRefMap),
-    right: &(&SpatialMap, &GeomRefMap),
-    bin: &i32,
-) {
-    // TODO: can extract these?
-    let empty_set = &HashSet::<EntityId>::default();
-    let empty = || Some(empty_set);
-    let (left_map, left_geoms) = left;
-    let (right_map, right_geoms) = right;
-    let left_ids = left_map.get(bin).or_else(empty).unwrap();
-    let right_ids = right_map.get(bin).or_else(empty).unwrap();
-    let collision_pairs =
-        left_ids
-            .iter()
-            .cartesian_product(right_ids)
-            .filter(|(left_id, right_id)| {
-                let left_geom = left_geoms.get(*left_id).unwrap();
-                let right_geom = right_geoms.get(*right_id).unwrap();
-                is_collision(*left_geom, *right_geom)
-            });
-
-    for (left_id, right_id) in collision_pairs {
-        collisions_acc
-            .get_mut(kind)
-            .unwrap()
-            .insert((*left_id, *right_id));
+    // Within a single layer, that's already covered both ways round
+    if !same_layer {
+        for (right_id, left_id, offset) in images(right_geoms, kind.0) {
+            pairs.push(((left_id, right_id), offset));
+        }
     }
+    // A pair may be found from either object. (Or at several offsets, e.g. by a query that isn't exact.)
+    pairs.sort();
+    pairs.dedup();
+    pairs
 }
 
+/// Tests the broad phase's candidate pairs for each kind of collision, plus those across the world edges if it wraps.
+/// Events are in the order of `kinds`, then by entity IDs.
+/// The work is spread across rayon's threads if there are at least `parallel_threshold` objects in the layers involved.
 fn detect_collisions(
-    walls: (&SpatialMap, &GeomRefMap),
-    baddies: (&SpatialMap, &GeomRefMap),
-    bullets: (&SpatialMap, &GeomRefMap),
-    cannons: (&SpatialMap, &GeomRefMap),
-    grid_bin_size: i32,
-) -> Collisions {
-    let bin_count = calc_bin_count(grid_bin_size);
-    let init = || {
-        let mut collisions_init = Collisions::new();
-        collisions_init.insert(CollisionKind::BaddieWall, CollisionPairs::new());
-        collisions_init.insert(CollisionKind::BulletBaddie, CollisionPairs::new());
-        collisions_init.insert(CollisionKind::BulletWall, CollisionPairs::new());
-        collisions_init.insert(CollisionKind::BaddieCannon, CollisionPairs::new());
-        collisions_init
-    };
-
-    let collisions = (0..bin_count)
-        .into_par_iter()
-        .fold(init, |mut collisions_acc, bin| {
-            add_collisions(
-                &mut collisions_acc,
-                &CollisionKind::BaddieWall,
-                &baddies,
-                &walls,
-                &bin,
-            );
-            add_collisions(
-                &mut collisions_acc,
-                &CollisionKind::BulletWall,
-                &bullets,
-                &walls,
-                &bin,
-            );
-            add_collisions(
-                &mut collisions_acc,
-                &CollisionKind::BulletBaddie,
-                &bullets,
-                &baddies,
-                &bin,
-            );
-            add_collisions(
-                &mut collisions_acc,
-                &CollisionKind::BaddieCannon,
-                &baddies,
-                &cannons,
-                &bin,
-            );
-            collisions_acc
-        })
-        // Stitch together sub-collections
-        .reduce(
-            || {
-                let mut collisions_init = Collisions::new();
-                collisions_init.insert(CollisionKind::BaddieWall, CollisionPairs::new());
-                collisions_init.insert(CollisionKind::BulletBaddie, CollisionPairs::new());
-                collisions_init.insert(CollisionKind::BulletWall, CollisionPairs::new());
-                collisions_init.insert(CollisionKind::BaddieCannon, CollisionPairs::new());
-                collisions_init
-            },
-            |mut acc, c_sub| {
-                for (collision_kind, entries) in c_sub {
-                    let acc_collisionpairs = acc.get_mut(&collision_kind).unwrap();
-                    for collision in entries {
-                        acc_collisionpairs.insert(collision);
-                    }
+    broad_phase: &dyn BroadPhase,
+    layers: &GeomLayers,
+    kinds: &[CollisionKind],
+    wrap: Option<Vector>,
+    parallel_threshold: usize,
+) -> Vec<CollisionEvent> {
+    // Layers without any objects
+    let empty_geoms = GeomRefMap::new();
+    let layer = |kind: &EntityKind| layers.get(kind).unwrap_or(&empty_geoms);
+    let objects: usize = kinds
+        .iter()
+        .flat_map(|(left, right)| [*left, *right])
+        .unique()
+        .map(|kind| layer(&kind).len())
+        .sum();
+    let parallel = objects >= parallel_threshold;
+    kinds
+        .iter()
+        .flat_map(|kind| {
+            let (left_geoms, right_geoms) = (layer(&kind.0), layer(&kind.1));
+            let candidates = broad_phase.candidate_pairs(kind.0, kind.1, parallel);
+            let mut collision_pairs: CollisionPairs =
+                flat_map_collect(&candidates, parallel, |(left_id, right_id)| {
+                    let left_geom = left_geoms.get(left_id).unwrap();
+                    let right_geom = right_geoms.get(right_id).unwrap();
+                    left_geom
+                        .contact(right_geom)
+                        .map(|contact| ((*left_id, *right_id), contact))
+                });
+            if let Some(size) = wrap {
+                let wrapped_candidates = wrapped_candidate_pairs(
+                    broad_phase,
+                    left_geoms,
+                    right_geoms,
+                    *kind,
+                    size,
+                    parallel,
+                );
+                let wrapped: Vec<_> = flat_map_collect(
+                    wrapped_candidates,
+                    parallel,
+                    |((left_id, right_id), offset)| {
+                        let left_geom = left_geoms.get(&left_id).unwrap();
+                        let right_geom = right_geoms.get(&right_id).unwrap().translate(offset);
+                        left_geom
+                            .contact(&right_geom)
+                            .map(|contact| ((left_id, right_id), contact))
+                    },
+                );
+                // Objects that also meet directly (e.g. both hanging over the same edge) keep that contact
+                for (pair, contact) in wrapped {
+                    collision_pairs.entry(pair).or_insert(contact);
                 }
-                acc
-            },
-        );
-    collisions
+            }
+            collision_pairs
+                .into_iter()
+                .sorted_by_key(|(pair, _)| *pair)
+                .map(move |((left, right), contact)| CollisionEvent {
+                    kind: *kind,
+                    left,
+                    right,
+                    contact,
+                })
+        })
+        .collect()
 }
 
-/// Calculates grid bin size by taking the biggest object's horiz/vert span (assumes uniform size by kind)
-/// According to radius circumscribed by rotation
-/// Down to a minimum size (to avoid diminishing perf)
-fn calc_bin_size(
-    walls: &GeomRefMap,
-    baddies: &GeomRefMap,
-    bullets: &GeomRefMap,
-    cannons: &GeomRefMap,
-) -> i32 {
-    let default = 250;
-    let x = (walls
-        .iter()
-        .take(1)
-        .chain(baddies.iter().take(1))
-        .chain(bullets.iter().take(1))
-        .chain(cannons.iter().take(1))
-        .map(|(_, geom)| box_side_len_sqr(&geom))
-        .max()
-        .unwrap_or(default) as f32)
-        .sqrt();
-    let res = (x * std::f32::consts::SQRT_2) as i32;
-    std::cmp::max(res, default)
-    // Uses a small optimization there - compares squares and only computes a single sqrt at the end
+/// Default for [`CollisionSystem::parallel_threshold`]. Below this, spreading the work across threads costs more than it saves.
+pub const DEFAULT_PARALLEL_THRESHOLD: usize = 1000;
+
+/// Detects collisions between the registered kinds of entity
+pub struct CollisionSystem {
+    /// Kinds of collision to detect, in registration order
+    kinds: Vec<CollisionKind>,
+    /// Size of the world, if it wraps around at the edges
+    wrap: Option<Vector>,
+    /// Number of objects from which detection is multithreaded
+    parallel_threshold: usize,
 }
 
-/// Detects collisions and runs handlers as appropriate
-pub struct CollisionSystem<'a> {
-    wall_map: SpatialMap,
-    #[allow(unused)]
-    wall_index: SpatialIndex,
-    baddie_map: SpatialMap,
-    #[allow(unused)]
-    baddie_index: SpatialIndex,
-    bullet_map: SpatialMap,
-    #[allow(unused)]
-    bullet_index: SpatialIndex,
-    cannon_map: SpatialMap,
-    #[allow(unused)]
-    cannon_index: SpatialIndex,
-    handlers: CollisionHandlers<'a>,
-    /// Bin size for spatial hashmap (square grid).
-    /// 10000 / 1000 => 10 * 10 grid
-    grid_bin_size: i32,
+impl Default for CollisionSystem {
+    fn default() -> Self {
+        Self {
+            kinds: Vec::new(),
+            wrap: None,
+            parallel_threshold: DEFAULT_PARALLEL_THRESHOLD,
+        }
+    }
 }
 
-impl<'a> CollisionSystem<'a> {
-    pub fn new(
-        walls: &GeomRefMap,
-        baddies: &GeomRefMap,
-        bullets: &GeomRefMap,
-        cannons: &GeomRefMap,
-        baddie_wall_handler: CollisionHandler<'a>,
-        bullet_wall_handler: CollisionHandler<'a>,
-        bullet_baddie_handler: CollisionHandler<'a>,
-        baddie_cannon_handler: CollisionHandler<'a>,
-    ) -> Self {
-        // build hashmaps from object geometries
-        let grid_bin_size = calc_bin_size(walls, baddies, bullets, cannons);
-        let (wall_map, wall_index) = build_map(walls, grid_bin_size);
-        let (baddie_map, baddie_index) = build_map(baddies, grid_bin_size);
-        let (bullet_map, bullet_index) = build_map(bullets, grid_bin_size);
-        let (cannon_map, cannon_index) = build_map(cannons, grid_bin_size);
-
-        let mut handlers = CollisionHandlers::new();
-        handlers.insert(CollisionKind::BaddieWall, baddie_wall_handler);
-        handlers.insert(CollisionKind::BulletBaddie, bullet_baddie_handler);
-        handlers.insert(CollisionKind::BulletWall, bullet_wall_handler);
-        handlers.insert(CollisionKind::BaddieCannon, baddie_cannon_handler);
+impl CollisionSystem {
+    /// Creates a collision system.
+    /// No collisions are detected until kinds of collision are registered, see [`CollisionSystem::register`].
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-        Self {
-            wall_map,
-            wall_index,
-            baddie_map,
-            baddie_index,
-            bullet_map,
-            bullet_index,
-            cannon_map,
-            cannon_index,
-            handlers,
-            grid_bin_size,
+    /// Registers interest in collisions between entities of kind `left` and kind `right` (which may be the same).
+    /// Registering the same kinds again has no effect.
+    pub fn register(&mut self, left: EntityKind, right: EntityKind) -> &mut Self {
+        if !self.kinds.contains(&(left, right)) {
+            self.kinds.push((left, right));
         }
+        self
+    }
+
+    /// Makes the world wrap around at its edges, given its size: objects hanging over an edge also collide with those
+    /// at the opposite edge (as if they were there). Contacts are given as if the second entity was moved to meet the first.
+    pub fn wrap_around(&mut self, size: Vector) -> &mut Self {
+        self.wrap = Some(size);
+        self
     }
 
-    /// Check collisions and run appropriate handlers
+    /// Sets the number of objects (in the registered kinds' layers) from which detection is spread across rayon's threads.
+    /// With fewer, it all runs on the calling thread. 0 means always multithreaded, and `usize::MAX` never.
+    pub fn parallel_threshold(&mut self, objects: usize) -> &mut Self {
+        self.parallel_threshold = objects;
+        self
+    }
+
+    /// Updates the broad phase with the given geometries (by kind), then detects collisions.
+    /// Events are grouped by kind of collision, in registration order, then ordered by entity IDs - so that responses
+    /// which don't commute (e.g. bouncing off 2 walls) are deterministic, whatever the order of the geometries.
     pub fn process(
-        &mut self,
-        wall_geoms: &GeomRefMap,
-        baddie_geoms: &GeomRefMap,
-        bullet_geoms: &GeomRefMap,
-        cannon_geoms: &GeomRefMap,
-    ) {
-        let collisions = detect_collisions(
-            (&self.wall_map, wall_geoms),
-            (&self.baddie_map, baddie_geoms),
-            (&self.bullet_map, bullet_geoms),
-            (&self.cannon_map, cannon_geoms),
-            self.grid_bin_size,
-        );
+        &self,
+        broad_phase: &mut dyn BroadPhase,
+        layers: &GeomLayers,
+    ) -> Vec<CollisionEvent> {
+        broad_phase.update(layers);
+        self.detect(broad_phase, layers)
+    }
 
-        // Can't parallelize this because the closures close over mutable data.
-        for (collision_kind, collision_pairs) in collisions {
-            for collision_pair in collision_pairs {
-                let handler = self.handlers.get_mut(&collision_kind).unwrap();
-                handler(collision_pair.0, collision_pair.1);
-            }
-        }
+    /// Detects collisions, with a broad phase that's already up to date with the given geometries.
+    /// Events are ordered as for [`CollisionSystem::process`].
+    pub fn detect(&self, broad_phase: &dyn BroadPhase, layers: &GeomLayers) -> Vec<CollisionEvent> {
+        detect_collisions(
+            broad_phase,
+            layers,
+            &self.kinds,
+            self.wrap,
+            self.parallel_threshold,
+        )
     }
 
-    // pub fn update(world: &mut World) {
-    //     // update hashmaps and check collisions
-    // }
+    /// Describes what was found by the last detection, given the broad phase it used and the events it gave.
+    /// Queries the broad phase again, so is best kept to debugging.
+    pub fn debug_info(
+        &self,
+        broad_phase: &dyn BroadPhase,
+        events: &[CollisionEvent],
+    ) -> CollisionDebug {
+        let pairs = self
+            .kinds
+            .iter()
+            .map(|kind| PairCounts {
+                kind: *kind,
+                candidates: broad_phase.candidate_pairs(kind.0, kind.1, false).len(),
+                collisions: events.iter().filter(|event| event.kind == *kind).count(),
+            })
+            .collect();
+        CollisionDebug {
+            grid: broad_phase.grid_occupancy(),
+            pairs,
+        }
+    }
 }
 
 // TODO: Decouple tests from World functions
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::world::ObjectFactory;
+    use crate::broad_phase::{BroadPhaseKind, SpatialGrid};
+    use crate::fixed::{Real, Scalar};
+    use crate::world::{ObjectFactory, GRID_HEIGHT, GRID_WIDTH};
 
     #[test]
-    fn grid_hash_single() {
-        let vertex = (1000, 2000);
-        let expected = Bins::from_iter([21].iter().cloned());
-        let actual = grid_hash(&[vertex, vertex, vertex, vertex, vertex], 1000);
+    fn collision_static_simple() {
+        // Arrange - 2 walls, 2 baddies, 1 of each colliding
+        let obj_factory = ObjectFactory::new(400);
+        let (wall1, _, wall1_geom, _) = obj_factory.make_wall((1200, 1200));
+        let (wall2, _, wall2_geom, _) = obj_factory.make_wall((1700, 1700));
+        // colliding baddie:
+        let (baddie1, _, baddie1_geom, _) = obj_factory.make_baddie((1200, 1200), (0, 0), 0.0);
+        // not colliding baddie:
+        let (baddie2, _, baddie2_geom, _) = obj_factory.make_baddie((0, 0), (0, 0), 0.0);
+        let mut layers = GeomLayers::new();
+        layers.insert(
+            EntityKind::Wall,
+            [(wall1.get_id(), &wall1_geom), (wall2.get_id(), &wall2_geom)]
+                .iter()
+                .cloned()
+                .collect(),
+        );
+        layers.insert(
+            EntityKind::Baddie,
+            [
+                (baddie1.get_id(), &baddie1_geom),
+                (baddie2.get_id(), &baddie2_geom),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+        );
+        let mut collision_system = CollisionSystem::new();
+        collision_system.register(EntityKind::Baddie, EntityKind::Wall);
+
+        // Act
+        let events = collision_system.process(&mut SpatialGrid::new(), &layers);
 
-        assert_eq!(expected, actual);
+        // Assert
+        assert_eq!(events.len(), 1);
+        let event = events[0];
+        assert_eq!(event.kind, (EntityKind::Baddie, EntityKind::Wall));
+        assert_eq!(
+            (event.left, event.right),
+            (baddie1.get_id(), wall1.get_id())
+        );
+        assert!(event.left != baddie2.get_id() && event.right != wall2.get_id());
     }
 
-    /// Tilted box spanning multiple bins
     #[test]
-    fn grid_hash_box() {
-        let vertices = [
-            (1000, 2000), // bin 21
-            (1770, 2200), // bin 21
-            (1550, 2900), // bin 21
-            (780, 2770),  // bin 20
-            (1000, 2000), // bin 21
-        ];
-        let expected = Bins::from_iter([20, 21].iter().cloned());
-        let actual = grid_hash(&vertices, 1000);
+    fn collision_events_drive_response() {
+        // Arrange - 1 wall, 1 baddie, colliding - the baddie reverses on each event
+        let obj_factory = ObjectFactory::new(1000);
+        let (wall, _, wall_geom, _) = obj_factory.make_wall((1200, 1200));
+        let (baddie, mut baddie_shape, baddie_geom, _) =
+            obj_factory.make_baddie((1200, 1200), (1000, 0), 0.0);
+        let mut layers = GeomLayers::new();
+        layers.insert(
+            EntityKind::Wall,
+            [(wall.get_id(), &wall_geom)].iter().cloned().collect(),
+        );
+        layers.insert(
+            EntityKind::Baddie,
+            [(baddie.get_id(), &baddie_geom)].iter().cloned().collect(),
+        );
+
+        let mut collision_system = CollisionSystem::new();
+        collision_system.register(EntityKind::Baddie, EntityKind::Wall);
 
-        assert_eq!(expected, actual);
+        // Act
+        for event in collision_system.process(&mut SpatialGrid::new(), &layers) {
+            assert_eq!(event.right, wall.get_id());
+            assert_eq!(event.left, baddie.get_id());
+            baddie_shape.reverse();
+        }
+
+        // Assert
+        assert_eq!(*baddie_shape.get_vel(), (-1000, 0));
     }
 
-    /// 2 walls with some occupying some bins in common - build map and index
+    /// Pairs within the same layer are only reported once, and never an object with itself
     #[test]
-    fn build_map_2walls_some_common_bins() {
-        // Arrange - 2 walls in bin 11
+    fn collision_same_layer() {
+        // Arrange - 2 overlapping baddies, and 1 apart
         let obj_factory = ObjectFactory::new(1000);
-        let (wall1, _, wall1_geom, _) = obj_factory.make_wall((1200, 1200));
-        let w1_bins_expected = Bins::from_iter([0, 1, 10, 11].iter().cloned());
-        let (wall2, _, wall2_geom, _) = obj_factory.make_wall((1700, 1700));
-        let w2_bins_expected = Bins::from_iter([11, 12, 21, 22].iter().cloned());
-        let walls_geoms: GeomRefMap =
-            [(wall1.get_id(), &wall1_geom), (wall2.get_id(), &wall2_geom)]
-                .iter()
-                .cloned()
-                .collect();
-        let expected = HashSet::from_iter([wall1.get_id(), wall2.get_id()].iter().cloned());
+        let (baddie1, _, baddie1_geom, _) = obj_factory.make_baddie((1200, 1200), (0, 0), 0.0);
+        let (baddie2, _, baddie2_geom, _) = obj_factory.make_baddie((1500, 1200), (0, 0), 0.0);
+        let (baddie3, _, baddie3_geom, _) = obj_factory.make_baddie((5000, 5000), (0, 0), 0.0);
+        let mut layers = GeomLayers::new();
+        layers.insert(
+            EntityKind::Baddie,
+            [
+                (baddie1.get_id(), &baddie1_geom),
+                (baddie2.get_id(), &baddie2_geom),
+                (baddie3.get_id(), &baddie3_geom),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+        );
+        let mut collision_system = CollisionSystem::new();
+        collision_system.register(EntityKind::Baddie, EntityKind::Baddie);
+
         // Act
-        let (wall_map, wall_index) = build_map(&walls_geoms, 1000);
+        let events = collision_system.process(&mut SpatialGrid::new(), &layers);
+
+        // Assert
+        assert_eq!(events.len(), 1);
+        let (a, b) = (events[0].left, events[0].right);
+        assert!(a == baddie1.get_id() || a == baddie2.get_id());
+        assert!(b == baddie1.get_id() || b == baddie2.get_id());
+        assert_ne!(a, b);
+    }
+
+    /// Collisions between unregistered pairs, or with empty layers, aren't reported
+    #[test]
+    fn collision_unregistered_pair_ignored() {
+        // Arrange - overlapping wall and bullet, but only bullet-baddie registered
+        let obj_factory = ObjectFactory::new(1000);
+        let (wall, _, wall_geom, _) = obj_factory.make_wall((1200, 1200));
+        let (bullet, _, bullet_geom, _) = obj_factory.make_bullet((1200, 1200), (1, 0));
+        let mut layers = GeomLayers::new();
+        layers.insert(
+            EntityKind::Wall,
+            [(wall.get_id(), &wall_geom)].iter().cloned().collect(),
+        );
+        layers.insert(
+            EntityKind::Bullet,
+            [(bullet.get_id(), &bullet_geom)].iter().cloned().collect(),
+        );
+        let mut collision_system = CollisionSystem::new();
+        collision_system.register(EntityKind::Bullet, EntityKind::Baddie);
 
-        // Assert - map
-        assert_eq!(wall_map.get(&11).unwrap(), &expected);
+        // Act
+        let events = collision_system.process(&mut SpatialGrid::new(), &layers);
 
-        // Assert - index
-        assert_eq!(wall_index.get(&wall1.get_id()), Some(&w1_bins_expected));
-        assert_eq!(wall_index.get(&wall2.get_id()), Some(&w2_bins_expected));
+        // Assert
+        assert!(events.is_empty());
     }
 
+    /// Events are grouped by kind, in registration order, then ordered by entity IDs
     #[test]
-    fn collision_static_simple() {
-        // Arrange - 2 walls, 2 baddies, 1 of each colliding, plus associated handler
-        let obj_factory = ObjectFactory::new(400);
-        let (wall1, _, wall1_geom, _) = obj_factory.make_wall((1200, 1200));
-        let (wall2, _, wall2_geom, _) = obj_factory.make_wall((1700, 1700));
-        let walls_geoms: GeomRefMap =
-            [(wall1.get_id(), &wall1_geom), (wall2.get_id(), &wall2_geom)]
+    fn collision_events_in_order() {
+        // Arrange - a bullet in a wall, and a row of overlapping baddies, the first also in the wall
+        let obj_factory = ObjectFactory::new(1000);
+        let (wall, _, wall_geom, _) = obj_factory.make_wall((1000, 5000));
+        let (bullet, _, bullet_geom, _) = obj_factory.make_bullet((1000, 5000), (1, 0));
+        let baddies: Vec<_> = (0..4)
+            .map(|i| obj_factory.make_baddie((1500 + 500 * i, 5000), (0, 0), 0.0))
+            .collect();
+        let mut layers = GeomLayers::new();
+        layers.insert(
+            EntityKind::Wall,
+            [(wall.get_id(), &wall_geom)].iter().cloned().collect(),
+        );
+        layers.insert(
+            EntityKind::Bullet,
+            [(bullet.get_id(), &bullet_geom)].iter().cloned().collect(),
+        );
+        layers.insert(
+            EntityKind::Baddie,
+            baddies
                 .iter()
-                .cloned()
-                .collect();
-
-        // colliding baddie:
-        let (baddie1, _, baddie1_geom, _) = obj_factory.make_baddie((1200, 1200), (0, 0), 0.0);
-        // not colliding baddie:
-        let (baddie2, _, baddie2_geom, _) = obj_factory.make_baddie((0, 0), (0, 0), 0.0);
-        let baddies_geoms: GeomRefMap = [
-            (baddie1.get_id(), &baddie1_geom),
-            (baddie2.get_id(), &baddie2_geom),
-        ]
-        .iter()
-        .cloned()
-        .collect();
-        let baddie_wall_handler = |baddie_id: EntityId, wall_id: EntityId| {
-            // Assert - handler called with correct arguments
-            assert!(
-                (wall_id == wall1.get_id() && baddie_id == baddie1.get_id())
-                    && !(baddie_id == baddie2.get_id() || wall_id == wall2.get_id())
-            )
-        };
-        let dummy_geoms = &GeomRefMap::new();
-        let dummy_handler = |_: EntityId, _: EntityId| ();
-        let mut collision_system = CollisionSystem::new(
-            &walls_geoms,
-            &baddies_geoms,
-            &dummy_geoms,
-            &dummy_geoms,
-            Box::new(baddie_wall_handler),
-            Box::new(dummy_handler),
-            Box::new(dummy_handler),
-            Box::new(dummy_handler),
+                .map(|(entity, _, geom, _)| (entity.get_id(), geom))
+                .collect(),
         );
+        let mut collision_system = CollisionSystem::new();
+        collision_system
+            .register(EntityKind::Baddie, EntityKind::Baddie)
+            .register(EntityKind::Bullet, EntityKind::Wall)
+            .register(EntityKind::Baddie, EntityKind::Wall)
+            .register(EntityKind::Baddie, EntityKind::Baddie);
+
         // Act
-        collision_system.process(&walls_geoms, &baddies_geoms, &dummy_geoms, &dummy_geoms);
+        let events = collision_system.process(&mut SpatialGrid::new(), &layers);
+
+        // Assert
+        let baddie = |i: usize| baddies[i].0.get_id();
+        let actual: Vec<_> = events
+            .iter()
+            .map(|event| (event.kind, event.left, event.right))
+            .collect();
+        let baddies_kind = (EntityKind::Baddie, EntityKind::Baddie);
+        let expected = vec![
+            (baddies_kind, baddie(0), baddie(1)),
+            (baddies_kind, baddie(1), baddie(2)),
+            (baddies_kind, baddie(2), baddie(3)),
+            (
+                (EntityKind::Bullet, EntityKind::Wall),
+                bullet.get_id(),
+                wall.get_id(),
+            ),
+            (
+                (EntityKind::Baddie, EntityKind::Wall),
+                baddie(0),
+                wall.get_id(),
+            ),
+        ];
+        assert_eq!(actual, expected);
+
+        // Act - multithreaded, however few objects
+        let parallel_events = collision_system
+            .parallel_threshold(0)
+            .process(&mut SpatialGrid::new(), &layers);
 
-        // Assert - see handler, above
+        // Assert - the same events, in the same order
+        assert_eq!(parallel_events, events);
     }
 
     #[test]
-    fn collision_can_mutate_baddie() {
-        // Arrange - 1 wall, 1 baddies, colliding, plus associated baddie_wall_handler
+    fn debug_info_counts_pairs() {
+        // Arrange - a baddie in a wall, and one on its own
         let obj_factory = ObjectFactory::new(1000);
-        let (wall, _, wall_geom, _) = obj_factory.make_wall((1200, 1200));
-        let walls_geoms: GeomRefMap = [(wall.get_id(), &wall_geom)].iter().cloned().collect();
-
-        let (baddie, mut baddie_shape, baddie_geom, _) =
-            obj_factory.make_baddie((1200, 1200), (1000, 0), 0.0);
-        let baddies_geoms: GeomRefMap = [(baddie.get_id(), &baddie_geom)].iter().cloned().collect();
+        let (wall, _, wall_geom, _) = obj_factory.make_wall((2000, 2000));
+        let (baddie1, _, baddie1_geom, _) = obj_factory.make_baddie((2300, 2000), (0, 0), 0.0);
+        let (baddie2, _, baddie2_geom, _) = obj_factory.make_baddie((7000, 7000), (0, 0), 0.0);
+        let mut layers = GeomLayers::new();
+        layers.insert(
+            EntityKind::Wall,
+            [(wall.get_id(), &wall_geom)].iter().cloned().collect(),
+        );
+        layers.insert(
+            EntityKind::Baddie,
+            [
+                (baddie1.get_id(), &baddie1_geom),
+                (baddie2.get_id(), &baddie2_geom),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+        );
+        let mut collision_system = CollisionSystem::new();
+        collision_system
+            .register(EntityKind::Baddie, EntityKind::Wall)
+            .register(EntityKind::Baddie, EntityKind::Baddie);
+        let mut broad_phase = SpatialGrid::new();
+        let events = collision_system.process(&mut broad_phase, &layers);
 
-        let baddie_wall_handler = |baddie_id: EntityId, wall_id: EntityId| {
-            assert_eq!(wall_id, wall.get_id());
-            assert_eq!(baddie_id, baddie.get_id());
-            baddie_shape.reverse();
-        };
-        let dummy_geoms = &GeomRefMap::new();
-        let dummy_handler = |_: EntityId, _: EntityId| ();
-        // Scope needed here for collision system - need to return borrowed references before assert
-        {
-            let mut collision_system = CollisionSystem::new(
-                &walls_geoms,
-                &baddies_geoms,
-                &dummy_geoms,
-                &dummy_geoms,
-                Box::new(baddie_wall_handler),
-                Box::new(dummy_handler),
-                Box::new(dummy_handler),
-                Box::new(dummy_handler),
-            );
-            // Act
-            collision_system.process(&walls_geoms, &baddies_geoms, &dummy_geoms, &dummy_geoms);
-        }
+        // Act
+        let debug = collision_system.debug_info(&broad_phase, &events);
 
         // Assert
-        assert_eq!(*baddie_shape.get_vel(), (-1000, 0));
+        assert_eq!(
+            debug.pairs,
+            vec![
+                PairCounts {
+                    kind: (EntityKind::Baddie, EntityKind::Wall),
+                    candidates: 1,
+                    collisions: 1,
+                },
+                PairCounts {
+                    kind: (EntityKind::Baddie, EntityKind::Baddie),
+                    candidates: 0,
+                    collisions: 0,
+                },
+            ]
+        );
+        let grid = debug.grid.unwrap();
+        assert_eq!(grid.bin_size, broad_phase.bin_size());
+        assert!(grid.counts.values().sum::<usize>() >= 3);
     }
 
+    /// Objects hanging over the world edge meet those at the opposite edge, but only if the world wraps around
+    #[test]
+    fn collision_across_wrapped_edge() {
+        // Arrange - baddie hanging over the right edge, cannon at the left edge, and a baddie just off the corner
+        let obj_factory = ObjectFactory::new(1000);
+        let size = (GRID_WIDTH as i32, GRID_HEIGHT as i32);
+        let (baddie1, _, baddie1_geom, _) =
+            obj_factory.make_baddie((size.0 - 100, 5000), (0, 0), 0.0);
+        let (baddie2, _, baddie2_geom, _) =
+            obj_factory.make_baddie((size.0 - 100, size.1 - 100), (0, 0), 0.0);
+        let (cannon, _, cannon_geom, _) = obj_factory.make_cannon((100, 5000));
+        let (corner_cannon, _, corner_cannon_geom, _) = obj_factory.make_cannon((100, 100));
+        let mut layers = GeomLayers::new();
+        layers.insert(
+            EntityKind::Baddie,
+            [
+                (baddie1.get_id(), &baddie1_geom),
+                (baddie2.get_id(), &baddie2_geom),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+        );
+        layers.insert(
+            EntityKind::Cannon,
+            [
+                (cannon.get_id(), &cannon_geom),
+                (corner_cannon.get_id(), &corner_cannon_geom),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+        );
+        for broad_phase_kind in BroadPhaseKind::ALL.iter() {
+            for wrap in [false, true].iter() {
+                let mut collision_system = CollisionSystem::new();
+                collision_system.register(EntityKind::Baddie, EntityKind::Cannon);
+                if *wrap {
+                    collision_system.wrap_around(size);
+                }
+
+                // Act
+                let events = collision_system.process(broad_phase_kind.create().as_mut(), &layers);
+
+                // Assert - pairs as if the cannons were moved to the baddies (so normals point away from the edges)
+                if *wrap {
+                    assert_eq!(events.len(), 2, "{:?}", broad_phase_kind);
+                    let expected = [(baddie1, cannon), (baddie2, corner_cannon)];
+                    for (event, (baddie, cannon)) in events.iter().zip(expected.iter()) {
+                        assert_eq!(
+                            (event.left, event.right),
+                            (baddie.get_id(), cannon.get_id())
+                        );
+                        let normal = event.contact.normal;
+                        assert!(normal.0 > Real::from_i32(0) || normal.1 > Real::from_i32(0));
+                    }
+                } else {
+                    assert!(events.is_empty(), "{:?}", broad_phase_kind);
+                }
+            }
+        }
+    }
+    /// In a wide, short world, objects meet across its own edges - not those of a square world
     #[test]
-    fn calc_bin_count() {
-        let bin_count_expected = 121;
-        let bin_count_actual = super::calc_bin_count(1000);
-        assert_eq!(bin_count_actual, bin_count_expected);
+    fn collision_across_wrapped_edge_non_square() {
+        // Arrange - baddies hanging over the right and bottom edges, with cannons at the opposite edges,
+        // and one hanging over where a square world's right edge would be
+        let obj_factory = ObjectFactory::new(1000);
+        let size = (16000, 6000);
+        let (right, _, right_geom, _) = obj_factory.make_baddie((15950, 3000), (0, 0), 0.0);
+        let (bottom, _, bottom_geom, _) = obj_factory.make_baddie((8000, 5950), (0, 0), 0.0);
+        let (inner, _, inner_geom, _) = obj_factory.make_baddie((9950, 1000), (0, 0), 0.0);
+        let (left_cannon, _, left_cannon_geom, _) = obj_factory.make_cannon((50, 3000));
+        let (top_cannon, _, top_cannon_geom, _) = obj_factory.make_cannon((8000, 50));
+        let (inner_cannon, _, inner_cannon_geom, _) = obj_factory.make_cannon((50, 1000));
+        let mut layers = GeomLayers::new();
+        layers.insert(
+            EntityKind::Baddie,
+            [
+                (right.get_id(), &right_geom),
+                (bottom.get_id(), &bottom_geom),
+                (inner.get_id(), &inner_geom),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+        );
+        layers.insert(
+            EntityKind::Cannon,
+            [
+                (left_cannon.get_id(), &left_cannon_geom),
+                (top_cannon.get_id(), &top_cannon_geom),
+                (inner_cannon.get_id(), &inner_cannon_geom),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+        );
+        let mut collision_system = CollisionSystem::new();
+        collision_system
+            .register(EntityKind::Baddie, EntityKind::Cannon)
+            .wrap_around(size);
+        let mut broad_phases: Vec<Box<dyn BroadPhase>> = BroadPhaseKind::ALL
+            .iter()
+            .map(|kind| kind.create())
+            .collect();
+        broad_phases.push(Box::new(SpatialGrid::new().with_world_size(size)));
+        broad_phases.push(Box::new(
+            SpatialGrid::with_bin_size(500).with_world_size(size),
+        ));
+
+        for broad_phase in broad_phases.iter_mut() {
+            // Act
+            let events = collision_system.process(broad_phase.as_mut(), &layers);
+
+            // Assert
+            let pairs: Vec<_> = events
+                .iter()
+                .map(|event| (event.left, event.right))
+                .collect();
+            assert_eq!(
+                pairs,
+                vec![
+                    (right.get_id(), left_cannon.get_id()),
+                    (bottom.get_id(), top_cannon.get_id()),
+                ]
+            );
+        }
     }
 }
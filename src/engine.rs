@@ -1,18 +1,67 @@
+use sdl2::controller::{Axis, Button, GameController};
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
 
 use std::time::{Duration, Instant};
 
-use crate::game_logic::{move_cannon, try_fire, update_world, LevelState};
-use crate::geometry::Direction;
-use crate::levels;
-use crate::render::Renderer;
+use bwb::game_logic::{self, LevelState};
+use bwb::geometry::{direction_vector, Direction, Vector};
+use bwb::levels::{LevelId, LevelSet, Seed};
+use bwb::simulation::{Input, LevelStats, Simulation};
+use bwb::timestep::FixedTimestep;
+use bwb::world::{self, PLAYER_HEALTH_MAX};
+
+use crate::render::{screen_to_world, Renderer};
 use crate::text;
-use crate::world;
 
 const MAX_FPS: u32 = 60; // Max FPS. Set this low to observe effects.
+const DEFAULT_TICK_RATE: u32 = 100; // Simulation ticks per second, independent of MAX_FPS.
+const STICK_DEAD_ZONE: i32 = 8000; // How far the aiming stick must be pushed (of 32767) to count
 
-type LevelId = i32;
+/// Game options, from the command line
+pub struct Options {
+    /// Simulation ticks per second
+    pub tick_rate: u32,
+    /// Path to save a replay of the most recently played level to
+    pub record: Option<String>,
+    /// Overrides the levels' seeds, if given
+    pub seed: Option<Seed>,
+}
+
+/// Parses command line arguments for the game: `[--tick-rate N] [--record PATH] [--seed N]`
+pub fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        tick_rate: DEFAULT_TICK_RATE,
+        record: None,
+        seed: None,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--tick-rate" => {
+                let value = args.next().ok_or("Missing value for --tick-rate")?;
+                options.tick_rate = match value.parse::<u32>() {
+                    Ok(rate) if rate > 0 && rate <= 1000 => rate,
+                    _ => return Err(format!("Invalid value for --tick-rate: '{}'", value)),
+                };
+            }
+            "--record" => {
+                let path = args.next().ok_or("Missing value for --record")?;
+                options.record = Some(path.clone());
+            }
+            "--seed" => {
+                let value = args.next().ok_or("Missing value for --seed")?;
+                let seed = value
+                    .parse::<Seed>()
+                    .map_err(|_| format!("Invalid value for --seed: '{}'", value))?;
+                options.seed = Some(seed);
+            }
+            other => return Err(format!("Unknown argument '{}'", other)),
+        }
+    }
+    Ok(options)
+}
 
 /// Wrapper of SDL event systems, which allows cleaner event handling.
 struct Events {
@@ -20,39 +69,143 @@ struct Events {
     // These can then be requeued using EventSubsystem.push_event, to be handled by the more appropriate handler later.
     event_pump: sdl2::EventPump,
     event_subsystem: sdl2::EventSubsystem,
+    /// Game controller, if one's connected, for aiming with its right stick. Events only arrive while it's open.
+    controller: Option<GameController>,
 }
 
 impl Events {
-    pub fn new(event_pump: sdl2::EventPump, event_subsystem: sdl2::EventSubsystem) -> Self {
+    pub fn new(
+        event_pump: sdl2::EventPump,
+        event_subsystem: sdl2::EventSubsystem,
+        controller: Option<GameController>,
+    ) -> Self {
         Events {
             event_pump,
             event_subsystem,
+            controller,
+        }
+    }
+
+    /// Direction the controller's right stick is pushed, if it's pushed past the dead zone
+    pub fn aim_stick(&self) -> Option<Vector> {
+        let controller = self.controller.as_ref()?;
+        let stick = (
+            controller.axis(Axis::RightX) as i32,
+            controller.axis(Axis::RightY) as i32,
+        );
+        if stick.0.abs().max(stick.1.abs()) < STICK_DEAD_ZONE {
+            return None;
         }
+        Some(stick)
     }
-    pub fn poll_iter(&mut self) -> sdl2::event::EventPollIterator {
+
+    pub fn poll_iter(&mut self) -> sdl2::event::EventPollIterator<'_> {
         self.event_pump.poll_iter()
     }
 
+    /// Takes all pending events, so a handler can go through every one, re-queuing any it doesn't handle
+    pub fn poll_all(&mut self) -> Vec<Event> {
+        self.event_pump.poll_iter().collect()
+    }
+
     pub fn push_event(&mut self, event: Event) -> Result<(), String> {
         self.event_subsystem.push_event(event)
     }
 }
 
+/// In-game settings, changed via the pause menu
+struct Settings {
+    /// Whether to show the level and seed while playing
+    show_hud: bool,
+}
+
+/// Pause menu entries, in display order
+#[derive(Clone, Copy, PartialEq)]
+enum PauseItem {
+    Resume,
+    Restart,
+    Options,
+    QuitToTitle,
+}
+
+const PAUSE_ITEMS: [PauseItem; 4] = [
+    PauseItem::Resume,
+    PauseItem::Restart,
+    PauseItem::Options,
+    PauseItem::QuitToTitle,
+];
+
+/// Options page entries, in display order
+#[derive(Clone, Copy, PartialEq)]
+enum OptionsItem {
+    Hud,
+    Back,
+}
+
+const OPTIONS_ITEMS: [OptionsItem; 2] = [OptionsItem::Hud, OptionsItem::Back];
+
+/// Pause menu state: which page is showing, and which entry is highlighted
+#[derive(Default)]
+struct PauseMenu {
+    selected: usize,
+    in_options: bool,
+}
+
+impl PauseMenu {
+    fn len(&self) -> usize {
+        if self.in_options {
+            OPTIONS_ITEMS.len()
+        } else {
+            PAUSE_ITEMS.len()
+        }
+    }
+
+    /// Moves the highlight up or down, wrapping around
+    fn navigate(&mut self, direction: Direction) {
+        let len = self.len();
+        self.selected = match direction {
+            Direction::Up => (self.selected + len - 1) % len,
+            _ => (self.selected + 1) % len,
+        };
+    }
+
+    /// Switches between the main page and the options page
+    fn show_options(&mut self, in_options: bool) {
+        self.in_options = in_options;
+        self.selected = 0;
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
 enum GameState {
     ShowingTitleScreen,
     StartingLevel(LevelId),
-    PlayingLevel(
-        world::World,
-        world::ObjectFactory,
-        Instant, /* last fire time */
-        LevelId,
-    ),
-    AdvancingLevel(LevelId),
-    GameOvering, // TODO: handling
-    Quitting,    // TODO: handling
+    PlayingLevel(Simulation),
+    Paused(Simulation, PauseMenu),
+    AdvancingLevel(LevelId, LevelStats),
+    GameOvering(LevelId),
+    Winning, // Completed the last level
+    Quitting,
+}
+
+/// Takes all pending presses of the given keys, in order.
+/// Any other event is re-queued for subsequent handlers.
+fn poll_keys(events: &mut Events, keys: &[Keycode]) -> Vec<Keycode> {
+    let mut pressed = vec![];
+    for event in events.poll_all() {
+        match event {
+            Event::KeyDown {
+                keycode: Some(keycode),
+                ..
+            } if keys.contains(&keycode) => pressed.push(keycode),
+            _ => events.push_event(event).unwrap(),
+        }
+    }
+    pressed
 }
 
 fn title_screen(renderer: &mut Renderer, events: &mut Events) -> GameState {
+    renderer.clear();
     renderer.draw_text_n(
         &vec![
             ("bwb", text::Size::Large),
@@ -62,19 +215,196 @@ fn title_screen(renderer: &mut Renderer, events: &mut Events) -> GameState {
         text::Position::CenterScreen,
     );
 
-    for event in events.poll_iter() {
+    let mut next_state = GameState::ShowingTitleScreen;
+    for event in events.poll_all() {
         match event {
             Event::KeyDown {
-                keycode: Some(_), ..
-            } => return GameState::StartingLevel(1),
+                keycode: Some(keycode),
+                ..
+            } if matches!(next_state, GameState::ShowingTitleScreen) => {
+                next_state = match keycode {
+                    Keycode::Escape => GameState::Quitting,
+                    _ => GameState::StartingLevel(1),
+                }
+            }
             _ => {
                 // re-queue event for subsequent handlers
                 events.push_event(event).unwrap();
-                break;
             }
         }
     }
-    GameState::ShowingTitleScreen
+    next_state
+}
+
+fn level_complete_screen(
+    renderer: &mut Renderer,
+    events: &mut Events,
+    levels: &LevelSet,
+    curr_level: LevelId,
+    stats: LevelStats,
+) -> GameState {
+    let title = format!("Level {} complete!", curr_level);
+    let time = format!("Time: {:.1}s", stats.time_ms as f32 / 1000.0);
+    let shots = format!("Shots fired: {}", stats.shots_fired);
+    let hits = format!("Hits: {}", stats.hits);
+    let health = format!(
+        "Health: {}/{}",
+        stats.health.unwrap_or(0),
+        PLAYER_HEALTH_MAX
+    );
+    renderer.clear();
+    renderer.draw_text_n(
+        &vec![
+            (title.as_str(), text::Size::Medium),
+            (time.as_str(), text::Size::Small),
+            (shots.as_str(), text::Size::Small),
+            (hits.as_str(), text::Size::Small),
+            (health.as_str(), text::Size::Small),
+            ("Press Enter to continue...", text::Size::Small),
+        ],
+        text::Position::CenterScreen,
+    );
+
+    let keys = [Keycode::Return, Keycode::Space, Keycode::Escape];
+    match poll_keys(events, &keys).first() {
+        Some(Keycode::Escape) => GameState::Quitting,
+        Some(_) => match levels.next_level(curr_level) {
+            Some(next_level) => GameState::StartingLevel(next_level),
+            None => GameState::Winning,
+        },
+        None => GameState::AdvancingLevel(curr_level, stats),
+    }
+}
+
+fn game_over_screen(
+    renderer: &mut Renderer,
+    events: &mut Events,
+    curr_level: LevelId,
+) -> GameState {
+    renderer.clear();
+    renderer.draw_text_n(
+        &vec![
+            ("Game Over", text::Size::Large),
+            ("R: Retry level", text::Size::Small),
+            ("T: Title screen", text::Size::Small),
+            ("Q: Quit", text::Size::Small),
+        ],
+        text::Position::CenterScreen,
+    );
+
+    let keys = [Keycode::R, Keycode::T, Keycode::Q, Keycode::Escape];
+    match poll_keys(events, &keys).first() {
+        Some(Keycode::R) => GameState::StartingLevel(curr_level),
+        Some(Keycode::T) => GameState::ShowingTitleScreen,
+        Some(_) => GameState::Quitting,
+        None => GameState::GameOvering(curr_level),
+    }
+}
+
+fn winning_screen(renderer: &mut Renderer, events: &mut Events) -> GameState {
+    renderer.clear();
+    renderer.draw_text_n(
+        &vec![
+            ("You win!", text::Size::Large),
+            ("All levels complete", text::Size::Medium),
+            (
+                "Press Enter to return to the title screen...",
+                text::Size::Small,
+            ),
+        ],
+        text::Position::CenterScreen,
+    );
+
+    let keys = [Keycode::Return, Keycode::Space, Keycode::Escape];
+    match poll_keys(events, &keys).first() {
+        Some(Keycode::Escape) => GameState::Quitting,
+        Some(_) => GameState::ShowingTitleScreen,
+        None => GameState::Winning,
+    }
+}
+
+fn pause_screen(
+    renderer: &mut Renderer,
+    events: &mut Events,
+    mut sim: Simulation,
+    mut menu: PauseMenu,
+    settings: &mut Settings,
+    record_path: &Option<String>,
+) -> GameState {
+    // The world isn't updated while paused, so draw it as of the last tick
+    let world = sim.world();
+    renderer.render(&world.entities, &world.geometries, &world.healths);
+    renderer.dim();
+
+    let labels: Vec<String> = if menu.in_options {
+        OPTIONS_ITEMS
+            .iter()
+            .map(|item| match item {
+                OptionsItem::Hud => {
+                    format!("HUD: {}", if settings.show_hud { "On" } else { "Off" })
+                }
+                OptionsItem::Back => "Back".to_string(),
+            })
+            .collect()
+    } else {
+        PAUSE_ITEMS
+            .iter()
+            .map(|item| match item {
+                PauseItem::Resume => "Resume",
+                PauseItem::Restart => "Restart level",
+                PauseItem::Options => "Options",
+                PauseItem::QuitToTitle => "Quit to title",
+            })
+            .map(String::from)
+            .collect()
+    };
+    let labels: Vec<String> = labels
+        .into_iter()
+        .enumerate()
+        .map(|(i, label)| {
+            if i == menu.selected {
+                format!("> {} <", label)
+            } else {
+                label
+            }
+        })
+        .collect();
+    let title = if menu.in_options { "Options" } else { "Paused" };
+    let mut lines = vec![(title, text::Size::Large)];
+    lines.extend(
+        labels
+            .iter()
+            .map(|label| (label.as_str(), text::Size::Small)),
+    );
+    renderer.draw_text_n(&lines, text::Position::CenterScreen);
+
+    // Every press since the last frame, in order, until one leaves the menu
+    let keys = [Keycode::Up, Keycode::Down, Keycode::Return, Keycode::Escape];
+    for key in poll_keys(events, &keys) {
+        match key {
+            Keycode::Up => menu.navigate(Direction::Up),
+            Keycode::Down => menu.navigate(Direction::Down),
+            Keycode::Escape if menu.in_options => menu.show_options(false),
+            Keycode::Escape => return GameState::PlayingLevel(sim),
+            _ if menu.in_options => match OPTIONS_ITEMS[menu.selected] {
+                OptionsItem::Hud => settings.show_hud = !settings.show_hud,
+                OptionsItem::Back => menu.show_options(false),
+            },
+            _ => match PAUSE_ITEMS[menu.selected] {
+                PauseItem::Resume => return GameState::PlayingLevel(sim),
+                PauseItem::Restart => {
+                    save_recording(&mut sim, record_path);
+                    return GameState::StartingLevel(sim.level());
+                }
+                PauseItem::Options => menu.show_options(true),
+                PauseItem::QuitToTitle => {
+                    save_recording(&mut sim, record_path);
+                    return GameState::ShowingTitleScreen;
+                }
+            },
+        }
+    }
+    GameState::Paused(sim, menu)
 }
 
 fn print_framerate(frame_time: i32) {
@@ -82,115 +412,204 @@ fn print_framerate(frame_time: i32) {
     println!("{}", frame_rate);
 }
 
-fn init_level(curr_level: i32) -> GameState {
-    let (world, obj_factory) = levels::init(curr_level);
-    GameState::PlayingLevel(
-        world,
-        obj_factory,
-        Instant::now() - Duration::from_secs(1),
-        curr_level,
-    )
+fn init_level(levels: &LevelSet, curr_level: i32, dt: i32, options: &Options) -> GameState {
+    let mut sim = Simulation::new(levels, curr_level, options.seed, dt);
+    if options.record.is_some() {
+        sim.record();
+    }
+    GameState::PlayingLevel(sim)
+}
+
+/// Saves the level's replay, if recording
+fn save_recording(sim: &mut Simulation, path: &Option<String>) {
+    if let (Some(replay), Some(path)) = (sim.take_recording(), path) {
+        match replay.save(path) {
+            Ok(()) => println!("Saved replay of level {} to {}", replay.level, path),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+/// Vector from the cannon to the given screen position (e.g. of the mouse). Zero if there's no cannon.
+fn aim_at(sim: &Simulation, screen_pos: (i32, i32)) -> Vector {
+    let world = sim.world();
+    let cannon = match world.cannon() {
+        Some(cannon) => cannon.get_id(),
+        None => return (0, 0),
+    };
+    let (cx, cy) = *world.shapes.get(&cannon).unwrap().get_center();
+    let (x, y) = screen_to_world(&screen_pos);
+    (x - cx, y - cy)
 }
 
 fn play_level(
     renderer: &mut Renderer,
     events: &mut Events,
-    frame_time: i32,
-    current_time: Instant,
-    mut world: world::World,
-    obj_factory: world::ObjectFactory,
-    mut prev_fire_time: Instant,
-    curr_level: i32,
+    timestep: &FixedTimestep,
+    ticks: u32,
+    mut sim: Simulation,
+    settings: &Settings,
+    record_path: &Option<String>,
 ) -> GameState {
-    let (world_temp, level_state) = update_world(world, frame_time);
-    world = world_temp;
-
-    match level_state {
-        LevelState::Complete => return GameState::AdvancingLevel(curr_level),
-        LevelState::GameOver => return GameState::GameOvering,
-        _ => false,
-    };
+    for _ in 0..ticks {
+        match sim.step() {
+            LevelState::Complete => {
+                save_recording(&mut sim, record_path);
+                return GameState::AdvancingLevel(sim.level(), sim.stats());
+            }
+            LevelState::GameOver => {
+                save_recording(&mut sim, record_path);
+                return GameState::GameOvering(sim.level());
+            }
+            LevelState::InProgress => (),
+        };
+    }
 
-    renderer.render(&world.0, &world.2, &world.3);
+    // Render in-between the last two ticks, for smooth movement regardless of tick rate
+    let world = sim.world();
+    let geometries = world::interpolate_geometries(&world.shapes, timestep.alpha());
+    renderer.render(&world.entities, &geometries, &world.healths);
+    if settings.show_hud {
+        let hud = format!("Level {}  Seed {}", sim.level(), sim.seed());
+        renderer.draw_text_n(
+            &vec![(hud.as_str(), text::Size::Small)],
+            text::Position::TopRight,
+        );
+    }
+    if renderer.debug_overlay() {
+        let debug = game_logic::collision_system()
+            .debug_info(world.broad_phase.as_ref(), &world.collisions);
+        renderer.render_collision_debug(&debug, &geometries, &world.collisions);
+    }
 
-    for event in events.poll_iter() {
-        match event {
+    // Current position, i.e. after any stick movements still to be handled
+    let aim_stick = events.aim_stick();
+    let mut paused = false;
+    for event in events.poll_all() {
+        let input = match event {
+            // Leave the rest for subsequent handlers, as input shouldn't reach a paused game
+            _ if paused => {
+                events.push_event(event).unwrap();
+                continue;
+            }
             Event::KeyDown {
                 keycode: Some(Keycode::Left),
                 ..
-            } => {
-                prev_fire_time = try_fire(
-                    current_time,
-                    prev_fire_time,
-                    &mut world,
-                    Direction::Left,
-                    &obj_factory,
-                )
-            }
+            } => Input::Fire(direction_vector(Direction::Left)),
             Event::KeyDown {
                 keycode: Some(Keycode::Right),
                 ..
-            } => {
-                prev_fire_time = try_fire(
-                    current_time,
-                    prev_fire_time,
-                    &mut world,
-                    Direction::Right,
-                    &obj_factory,
-                )
+            } => Input::Fire(direction_vector(Direction::Right)),
+            Event::MouseMotion { x, y, .. } => Input::Aim(aim_at(&sim, (x, y))),
+            Event::MouseButtonDown {
+                mouse_btn: MouseButton::Left,
+                x,
+                y,
+                ..
+            } => Input::Fire(aim_at(&sim, (x, y))),
+            Event::ControllerAxisMotion {
+                axis: Axis::RightX, ..
             }
+            | Event::ControllerAxisMotion {
+                axis: Axis::RightY, ..
+            } => match aim_stick {
+                Some(stick) => Input::Aim(stick),
+                None => continue,
+            },
+            Event::ControllerButtonDown {
+                button: Button::RightShoulder,
+                ..
+            } => match aim_stick {
+                Some(stick) => Input::Fire(stick),
+                None => continue,
+            },
             Event::KeyDown {
                 keycode: Some(Keycode::Up),
                 ..
-            } => move_cannon(&mut world, Direction::Up),
+            } => Input::Move(Direction::Up),
             Event::KeyDown {
                 keycode: Some(Keycode::Down),
                 ..
-            } => move_cannon(&mut world, Direction::Down),
+            } => Input::Move(Direction::Down),
+            Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            } => {
+                paused = true;
+                continue;
+            }
             _ => {
                 // re-queue event for subsequent handlers
                 events.push_event(event).unwrap();
-                break;
+                continue;
             }
-        }
+        };
+        sim.apply(input);
     }
 
-    GameState::PlayingLevel(world, obj_factory, prev_fire_time, curr_level)
+    if paused {
+        GameState::Paused(sim, PauseMenu::default())
+    } else {
+        GameState::PlayingLevel(sim)
+    }
 }
 
-pub fn run() {
+pub fn run(levels: LevelSet, options: Options) {
     let sdl_context = sdl2::init().unwrap();
     let ttf_context = sdl2::ttf::init().unwrap();
     let mut renderer = Renderer::new(&sdl_context, text::load_font(&ttf_context));
 
+    // The first game controller, if any
+    let controller_subsystem = sdl_context.game_controller().unwrap();
+    let controller = (0..controller_subsystem.num_joysticks().unwrap_or(0))
+        .find(|index| controller_subsystem.is_game_controller(*index))
+        .and_then(|index| controller_subsystem.open(index).ok());
     let mut events = Events::new(
         sdl_context.event_pump().unwrap(),
         sdl_context.event().unwrap(),
+        controller,
     );
 
     let mut game_state = GameState::ShowingTitleScreen;
+    let mut settings = Settings { show_hud: true };
+    let mut timestep = FixedTimestep::new(options.tick_rate);
     let mut current_time = Instant::now();
 
     'running: loop {
         let new_time = Instant::now();
         let frame_time = (new_time - current_time).as_millis() as i32;
+        let ticks = timestep.advance(new_time - current_time);
         current_time = new_time;
 
         game_state = match game_state {
             GameState::ShowingTitleScreen => title_screen(&mut renderer, &mut events),
-            GameState::StartingLevel(curr_level) => init_level(curr_level),
-            GameState::PlayingLevel(world, obj_factory, prev_fire_time, curr_level) => play_level(
+            GameState::StartingLevel(curr_level) => {
+                init_level(&levels, curr_level, timestep.dt_ms(), &options)
+            }
+            GameState::PlayingLevel(sim) => play_level(
+                &mut renderer,
+                &mut events,
+                &timestep,
+                ticks,
+                sim,
+                &settings,
+                &options.record,
+            ),
+            GameState::Paused(sim, menu) => pause_screen(
                 &mut renderer,
                 &mut events,
-                frame_time,
-                current_time,
-                world,
-                obj_factory,
-                prev_fire_time,
-                curr_level,
+                sim,
+                menu,
+                &mut settings,
+                &options.record,
             ),
-            GameState::AdvancingLevel(curr_level) => GameState::StartingLevel(curr_level + 1), // TODO: level complete screen; last level?
-            GameState::GameOvering => GameState::Quitting, // TODO: game over screen
+            GameState::AdvancingLevel(curr_level, stats) => {
+                level_complete_screen(&mut renderer, &mut events, &levels, curr_level, stats)
+            }
+            GameState::GameOvering(curr_level) => {
+                game_over_screen(&mut renderer, &mut events, curr_level)
+            }
+            GameState::Winning => winning_screen(&mut renderer, &mut events),
             GameState::Quitting => break 'running,
         };
 
@@ -200,11 +619,19 @@ pub fn run() {
                     keycode: Some(Keycode::F),
                     ..
                 } => print_framerate(frame_time),
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
+                Event::KeyDown {
+                    keycode: Some(Keycode::D),
                     ..
-                } => break 'running,
+                } => renderer.toggle_debug_overlay(),
+                // Escape is left to the current state: it pauses in-game, and quits from the other screens
+                Event::Quit { .. } => {
+                    if let GameState::PlayingLevel(sim) | GameState::Paused(sim, _) =
+                        &mut game_state
+                    {
+                        save_recording(sim, &options.record);
+                    }
+                    break 'running;
+                }
                 _ => {}
             }
         }
@@ -1,29 +1,110 @@
+//! Entities - identity and kind of the objects in the world
+
 use std::hash::{Hash, Hasher};
 
-static mut ID_COUNTER: u32 = 0;
+/// Unique identifier of an entity: a slot index, plus the generation of that slot.
+/// Slots are reused once freed, with the generation bumped, so stale IDs never match a newer entity.
+#[derive(Hash, Eq, PartialEq, PartialOrd, Ord, Copy, Clone, Debug)]
+pub struct EntityId {
+    index: u32,
+    generation: u32,
+}
 
-fn generate_id() -> EntityId {
-    #![allow(unused)] // due to unsafe
-    let mut id = 0;
-    // Not thread safe TODO: consider a better way to do this e.g. inject, or use a mutex
-    unsafe {
-        ID_COUNTER += 1;
-        id = ID_COUNTER;
+impl EntityId {
+    /// Slot index
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Number of times the slot had been reused when the ID was allocated
+    pub fn generation(&self) -> u32 {
+        self.generation
     }
-    EntityId(id)
 }
 
-#[derive(Hash, Eq, PartialEq, Copy, Clone, Debug)]
-pub struct EntityId(u32);
+/// Allocates entity IDs for a world.
+/// IDs only depend on the sequence of allocations and frees, so are reproducible for a given world.
+#[derive(Default)]
+pub struct EntityAllocator {
+    /// Current generation of each slot
+    generations: Vec<u32>,
+    /// Whether each slot is in use
+    alive: Vec<bool>,
+    /// Freed slots, to be reused
+    free_slots: Vec<u32>,
+}
+
+impl EntityAllocator {
+    /// Creates an allocator with no IDs allocated
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allocates an ID, reusing a freed slot if there is one
+    pub fn allocate(&mut self) -> EntityId {
+        match self.free_slots.pop() {
+            Some(index) => {
+                let i = index as usize;
+                self.generations[i] += 1;
+                self.alive[i] = true;
+                EntityId {
+                    index,
+                    generation: self.generations[i],
+                }
+            }
+            None => {
+                self.generations.push(0);
+                self.alive.push(true);
+                EntityId {
+                    index: self.generations.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    /// Frees the ID's slot for reuse. Returns false if the ID was stale, i.e. already freed.
+    pub fn free(&mut self, id: EntityId) -> bool {
+        if !self.is_alive(id) {
+            return false;
+        }
+        self.alive[id.index as usize] = false;
+        self.free_slots.push(id.index);
+        true
+    }
+
+    /// Whether the ID refers to a live entity, rather than a freed (stale) one
+    pub fn is_alive(&self, id: EntityId) -> bool {
+        let i = id.index as usize;
+        i < self.alive.len() && self.alive[i] && self.generations[i] == id.generation
+    }
+
+    /// Number of live entities
+    pub fn len(&self) -> usize {
+        self.alive.len() - self.free_slots.len()
+    }
+
+    /// Whether there are no live entities
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
 
-#[derive(PartialEq, Clone, Copy, Hash, Eq)]
+/// The kind of an entity, which determines its behaviour
+#[derive(PartialEq, Clone, Copy, Hash, Eq, Debug)]
 pub enum EntityKind {
+    /// Enemy. Bounces off walls, damages the player.
     Baddie,
+    /// Static obstacle
     Wall,
+    /// Fired by the cannon, destroys baddies
     Bullet,
+    /// The player
     Cannon,
 
     // For proxies. Consider using Option if it becomes more widely used.
+    /// Placeholder kind, for proxy entities (see [`Entity::from_id`])
+    #[allow(clippy::upper_case_acronyms)]
     UNDEFINED,
 }
 
@@ -50,18 +131,19 @@ impl PartialEq for Entity {
 impl Eq for Entity {}
 
 impl Entity {
-    /// Creates a new entity.
-    pub fn new(kind: EntityKind) -> Self {
+    /// Creates a new entity, with an ID from the given allocator.
+    pub fn new(allocator: &mut EntityAllocator, kind: EntityKind) -> Self {
         Self {
-            id: generate_id(),
-            kind
+            id: allocator.allocate(),
+            kind,
         }
     }
 
     /// Creates a dummy entity that can be used as a proxy for others, currently just for hashing purposes
     pub fn from_id(id: EntityId) -> Self {
         Self {
-            id, kind: EntityKind::UNDEFINED
+            id,
+            kind: EntityKind::UNDEFINED,
         }
     }
 
@@ -70,7 +152,60 @@ impl Entity {
         self.id
     }
 
+    /// Returns the entity's kind.
     pub fn get_kind(&self) -> &EntityKind {
         &self.kind
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocate_is_sequential_per_allocator() {
+        let mut allocator1 = EntityAllocator::new();
+        let mut allocator2 = EntityAllocator::new();
+
+        let ids1: Vec<EntityId> = (0..3).map(|_| allocator1.allocate()).collect();
+        let ids2: Vec<EntityId> = (0..3).map(|_| allocator2.allocate()).collect();
+
+        assert_eq!(ids1, ids2);
+        assert_eq!(
+            ids1.iter().map(|id| id.index()).collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn freed_slot_reused_with_new_generation() {
+        // Arrange
+        let mut allocator = EntityAllocator::new();
+        let id1 = allocator.allocate();
+        let id2 = allocator.allocate();
+
+        // Act
+        assert!(allocator.free(id1));
+        let id3 = allocator.allocate();
+
+        // Assert - slot reused, but the old ID is stale
+        assert_eq!(id3.index(), id1.index());
+        assert_eq!(id3.generation(), id1.generation() + 1);
+        assert_ne!(id3, id1);
+        assert!(!allocator.is_alive(id1));
+        assert!(allocator.is_alive(id2));
+        assert!(allocator.is_alive(id3));
+        assert_eq!(allocator.len(), 2);
+    }
+
+    #[test]
+    fn free_stale_id_rejected() {
+        let mut allocator = EntityAllocator::new();
+        let id = allocator.allocate();
+        allocator.free(id);
+        allocator.allocate();
+
+        assert!(!allocator.free(id));
+        assert_eq!(allocator.len(), 1);
+    }
+}
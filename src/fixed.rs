@@ -0,0 +1,390 @@
+//! Fixed-point numbers, for deterministic physics.
+//!
+//! Floating point results can differ between CPUs, compilers and maths libraries - `sin` and `cos` especially,
+//! and whether multiplies and adds get fused - so the same simulation on two machines can drift apart.
+//! With the `fixed-point` cargo feature, the physics (rotation, bouncing, and contacts from the separating axis test)
+//! uses [`Fixed`] rather than `f32`. That's integer arithmetic only, so results are bit-identical everywhere,
+//! e.g. for lockstep replays and networked play. Velocity integration is already integer (see `Shape::calc_step`).
+//!
+//! Code shared by both modes uses [`Real`], the physics number type for the build, through the [`Scalar`] trait.
+//! World hashes (see `replay`) include rotations, so replays record their mode, and only load in a build with it.
+
+use std::fmt;
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+
+/// Number type used by the physics - `f32`, or [`Fixed`] with the `fixed-point` feature
+#[cfg(not(feature = "fixed-point"))]
+pub type Real = f32;
+
+/// Number type used by the physics - `f32`, or [`Fixed`] with the `fixed-point` feature
+#[cfg(feature = "fixed-point")]
+pub type Real = Fixed;
+
+/// Operations the physics needs from its number type, so that it can be written once for `f32` and [`Fixed`]
+pub trait Scalar:
+    Copy
+    + PartialOrd
+    + fmt::Debug
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+{
+    /// 2π, i.e. a full turn in radians
+    const TAU: Self;
+
+    /// Converts from an integer (exactly, within range)
+    fn from_i32(value: i32) -> Self;
+    /// Converts from a float, to the nearest representable value
+    fn from_f32(value: f32) -> Self;
+    /// Converts to a float, e.g. for rendering
+    fn to_f32(self) -> f32;
+    /// Converts to an integer, rounding towards zero (like `as i32`)
+    fn to_i32(self) -> i32;
+    /// Rounds to the nearest integer, half way cases away from zero
+    fn round(self) -> Self;
+    /// Rounds up to an integer
+    fn ceil(self) -> Self;
+    /// Absolute value
+    fn abs(self) -> Self;
+    /// The lesser of the two
+    fn min(self, other: Self) -> Self;
+    /// The greater of the two
+    fn max(self, other: Self) -> Self;
+    /// Square root
+    fn sqrt(self) -> Self;
+    /// Sine and cosine, of an angle in radians
+    fn sin_cos(self) -> (Self, Self);
+    /// Angle of the vector (`x`, `self`) from the x axis, in radians in [-π, π] (like `f32::atan2`)
+    fn atan2(self, x: Self) -> Self;
+}
+
+impl Scalar for f32 {
+    const TAU: Self = std::f32::consts::TAU;
+
+    fn from_i32(value: i32) -> Self {
+        value as f32
+    }
+
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn to_i32(self) -> i32 {
+        self as i32
+    }
+
+    fn round(self) -> Self {
+        f32::round(self)
+    }
+
+    fn ceil(self) -> Self {
+        f32::ceil(self)
+    }
+
+    fn abs(self) -> Self {
+        f32::abs(self)
+    }
+
+    fn min(self, other: Self) -> Self {
+        f32::min(self, other)
+    }
+
+    fn max(self, other: Self) -> Self {
+        f32::max(self, other)
+    }
+
+    fn sqrt(self) -> Self {
+        f32::sqrt(self)
+    }
+
+    fn sin_cos(self) -> (Self, Self) {
+        (self.sin(), self.cos())
+    }
+
+    fn atan2(self, x: Self) -> Self {
+        f32::atan2(self, x)
+    }
+}
+
+/// Number of fractional bits
+const FRAC_BITS: u32 = 16;
+const ONE_RAW: i64 = 1 << FRAC_BITS;
+/// π/2, with 32 fractional bits, for reducing angles precisely
+const FRAC_PI_2_Q32: i128 = 6_746_518_852;
+const FRAC_PI_2: Fixed = Fixed(102_944);
+const FRAC_PI_6: Fixed = Fixed(34_315);
+const TAN_FRAC_PI_12: Fixed = Fixed(17_560);
+const SQRT_3: Fixed = Fixed(113_512);
+
+/// Signed fixed-point number, with 16 fractional bits (a resolution of ~0.000015) and 47 integer bits.
+/// Intermediate products are calculated at double width, so they don't overflow.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Hash)]
+pub struct Fixed(i64);
+
+impl Fixed {
+    /// Zero
+    pub const ZERO: Fixed = Fixed(0);
+    /// One
+    pub const ONE: Fixed = Fixed(ONE_RAW);
+    /// π, to the nearest representable value
+    pub const PI: Fixed = Fixed(205_887);
+
+    /// Creates from the raw representation, i.e. the value multiplied by 2^16
+    pub const fn from_bits(bits: i64) -> Self {
+        Fixed(bits)
+    }
+
+    /// Returns the raw representation (see [`Fixed::from_bits`]), e.g. for hashing
+    pub const fn to_bits(self) -> i64 {
+        self.0
+    }
+
+    /// Sine and cosine of an angle in [0, π/2], from their Taylor series
+    fn sin_cos_quadrant(self) -> (Fixed, Fixed) {
+        let x2 = self * self;
+        // Nested, i.e. 1 - x²/(2·3) (1 - x²/(4·5) (...)), to the term after the last that's significant at π/2
+        let series = |terms: &[i32]| {
+            terms.iter().rev().fold(Fixed::ONE, |acc, divisor| {
+                Fixed::ONE - x2 * acc / Fixed::from_i32(*divisor)
+            })
+        };
+        let sin = self * series(&[6, 20, 42, 72, 110, 156]);
+        let cos = series(&[2, 12, 30, 56, 90, 132]);
+        (sin, cos)
+    }
+
+    /// Arctangent of a value in [0, 1], from its Taylor series
+    fn atan_unit(self) -> Fixed {
+        // Reduced to within tan(π/12) of 0, where the series converges quickly, as atan(z) = π/6 + atan((√3z - 1) / (√3 + z))
+        let (offset, z) = if self > TAN_FRAC_PI_12 {
+            (FRAC_PI_6, (SQRT_3 * self - Fixed::ONE) / (SQRT_3 + self))
+        } else {
+            (Fixed::ZERO, self)
+        };
+        let z2 = z * z;
+        // Nested, i.e. z (1 - z² (1/3 - z² (1/5 - ...)))
+        let series = [1, 3, 5, 7, 9, 11]
+            .iter()
+            .rev()
+            .fold(Fixed::ZERO, |acc, divisor| {
+                Fixed::ONE / Fixed::from_i32(*divisor) - z2 * acc
+            });
+        offset + z * series
+    }
+}
+
+/// Integer square root, rounded down (Newton's method)
+fn isqrt(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+    // Start from a power of 2 that's at least the root, so it converges from above
+    let mut x = 1u128 << (128 - n.leading_zeros()).div_ceil(2);
+    loop {
+        let next = (x + n / x) / 2;
+        if next >= x {
+            return x;
+        }
+        x = next;
+    }
+}
+
+impl Scalar for Fixed {
+    const TAU: Self = Fixed(411_775);
+
+    fn from_i32(value: i32) -> Self {
+        Fixed(value as i64 * ONE_RAW)
+    }
+
+    fn from_f32(value: f32) -> Self {
+        Fixed((value as f64 * ONE_RAW as f64).round() as i64)
+    }
+
+    fn to_f32(self) -> f32 {
+        (self.0 as f64 / ONE_RAW as f64) as f32
+    }
+
+    fn to_i32(self) -> i32 {
+        (self.0 / ONE_RAW) as i32
+    }
+
+    fn round(self) -> Self {
+        let half = ONE_RAW / 2;
+        let rounded = (self.0.abs() + half) / ONE_RAW * ONE_RAW;
+        Fixed(rounded * self.0.signum())
+    }
+
+    fn ceil(self) -> Self {
+        Fixed((self.0 + ONE_RAW - 1).div_euclid(ONE_RAW) * ONE_RAW)
+    }
+
+    fn abs(self) -> Self {
+        Fixed(self.0.abs())
+    }
+
+    fn min(self, other: Self) -> Self {
+        Fixed(self.0.min(other.0))
+    }
+
+    fn max(self, other: Self) -> Self {
+        Fixed(self.0.max(other.0))
+    }
+
+    /// Square root, rounded down. Negative numbers have no square root, so give 0.
+    fn sqrt(self) -> Self {
+        if self.0 <= 0 {
+            return Fixed::ZERO;
+        }
+        Fixed(isqrt((self.0 as u128) << FRAC_BITS) as i64)
+    }
+
+    fn sin_cos(self) -> (Self, Self) {
+        // Reduce to an angle within a quarter turn, and which quarter it's in
+        let angle = (self.0 as i128) << (32 - FRAC_BITS);
+        let quadrant = angle.div_euclid(FRAC_PI_2_Q32).rem_euclid(4);
+        let reduced = Fixed((angle.rem_euclid(FRAC_PI_2_Q32) >> (32 - FRAC_BITS)) as i64);
+        let (sin, cos) = reduced.sin_cos_quadrant();
+        match quadrant {
+            0 => (sin, cos),
+            1 => (cos, -sin),
+            2 => (-sin, -cos),
+            _ => (-cos, sin),
+        }
+    }
+
+    fn atan2(self, x: Self) -> Self {
+        // Angle of (|x|, |y|), from whichever of its tangent or cotangent is at most 1
+        let (x_size, y_size) = (x.abs(), self.abs());
+        let angle = if y_size.0 == 0 {
+            Fixed::ZERO
+        } else if y_size <= x_size {
+            (y_size / x_size).atan_unit()
+        } else {
+            FRAC_PI_2 - (x_size / y_size).atan_unit()
+        };
+        let angle = if x.0 < 0 { Fixed::PI - angle } else { angle };
+        if self.0 < 0 {
+            -angle
+        } else {
+            angle
+        }
+    }
+}
+
+impl Add for Fixed {
+    type Output = Fixed;
+
+    fn add(self, other: Fixed) -> Fixed {
+        Fixed(self.0 + other.0)
+    }
+}
+
+impl AddAssign for Fixed {
+    fn add_assign(&mut self, other: Fixed) {
+        self.0 += other.0;
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Fixed;
+
+    fn sub(self, other: Fixed) -> Fixed {
+        Fixed(self.0 - other.0)
+    }
+}
+
+impl SubAssign for Fixed {
+    fn sub_assign(&mut self, other: Fixed) {
+        self.0 -= other.0;
+    }
+}
+
+impl Mul for Fixed {
+    type Output = Fixed;
+
+    fn mul(self, other: Fixed) -> Fixed {
+        Fixed(((self.0 as i128 * other.0 as i128) >> FRAC_BITS) as i64)
+    }
+}
+
+impl Div for Fixed {
+    type Output = Fixed;
+
+    /// Rounds towards zero. Panics on division by zero.
+    fn div(self, other: Fixed) -> Fixed {
+        Fixed((((self.0 as i128) << FRAC_BITS) / other.0 as i128) as i64)
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Fixed;
+
+    fn neg(self) -> Fixed {
+        Fixed(-self.0)
+    }
+}
+
+impl fmt::Debug for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_f32())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_arithmetic() {
+        let (a, b) = (Fixed::from_f32(2.5), Fixed::from_i32(-4));
+
+        assert_eq!((a + b).to_f32(), -1.5);
+        assert_eq!((a * b).to_i32(), -10);
+        assert_eq!((a / b).to_f32(), -0.625);
+        assert_eq!((-a).round(), Fixed::from_i32(-3));
+        assert_eq!(Fixed::from_f32(-1.5).ceil(), Fixed::from_i32(-1));
+        assert_eq!(Fixed::from_f32(-1.5).to_i32(), -1);
+        assert_eq!(Fixed::from_i32(10000).sqrt(), Fixed::from_i32(100));
+        assert_eq!(Fixed::from_i32(-1).sqrt(), Fixed::ZERO);
+        assert!((Fixed::from_i32(2).sqrt().to_f32() - 2f32.sqrt()).abs() < 0.0001);
+    }
+
+    #[test]
+    fn fixed_sin_cos_matches_float() {
+        // Including big angles, as rotations accumulate without wrapping
+        for i in -400i32..400 {
+            let angle = i as f32 * 0.1 + if i.abs() > 300 { 10000.0 } else { 0.0 };
+            let (sin, cos) = Fixed::from_f32(angle).sin_cos();
+
+            assert!((sin.to_f32() - angle.sin()).abs() < 0.0005, "sin {}", angle);
+            assert!((cos.to_f32() - angle.cos()).abs() < 0.0005, "cos {}", angle);
+        }
+        assert_eq!(Fixed::ZERO.sin_cos(), (Fixed::ZERO, Fixed::ONE));
+    }
+
+    #[test]
+    fn fixed_atan2_matches_float() {
+        // All the way round, at various lengths, including the axes
+        for i in 0..360 {
+            let angle = (i as f32).to_radians() - std::f32::consts::PI;
+            for length in [0.5, 1.0, 7.0, 5000.0] {
+                let (y, x) = (angle.sin() * length, angle.cos() * length);
+                let expected = y.atan2(x);
+                let actual = Fixed::from_f32(y).atan2(Fixed::from_f32(x)).to_f32();
+
+                // -π and π are the same angle
+                let error = (actual - expected).abs();
+                let error = error.min((error - 2.0 * std::f32::consts::PI).abs());
+                assert!(error < 0.0005, "atan2({}, {}) = {}", y, x, actual);
+            }
+        }
+        assert_eq!(Fixed::ZERO.atan2(Fixed::ZERO), Fixed::ZERO);
+        assert_eq!(Fixed::ONE.atan2(Fixed::ZERO), FRAC_PI_2);
+    }
+}
@@ -6,62 +6,86 @@
 //! * All enemies destroyed => level ends
 //! * Enemy meets player => Player health decreases + enemy destroyed
 //! * Player health decreases to 0 => Game Over
-
+//!
 //! Other rules:
 //! * Bullets are destroyed when they reach edge of screen
-//! * Enemies wrap to the other side of the screen
+//! * Enemies wrap to the other side of the screen. The world wraps around for collisions too:
+//!   anything hanging over an edge meets what's at the opposite edge.
 //! * Player health reset at start of level
 
-use crate::collision_system::CollisionSystem;
+use crate::collision_system::{CollisionKind, CollisionSystem};
 use crate::entity::{EntityId, EntityKind};
-use crate::geometry::{direction_vector, Direction, P};
+use crate::fixed::{Real, Scalar};
+use crate::geometry::{normalize, Direction, Geometry, Vector, P};
 use crate::shape::Shape;
 use crate::world;
 use crate::world::{
-    update_geometry, Entities, GameObjects, Geometries, Healths, Shapes, World, GRID_HEIGHT,
-    GRID_WIDTH,
+    update_geometry, Entities, Geometries, Shapes, Systems, World, GRID_HEIGHT, GRID_WIDTH,
 };
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::f32::consts::FRAC_PI_4;
 use std::time::{Duration, Instant};
 
-fn get_cannon_pos(game_objects: &GameObjects) -> &P {
-    let cannon_id = world::get_cannon(game_objects).unwrap().get_id();
-    let (_, shapes, _, _) = game_objects;
-    shapes.get(&cannon_id).unwrap().get_center()
+/// Minimum time between shots (1 / rate of fire)
+pub const RELOAD_TIME: Duration = Duration::from_millis(1000);
+
+fn get_cannon_pos(world: &World) -> &P {
+    let cannon_id = world.cannon().unwrap().get_id();
+    world.shapes.get(&cannon_id).unwrap().get_center()
 }
 
 // (ACTION)
-/// Try fire the cannon, throttled to the rate of fire.
+/// Try fire the cannon along `aim` (any non-zero vector, e.g. from the cannon to the mouse), throttled to the rate of fire.
+/// The cannon turns to face that way, whether or not it's reloaded, and bullets leave from its muzzle.
 /// Returns the instant of when the cannon was previously fired successfully.  
-/// Note: Rate of fire is hardcoded within.  
+/// Note: Rate of fire is set by [`RELOAD_TIME`].  
 pub fn try_fire(
     now: Instant,
     prev: Instant,
-    game_objects: &mut GameObjects,
-    direction: Direction,
+    world: &mut World,
+    aim: Vector,
     obj_factory: &world::ObjectFactory,
 ) -> Instant {
-    // 1 / rate of fire
-    const RELOAD_TIME: Duration = Duration::from_millis(1000);
-
-    let cannon_pos = *get_cannon_pos(game_objects);
+    if aim == (0, 0) {
+        return prev;
+    }
+    aim_cannon(world, aim);
 
     if now > prev + RELOAD_TIME {
         // Fire!!
-        world::add(
-            game_objects,
-            obj_factory.make_bullet(cannon_pos, direction_vector(direction)),
-        );
+        let (cx, cy) = *get_cannon_pos(world);
+        let cannon_id = world.cannon().unwrap().get_id();
+        let diameter_sqr = world.geometries.get(&cannon_id).unwrap().diameter_sqr();
+        let muzzle_distance = (Real::from_i32(diameter_sqr).sqrt() / Real::from_i32(2)).to_i32();
+        let (dx, dy) = normalize(aim, muzzle_distance);
+        world.add(obj_factory.make_bullet((cx + dx, cy + dy), aim));
         return now;
     }
-    return prev;
+    prev
+}
+
+// (ACTION)
+/// Turns the cannon to face along `aim` (any vector). The zero vector leaves it facing the way it was.
+pub fn aim_cannon(world: &mut World, aim: Vector) {
+    if aim == (0, 0) {
+        return;
+    }
+    let cannon_id = world.cannon().unwrap().get_id();
+    let angle = Real::from_i32(aim.1).atan2(Real::from_i32(aim.0));
+    // The cannon's a square, turned so that a corner (the muzzle) points along the aim
+    let rotation = angle + Real::from_f32(FRAC_PI_4);
+    world
+        .shapes
+        .get_mut(&cannon_id)
+        .unwrap()
+        .set_rotation(rotation);
 }
 
 // (ACTION)
 /// Moves the cannon
-pub fn move_cannon(game_objects: &mut GameObjects, direction: Direction) {
-    let cannon_id = world::get_cannon(game_objects).unwrap().get_id();
-    let shape = game_objects.1.get_mut(&cannon_id).unwrap();
+pub fn move_cannon(world: &mut World, direction: Direction) {
+    let cannon_id = world.cannon().unwrap().get_id();
+    let shape = world.shapes.get_mut(&cannon_id).unwrap();
     shape.set_movement(direction);
 }
 
@@ -80,11 +104,7 @@ fn move_with_wrap(start: i32, amt: i32, bound: i32) -> i32 {
 /// `dt`: frame time, in ms
 fn update_pos(box_state: &mut Shape, dt: i32, wrap: bool) {
     let (cx, cy) = *box_state.get_center();
-    let (vx, vy) = *box_state.get_vel();
-
-    // Watch for rounding errors here - grid has to be suitably large otherwise slow moving objects will misbehave
-    let step_x = vx * dt / 1000;
-    let step_y = vy * dt / 1000;
+    let (step_x, step_y) = box_state.calc_step(dt);
 
     let new_center = if wrap {
         (
@@ -96,7 +116,7 @@ fn update_pos(box_state: &mut Shape, dt: i32, wrap: bool) {
     };
     box_state.set_center(new_center);
 
-    box_state.rotate(dt as f32 / 1000.0);
+    box_state.rotate(dt);
 }
 
 fn is_inside_world(point: P) -> bool {
@@ -105,84 +125,132 @@ fn is_inside_world(point: P) -> bool {
 }
 
 /// Handle when bullets miss i.e. reach edge of world without hitting anything - remove them.
-fn handle_bullet_misses(game_objects: &mut GameObjects) {
-    let bullets = game_objects
-        .0
+fn handle_bullet_misses(world: &mut World, _dt: i32) {
+    let mut to_remove: Vec<EntityId> = world
+        .query()
+        .of_kind(EntityKind::Bullet)
         .iter()
-        .filter(|e| *e.get_kind() == EntityKind::Bullet);
-
-    let to_remove: Vec<EntityId> = bullets
         .filter(|b| {
-            let shape = game_objects.1.get(&b.get_id()).unwrap();
+            let shape = world.shapes.get(&b.get_id()).unwrap();
             !is_inside_world(*shape.get_center())
         })
         .map(|b| b.get_id())
         .collect();
 
+    // In ID order, so that IDs are reused deterministically
+    to_remove.sort_unstable();
     for b in to_remove {
-        world::remove(game_objects, b);
+        world.remove(b);
     }
 }
 
-fn detect_and_handle_collisions(
+/// Calculates the shapes swept out in the last step by entities of the given kinds, for continuous collision detection.
+/// Entities that wrapped around the world edge aren't swept, to avoid sweeping across the world.
+fn sweep_geometries(
     entities: &Entities,
-    shapes: &mut Shapes,
+    shapes: &Shapes,
     geometries: &Geometries,
-    healths: &mut Healths,
-) -> HashSet<EntityId> {
-    // Removal collections. Need a separate one for each closure, but they can be merged at the end.
-    let mut to_remove = HashSet::<EntityId>::new();
-    let mut to_remove_2 = HashSet::<EntityId>::new();
-    let mut to_remove_3 = HashSet::<EntityId>::new();
-    {
-        let baddie_wall_handler = |baddie_id: EntityId, _wall_id: EntityId| {
-            let baddie_shape = shapes.get_mut(&baddie_id).unwrap();
-            baddie_shape.move_back();
-            baddie_shape.reverse();
-        };
-
-        let bullet_wall_handler = |bullet_id: EntityId, _wall_id: EntityId| {
-            to_remove.insert(bullet_id);
-        };
-
-        let bullet_baddie_handler = |bullet_id: EntityId, baddie_id: EntityId| {
-            to_remove_2.insert(bullet_id);
-            to_remove_2.insert(baddie_id);
-        };
-
-        let baddie_cannon_handler = |baddie_id: EntityId, cannon_id: EntityId| {
-            to_remove_3.insert(baddie_id);
-            let cannon_health = healths.get_mut(&cannon_id).unwrap();
-            let new_health = *cannon_health - 1;
-            *cannon_health = new_health;
-        };
-
-        let (wall_geoms, baddie_geoms, bullet_geoms, cannon_geoms) =
-            world::destructure_geom(&entities, &geometries);
-        let mut collision_system = CollisionSystem::new(
-            &wall_geoms,
-            &baddie_geoms,
-            &bullet_geoms,
-            &cannon_geoms,
-            Box::new(baddie_wall_handler),
-            Box::new(bullet_wall_handler),
-            Box::new(bullet_baddie_handler),
-            Box::new(baddie_cannon_handler),
-        );
-        collision_system.process(&wall_geoms, &baddie_geoms, &bullet_geoms, &cannon_geoms);
+    ccd_kinds: &HashSet<EntityKind>,
+) -> HashMap<EntityId, Geometry> {
+    entities
+        .iter()
+        .filter(|e| ccd_kinds.contains(e.get_kind()))
+        .filter_map(|e| {
+            let id = e.get_id();
+            let (dx, dy) = shapes.get(&id).unwrap().get_displacement();
+            let wrapped = dx.abs() > GRID_WIDTH as i32 / 2 || dy.abs() > GRID_HEIGHT as i32 / 2;
+            if wrapped {
+                return None;
+            }
+            Some((id, geometries.get(&id).unwrap().sweep((dx, dy))))
+        })
+        .collect()
+}
+
+/// The kinds of collision that the game responds to
+const COLLISION_KINDS: [CollisionKind; 4] = [
+    (EntityKind::Baddie, EntityKind::Wall),
+    (EntityKind::Bullet, EntityKind::Wall),
+    (EntityKind::Bullet, EntityKind::Baddie),
+    (EntityKind::Baddie, EntityKind::Cannon),
+];
+
+/// Detects collisions, for the systems that follow to respond to (see [`World::collisions`])
+fn detect_collisions(world: &mut World, _dt: i32) {
+    let World {
+        entities,
+        shapes,
+        geometries,
+        ccd_kinds,
+        broad_phase,
+        collisions,
+        ..
+    } = world;
+    let swept = sweep_geometries(entities, shapes, geometries, ccd_kinds);
+    let mut layers = world::layer_geoms(entities, geometries);
+    for (id, polygon) in swept.iter() {
+        let kind = world::get_entity(entities, *id).get_kind();
+        layers.get_mut(kind).unwrap().insert(*id, polygon);
     }
-    // Union the removal lists
-    for tr in to_remove_2 {
-        to_remove.insert(tr);
+    *collisions = collision_system().process(broad_phase.as_mut(), &layers);
+}
+
+/// The collision system the game uses: the kinds of collision it responds to, in a world that wraps around
+pub fn collision_system() -> CollisionSystem {
+    let mut collision_system = CollisionSystem::new();
+    collision_system.wrap_around((GRID_WIDTH as i32, GRID_HEIGHT as i32));
+    for (left, right) in COLLISION_KINDS.iter() {
+        collision_system.register(*left, *right);
     }
-    for tr in to_remove_3 {
-        to_remove.insert(tr);
+    collision_system
+}
+
+/// Baddies bounce off walls, and are pushed back out of them (plus a unit, so they're clear of the wall)
+fn bounce_off_walls(world: &mut World, _dt: i32) {
+    let events = world
+        .collisions
+        .iter()
+        .filter(|event| event.kind == (EntityKind::Baddie, EntityKind::Wall));
+    for event in events {
+        let baddie_shape = world.shapes.get_mut(&event.left).unwrap();
+        baddie_shape.reflect(event.contact.normal);
+        let (nx, ny) = event.contact.normal;
+        let push = event.contact.depth.ceil() + Real::from_i32(1);
+        baddie_shape.translate(((-nx * push).round().to_i32(), (-ny * push).round().to_i32()));
     }
+}
 
-    to_remove
+/// Bullets are destroyed by walls, and destroy the baddies they hit.
+/// Baddies that reach the cannon damage it, and are destroyed.
+fn handle_hits(world: &mut World, _dt: i32) {
+    // In ID order, so that IDs are reused deterministically
+    let mut to_remove = BTreeSet::<EntityId>::new();
+    for event in world.collisions.iter() {
+        match event.kind {
+            (EntityKind::Bullet, EntityKind::Wall) => {
+                to_remove.insert(event.left);
+            }
+            (EntityKind::Bullet, EntityKind::Baddie) => {
+                to_remove.insert(event.left);
+                to_remove.insert(event.right);
+            }
+            (EntityKind::Baddie, EntityKind::Cannon) => {
+                to_remove.insert(event.left);
+                let cannon_health = world.healths.get_mut(&event.right).unwrap();
+                *cannon_health -= 1;
+            }
+            _ => (),
+        }
+    }
+    for e in to_remove {
+        world.remove(e);
+    }
 }
 
-fn update_positions(entities: &Entities, shapes: &mut Shapes, dt: i32) {
+fn update_positions(world: &mut World, dt: i32) {
+    let World {
+        entities, shapes, ..
+    } = world;
     for entity in entities.iter() {
         let shape = shapes.get_mut(&entity.get_id()).unwrap();
         match entity.get_kind() {
@@ -195,82 +263,79 @@ fn update_positions(entities: &Entities, shapes: &mut Shapes, dt: i32) {
     }
 }
 
-fn update_geometries(shapes: &Shapes, geometries: &mut Geometries) {
+fn update_geometries(world: &mut World, _dt: i32) {
+    let World {
+        shapes, geometries, ..
+    } = world;
     for (id, shape) in shapes.iter() {
-        let geometry = geometries.get_mut(&id).unwrap();
+        let geometry = geometries.get_mut(id).unwrap();
         update_geometry(geometry, shape);
     }
 }
 
 /// Gets player health.
 /// Optional as there may not be a player in the world, as in some test cases.
-fn player_health(game_objects: &GameObjects) -> Option<i32> {
-    if let Some(cannon) = world::get_cannon(game_objects) {
-        let cannon_id = cannon.get_id();
-        let (_, _, _, healths) = game_objects;
-        Some(*healths.get(&cannon_id).unwrap())
-    } else {
-        None
-    }
+pub fn player_health(world: &World) -> Option<i32> {
+    let cannon = world.cannon()?;
+    Some(*world.get::<world::Health>(cannon.get_id()).unwrap())
 }
 
-#[derive(Debug)]
+/// Outcome of a world update
+#[derive(Debug, PartialEq)]
 pub enum LevelState {
+    /// Level still being played
     InProgress,
+    /// All baddies destroyed
     Complete,
+    /// Player health reached 0
     GameOver,
 }
 
-pub fn update_world(mut world: World, dt: i32) -> (World, LevelState) {
-    // Update shape state
-    let (entities, mut shapes, geometries, healths) = world;
-    update_positions(&entities, &mut shapes, dt);
-    world = (entities, shapes, geometries, healths);
-
-    // Update geometry ready for collision detection
-    let (entities, shapes, mut geometries, healths) = world;
-    update_geometries(&shapes, &mut geometries);
-    world = (entities, shapes, geometries, healths);
-
-    handle_bullet_misses(&mut world);
-    // Detect & handle collisions
-    let (entities, mut shapes, geometries, mut healths) = world;
-    let to_remove = detect_and_handle_collisions(&entities, &mut shapes, &geometries, &mut healths);
-    world = (entities, shapes, geometries, healths);
-    for e in to_remove {
-        world::remove(&mut world, e);
-    }
+/// The systems that make up a world update, in order: moves objects, then detects collisions and responds to them.
+/// Further systems can be registered after these, e.g. for new components.
+pub fn systems() -> Systems {
+    let mut systems = Systems::new();
+    systems
+        .register("move", update_positions)
+        // Update geometry ready for collision detection
+        .register("geometry", update_geometries)
+        .register("bullet_misses", handle_bullet_misses)
+        .register("collisions", detect_collisions)
+        // Responses to the collisions
+        .register("bounce", bounce_off_walls)
+        .register("hits", handle_hits)
+        // 2nd pass of geometry update to reflect destroyed/backed-out objects.
+        // Could be more efficient, but so far it's not a bottleneck.
+        .register("geometry", update_geometries);
+    systems
+}
 
-    // 2nd pass of geometry update to reflect destroyed/backed-out objects.
-    // Could be more efficient, but so far it's not a bottleneck.
-    let (entities, shapes, mut geometries, healths) = world;
-    update_geometries(&shapes, &mut geometries);
-    world = (entities, shapes, geometries, healths);
+/// Advances the world by time-step `dt` (ms), running the standard [`systems`].
+/// Returns the resulting state of the level.
+pub fn update_world(world: &mut World, dt: i32) -> LevelState {
+    systems().run(world, dt);
+    level_state(world)
+}
 
-    let state = if player_health(&world) == Some(0) {
+/// Gets the state of the level, from the world
+pub fn level_state(world: &World) -> LevelState {
+    if player_health(world) == Some(0) {
         LevelState::GameOver
-    } else if level_complete(&world) {
+    } else if world.count(EntityKind::Baddie) == 0 {
         LevelState::Complete
     } else {
         LevelState::InProgress
-    };
-    (world, state)
-}
-
-fn level_complete(world: &World) -> bool {
-    let (entities, _, _, _) = world;
-    let baddies = entities
-        .iter()
-        .filter(|e| e.get_kind() == &EntityKind::Baddie);
-    baddies.count() == 0
+    }
 }
 
 /// Game logic tests. Note: These are integration tests, rather than unit tests.
 #[cfg(test)]
 mod tests {
-    use super::{update_world, LevelState, GRID_WIDTH};
-    use crate::entity::Entity;
+    use super::{player_health, update_world, LevelState, GRID_HEIGHT, GRID_WIDTH};
+    use crate::entity::{Entity, EntityKind};
+    use crate::fixed::Scalar;
     use crate::world;
+    use std::f32::consts::TAU;
     #[test]
     fn bullet_meets_enemy_both_destroyed() {
         // Arrange
@@ -288,15 +353,14 @@ mod tests {
         // simulate 20ms
         let dt = 20;
 
-        let world = world::create_world(vec![
-            hit_baddie,
-            missed_baddie,
-            hitting_bullet,
-            missing_bullet,
-        ]);
+        let mut world = world::create_world(
+            &obj_factory,
+            vec![hit_baddie, missed_baddie, hitting_bullet, missing_bullet],
+        );
 
         // Act
-        let ((entities, _, _, _), _) = update_world(world, dt);
+        update_world(&mut world, dt);
+        let entities = &world.entities;
 
         // Assert
         assert_eq!(entities.len(), 2);
@@ -308,13 +372,15 @@ mod tests {
     fn bullet_destroyed_at_screen_edge() {
         // Arrange
         let obj_factory = world::ObjectFactory::new(1000);
-        let world = world::create_world(vec![
-            obj_factory.make_bullet((GRID_WIDTH as i32 - 10, 100), (1, 0))
-        ]);
+        let mut world = world::create_world(
+            &obj_factory,
+            vec![obj_factory.make_bullet((GRID_WIDTH as i32 - 10, 100), (1, 0))],
+        );
         let dt = 20;
 
         // Act
-        let ((entities, _, _, _), _) = update_world(world, dt);
+        update_world(&mut world, dt);
+        let entities = &world.entities;
 
         // Assert
         assert_eq!(entities.len(), 0);
@@ -326,12 +392,13 @@ mod tests {
         let obj_factory = world::ObjectFactory::new(1000);
         let baddie = obj_factory.make_baddie((GRID_WIDTH as i32 - 10, 1000), (1000, 0), 0.0);
         let baddie_id = baddie.0.get_id();
-        let world = world::create_world(vec![baddie]);
+        let mut world = world::create_world(&obj_factory, vec![baddie]);
         let dt = 20;
         let new_center_expected = (10, 1000);
 
         // Act
-        let ((_, shapes, _, _), _) = update_world(world, dt);
+        update_world(&mut world, dt);
+        let shapes = &world.shapes;
 
         // Assert
         let new_center_actual = shapes.get(&baddie_id).unwrap().get_center();
@@ -344,12 +411,13 @@ mod tests {
         let obj_factory = world::ObjectFactory::new(1000);
         let baddie = obj_factory.make_baddie((10, 1000), (-1000, 0), 0.0);
         let baddie_id = baddie.0.get_id();
-        let world = world::create_world(vec![baddie]);
+        let mut world = world::create_world(&obj_factory, vec![baddie]);
         let dt = 20;
         let new_center_expected = (GRID_WIDTH as i32 - 10, 1000);
 
         // Act
-        let ((_, shapes, _, _), _) = update_world(world, dt);
+        update_world(&mut world, dt);
+        let shapes = &world.shapes;
 
         // Assert
         let new_center_actual = shapes.get(&baddie_id).unwrap().get_center();
@@ -363,19 +431,140 @@ mod tests {
         let baddie = obj_factory.make_baddie((1000, 1000), (1000, 0), 0.0); // assume size 750 => right edge is at x=1375
         let baddie_id = baddie.0.get_id();
         let wall = obj_factory.make_wall((1900, 1000)); // assume size is 1000 => left edge is at 1400
-        let world = world::create_world(vec![baddie, wall]);
+        let mut world = world::create_world(&obj_factory, vec![baddie, wall]);
         let dt = 100;
         // Expect baddie to travel 25 to the wall, and then be reversed. Doesn't need to be exact so just check the velocity is reversed.
 
         // Act
-        let ((_, shapes, _, _), _) = update_world(world, dt);
+        update_world(&mut world, dt);
+        let shapes = &world.shapes;
 
         // Assert
         let new_vel = *shapes.get(&baddie_id).unwrap().get_vel();
         assert_eq!(new_vel, (-1000, 0));
     }
 
-    // // COULDDO: Test bounce + wrap
+    #[test]
+    fn baddies_glance_off_walls() {
+        // Arrange - as above, but also moving down, so hits the wall at an angle
+        let obj_factory = world::ObjectFactory::new(1000);
+        let baddie = obj_factory.make_baddie((1000, 1000), (1000, 500), 0.0);
+        let baddie_id = baddie.0.get_id();
+        let wall = obj_factory.make_wall((1900, 1000));
+        let wall_geom = wall.2.clone();
+        let mut world = world::create_world(&obj_factory, vec![baddie, wall]);
+        let dt = 100;
+
+        // Act
+        update_world(&mut world, dt);
+
+        // Assert - only the component into the wall is reversed, and the baddie is clear of the wall
+        let shapes = &world.shapes;
+        let new_vel = *shapes.get(&baddie_id).unwrap().get_vel();
+        assert_eq!(new_vel, (-1000, 500));
+        let baddie_geom = world.geometries.get(&baddie_id).unwrap();
+        assert!(!baddie_geom.collides(&wall_geom));
+    }
+
+    #[test]
+    fn baddies_bounce_off_walls_across_screen_edge() {
+        // Arrange - baddie hanging over the right edge (assume size 750 => right edge at x=75 on the left side),
+        // moving towards a wall at the left edge (assume size 1000 => left edge at x=100)
+        let obj_factory = world::ObjectFactory::new(1000);
+        let baddie = obj_factory.make_baddie((GRID_WIDTH as i32 - 300, 1000), (1000, 0), 0.0);
+        let baddie_id = baddie.0.get_id();
+        let wall = obj_factory.make_wall((600, 1000));
+        let mut world = world::create_world(&obj_factory, vec![baddie, wall]);
+        let dt = 100;
+
+        // Act
+        update_world(&mut world, dt);
+
+        // Assert - bounced, and pushed back out of the wall (from where it moved to, at x = GRID_WIDTH - 200)
+        let shape = world.shapes.get(&baddie_id).unwrap();
+        assert_eq!(*shape.get_vel(), (-1000, 0));
+        assert!(shape.get_center().0 < GRID_WIDTH as i32 - 200);
+    }
+
+    #[test]
+    fn baddie_meets_player_across_screen_edge() {
+        // Arrange - baddie hanging over the bottom edge, player at the top
+        let obj_factory = world::ObjectFactory::new(1000);
+        let baddie = obj_factory.make_baddie((5000, GRID_HEIGHT as i32 - 100), (0, 0), 0.0);
+        let baddie_id = baddie.0.get_id();
+        let cannon = obj_factory.make_cannon((5000, 100));
+        let mut world = world::create_world(&obj_factory, vec![baddie, cannon]);
+
+        // Act
+        update_world(&mut world, 20);
+
+        // Assert
+        assert!(!world.entities.contains(&Entity::from_id(baddie_id)));
+        assert_eq!(player_health(&world), Some(world::PLAYER_HEALTH_MAX - 1));
+    }
+
+    #[test]
+    fn slow_baddie_moves_at_small_timestep() {
+        // Arrange - 50 units/sec at 10ms ticks => 0.5 units per tick
+        let obj_factory = world::ObjectFactory::new(1000);
+        let baddie = obj_factory.make_baddie((1000, 1000), (50, -50), 0.0);
+        let baddie_id = baddie.0.get_id();
+        let mut world = world::create_world(&obj_factory, vec![baddie]);
+
+        // Act - 1 sec
+        for _ in 0..100 {
+            update_world(&mut world, 10);
+        }
+
+        // Assert
+        let center = world.shapes.get(&baddie_id).unwrap().get_center();
+        assert_eq!(*center, (1050, 950));
+    }
+
+    #[test]
+    fn rotation_wraps_within_a_turn() {
+        // Arrange - spinning at 1 radian/sec either way
+        let obj_factory = world::ObjectFactory::new(1000);
+        let clockwise = obj_factory.make_baddie((1000, 1000), (0, 0), 1.0);
+        let anticlockwise = obj_factory.make_baddie((5000, 5000), (0, 0), -1.0);
+        let ids = [clockwise.0.get_id(), anticlockwise.0.get_id()];
+        let mut world = world::create_world(&obj_factory, vec![clockwise, anticlockwise]);
+
+        // Act - 10 sec, i.e. past a full turn
+        for _ in 0..100 {
+            update_world(&mut world, 100);
+        }
+
+        // Assert - wrapped, without the interpolated rotation jumping back a turn
+        for (id, expected) in ids.iter().zip([10.0 - TAU, TAU - 10.0].iter()) {
+            let shape = world.shapes.get(id).unwrap();
+            assert!((shape.get_rotation().to_f32() - expected).abs() < 0.01);
+            let (_, halfway) = shape.interpolate(0.5);
+            assert!((halfway.to_f32() - shape.get_rotation().to_f32()).abs() < 0.06);
+        }
+    }
+
+    #[test]
+    fn interpolated_geometry_between_ticks() {
+        // Arrange
+        let obj_factory = world::ObjectFactory::new(1000);
+        let baddie = obj_factory.make_baddie((1000, 1000), (1000, 0), 0.0);
+        let baddie_id = baddie.0.get_id();
+        let mut world = world::create_world(&obj_factory, vec![baddie]);
+
+        // Act - moves 100 units
+        update_world(&mut world, 100);
+        let shape = world.shapes.get(&baddie_id).unwrap();
+        let geom_prev = world::interpolate_geometry(shape, 0.0);
+        let geom_mid = world::interpolate_geometry(shape, 0.5);
+        let geom_curr = world::interpolate_geometry(shape, 1.0);
+
+        // Assert
+        let geom = world.geometries.get(&baddie_id).unwrap();
+        assert_eq!(geom_curr, *geom);
+        assert_eq!(geom_mid.aabb().min.0, geom.aabb().min.0 - 50);
+        assert_eq!(geom_prev.aabb().min.0, geom.aabb().min.0 - 100);
+    }
 
     #[test]
     fn bullet_destroyed_by_wall() {
@@ -387,16 +576,101 @@ mod tests {
         let bullet_id = bullet.0.get_id();
         // assume size is 1000 => left edge is at 1400
         let wall = obj_factory.make_wall((1900, 1000));
-        let world = world::create_world(vec![bullet, wall]);
+        let mut world = world::create_world(&obj_factory, vec![bullet, wall]);
 
         let dt = 20;
 
         // Act
-        let ((entities, _, _, _), _) = update_world(world, dt);
+        update_world(&mut world, dt);
+        let entities = &world.entities;
 
         // Assert
         assert_eq!(entities.len(), 1);
-        assert!(entities.contains(&Entity::from_id(bullet_id)) == false);
+        assert!(!entities.contains(&Entity::from_id(bullet_id)));
+    }
+
+    /// Collisions are kept in the world after the update, e.g. for a scoring system registered after the standard ones
+    #[test]
+    fn collision_events_available_to_later_systems() {
+        // Arrange - a bullet about to hit a baddie, and a system keeping score on the cannon
+        struct Score(usize);
+        impl world::Component for Score {}
+        let obj_factory = world::ObjectFactory::new(1000);
+        let bullet = obj_factory.make_bullet((1000, 1000), (1, 0));
+        let baddie = obj_factory.make_baddie((1400, 1000), (0, 0), 0.0);
+        let cannon = obj_factory.make_cannon((5000, 5000));
+        let (bullet_id, baddie_id) = (bullet.0.get_id(), baddie.0.get_id());
+        let mut world = world::create_world(&obj_factory, vec![bullet, baddie, cannon]);
+        let mut systems = super::systems();
+        systems.register("score", |world, _| {
+            let hits = world
+                .collisions
+                .iter()
+                .filter(|event| event.kind == (EntityKind::Bullet, EntityKind::Baddie))
+                .count();
+            let cannon_id = world.cannon().unwrap().get_id();
+            let score = world.get::<Score>(cannon_id).map_or(0, |score| score.0);
+            world.insert(cannon_id, Score(score + hits));
+        });
+
+        // Act
+        systems.run(&mut world, 100);
+
+        // Assert
+        assert_eq!(world.collisions.len(), 1);
+        assert_eq!(world.collisions[0].left, bullet_id);
+        assert_eq!(world.collisions[0].right, baddie_id);
+        let cannon_id = world.cannon().unwrap().get_id();
+        assert_eq!(world.get::<Score>(cannon_id).unwrap().0, 1);
+    }
+
+    /// At 200ms ticks, small bullets move further than the size of small baddies in a single tick
+    #[test]
+    fn bullet_does_not_tunnel_through_baddie() {
+        // Arrange - bullet size 10, baddie size 75, bullet moves 200 per tick
+        let obj_factory = world::ObjectFactory::new(100);
+        let bullet = obj_factory.make_bullet((1000, 1000), (1, 0));
+        let baddie = obj_factory.make_baddie((1100, 1000), (0, 0), 0.0);
+        let mut world = world::create_world(&obj_factory, vec![bullet, baddie]);
+
+        // Act
+        update_world(&mut world, 200);
+
+        // Assert
+        assert_eq!(world.entities.len(), 0);
+    }
+
+    #[test]
+    fn bullet_does_not_tunnel_through_wall() {
+        // Arrange - bullet size 10, wall size 100, bullet moves 200 per tick
+        let obj_factory = world::ObjectFactory::new(100);
+        let bullet = obj_factory.make_bullet((1000, 1000), (1, 0));
+        let wall = obj_factory.make_wall((1100, 1000));
+        let wall_id = wall.0.get_id();
+        let mut world = world::create_world(&obj_factory, vec![bullet, wall]);
+
+        // Act
+        update_world(&mut world, 200);
+
+        // Assert
+        assert_eq!(world.entities.len(), 1);
+        assert!(world.entity(wall_id).is_some());
+    }
+
+    #[test]
+    fn bullet_tunnels_without_ccd() {
+        // Arrange - as above, but with CCD turned off
+        let obj_factory = world::ObjectFactory::new(100);
+        let bullet = obj_factory.make_bullet((1000, 1000), (1, 0));
+        let baddie = obj_factory.make_baddie((1100, 1000), (0, 0), 0.0);
+        let mut world = world::create_world(&obj_factory, vec![bullet, baddie]);
+        world.ccd_kinds.clear();
+
+        // Act
+        update_world(&mut world, 200);
+
+        // Assert
+        assert_eq!(world.entities.len(), 2);
     }
 
     #[test]
@@ -406,16 +680,17 @@ mod tests {
         let cannon = obj_factory.make_cannon((1000, 1000));
         let baddie = obj_factory.make_baddie((1000, 1000), (0, 0), 0.0);
         let baddie_id = baddie.0.get_id();
-        let world = world::create_world(vec![cannon, baddie]);
+        let mut world = world::create_world(&obj_factory, vec![cannon, baddie]);
 
         let dt = 20;
 
         // Act
-        let ((entities, _, _, _), _) = update_world(world, dt);
+        update_world(&mut world, dt);
+        let entities = &world.entities;
 
         // Assert
         assert_eq!(entities.len(), 1);
-        assert!(entities.contains(&Entity::from_id(baddie_id)) == false);
+        assert!(!entities.contains(&Entity::from_id(baddie_id)));
     }
 
     #[test]
@@ -425,15 +700,15 @@ mod tests {
         let cannon = obj_factory.make_cannon((1000, 1000));
         let cannon_id = cannon.0.get_id();
         let baddie = obj_factory.make_baddie((1000, 1000), (0, 0), 0.0);
-        let world = world::create_world(vec![cannon, baddie]);
+        let mut world = world::create_world(&obj_factory, vec![cannon, baddie]);
         let expected_health_change = -1;
 
         let dt = 20;
 
         // Act
-        let health_before = *world.3.get(&cannon_id).unwrap();
-        let ((_, _, _, healths), _) = update_world(world, dt);
-        let health_after = healths.get(&cannon_id).unwrap();
+        let health_before = *world.healths.get(&cannon_id).unwrap();
+        update_world(&mut world, dt);
+        let health_after = world.healths.get(&cannon_id).unwrap();
 
         // Assert
         assert_eq!(health_after - health_before, expected_health_change);
@@ -445,16 +720,12 @@ mod tests {
         let cannon = obj_factory.make_cannon((1000, 1000));
         let (entity, shape, geometry, _) = cannon;
         let cannon = (entity, shape, geometry, Some(0));
-        let world = world::create_world(vec![cannon]);
+        let mut world = world::create_world(&obj_factory, vec![cannon]);
 
         // Act
-        let (_, level_state) = update_world(world, 10);
+        let level_state = update_world(&mut world, 10);
 
         // Assert
-        let gameover = match level_state {
-            LevelState::GameOver => true,
-            _ => false,
-        };
-        assert!(gameover);
+        assert_eq!(level_state, LevelState::GameOver);
     }
 }
@@ -1,5 +1,8 @@
 //! Geometry and math operations
 
+use crate::fixed::{Real, Scalar};
+use itertools::Itertools;
+
 /// A vector of (x, y)
 pub type Vector = (i32, i32);
 
@@ -15,36 +18,55 @@ pub type Vertex = Vector;
 /// An interval/range of (Min, Max)
 pub type MinMax = (i32, i32);
 
-/// Object geometry. All objects are boxes (the first vertex is repeated to close the shape).
-pub type Geometry = [Vertex; 5];
+/// Object geometry, in world coordinates
+#[derive(Clone, Debug, PartialEq)]
+pub enum Geometry {
+    /// Convex polygon, closed by repeating the first vertex at the end (see [`is_collision`])
+    Polygon(Vec<Vertex>),
+    /// Circle
+    Circle {
+        /// Centre point
+        center: P,
+        /// Radius, in world units
+        radius: i32,
+    },
+}
+
+/// Number of sides of the polygons that stand in for circles, e.g. for drawing them
+pub const CIRCLE_SIDES: usize = 16;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Screen direction, e.g. for cannon movement and firing
 pub enum Direction {
+    /// Towards y = 0
     Up,
+    /// Towards y = max
     Down,
+    /// Towards x = 0
     Left,
+    /// Towards x = max
     Right,
 }
 
 /// Rotate point `p` around center `c` by `angle` radians (in-place)
 /// Based on https://stackoverflow.com/a/2259502
-pub fn rotate(p: &mut P, c: &P, angle: f32) {
-    let sin = angle.sin();
-    let cos = angle.cos();
+pub fn rotate(p: &mut P, c: &P, angle: Real) {
+    let (sin, cos) = angle.sin_cos();
 
     let (px, py) = *p;
     let (cx, cy) = c;
 
     // Move point to origin
-    let temp_x = (px - cx) as f32;
-    let temp_y = (py - cy) as f32;
+    let temp_x = Real::from_i32(px - cx);
+    let temp_y = Real::from_i32(py - cy);
 
     // Calculate rotation
     let rx = temp_x * cos - temp_y * sin;
     let ry = temp_x * sin + temp_y * cos;
 
     // Move rotated point back
-    p.0 = cx + rx as i32;
-    p.1 = cy + ry as i32;
+    p.0 = cx + rx.to_i32();
+    p.1 = cy + ry.to_i32();
 }
 
 /// Scale the vector `v` by constant `a`
@@ -52,6 +74,17 @@ pub fn scale(v: Vector, a: i32) -> Vector {
     (a * v.0, a * v.1)
 }
 
+/// Scale the vector `v` to the given length, rounded to whole units. The zero vector stays zero.
+pub fn normalize(v: Vector, length: i32) -> Vector {
+    let (x, y) = (Real::from_i32(v.0), Real::from_i32(v.1));
+    let magnitude = (x * x + y * y).sqrt();
+    if magnitude == Real::from_i32(0) {
+        return (0, 0);
+    }
+    let factor = Real::from_i32(length) / magnitude;
+    ((x * factor).round().to_i32(), (y * factor).round().to_i32())
+}
+
 /// Calculate the edge vector between v1 and v2
 fn edge(v1: Vertex, v2: Vertex) -> Vector {
     (v2.0 - v1.0, v2.1 - v1.1)
@@ -76,11 +109,7 @@ fn check_overlap(range1: MinMax, range2: MinMax) -> bool {
     let (r1min, r1max) = range1;
     let (r2min, r2max) = range2;
 
-    if (r2min < r1min && r2max < r1min) || (r2min > r1min && r2min > r1max) {
-        false
-    } else {
-        true
-    }
+    !((r2min < r1min && r2max < r1min) || (r2min > r1min && r2min > r1max))
 }
 
 /// Determines whether `current` contains `input`, and if not then expands to include it.
@@ -97,7 +126,7 @@ fn calc_projected_range(poly: &[P], normal: Vector) -> MinMax {
         // project onto normal
         .map(|vertex| dotprod(*vertex, normal))
         // fold into (min, max) tuple
-        .fold((std::i32::MAX, std::i32::MIN), |acc, projected| {
+        .fold((i32::MAX, i32::MIN), |acc, projected| {
             build_range(acc, projected)
         })
     // (There's a minmax function in Itertools crate that could've been used for that)
@@ -106,8 +135,9 @@ fn calc_projected_range(poly: &[P], normal: Vector) -> MinMax {
 /// Check whether there is a collision (i.e. intersection) between the given polygons, using the Separating Axis Theorem.
 /// * Only works with **convex** polygons
 /// * The polygons should be constructed such that the first vertex is repeated at the end, indicating a closed shape.
-/// (Otherwise the edge calc will have to be tweaked)
+///   (Otherwise the edge calc will have to be tweaked)
 /// * Doesn't calculate intersection points though
+///
 /// Based on http://programmerart.weebly.com/separating-axis-theorem.html
 pub fn is_collision(poly1: &[P], poly2: &[P]) -> bool {
     assert_eq!(poly1.first(), poly1.last());
@@ -142,6 +172,331 @@ pub fn is_collision(poly1: &[P], poly2: &[P]) -> bool {
     true
 }
 
+/// Vertices within this distance (world units) of the deepest are treated as touching too, e.g. for edge-edge contact
+const CONTACT_TOLERANCE: i32 = 2;
+
+/// Contact manifold of two colliding polygons
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Contact {
+    /// How far the polygons overlap along `normal`, in world units
+    pub depth: Real,
+    /// Separating normal (unit length), pointing from the first polygon towards the second.
+    /// Moving the first polygon by `-normal * depth` resolves the penetration.
+    pub normal: (Real, Real),
+    /// Approximate point of contact - the second polygon's deepest vertex, or the mid-point of its deepest edge
+    pub point: P,
+}
+
+/// Calculates the centre of a closed polygon (see [`is_collision`]), as the mean of its vertices
+fn centroid(poly: &[P]) -> (Real, Real) {
+    let vertices = &poly[1..];
+    let n = Real::from_i32(vertices.len() as i32);
+    let (sx, sy) = vertices
+        .iter()
+        .fold((0, 0), |(sx, sy), (x, y)| (sx + x, sy + y));
+    (Real::from_i32(sx) / n, Real::from_i32(sy) / n)
+}
+
+/// Like [`is_collision`], but also calculates the contact manifold, from the axis of least penetration.
+/// Returns `None` if the polygons don't intersect.
+pub fn contact(poly1: &[P], poly2: &[P]) -> Option<Contact> {
+    assert_eq!(poly1.first(), poly1.last());
+    assert_eq!(poly2.first(), poly2.last());
+
+    // (depth, axis) with the least depth so far
+    let mut least: Option<(Real, (Real, Real))> = None;
+    for poly in &[poly1, poly2] {
+        for iv in 1..poly.len() {
+            let normal = normal(edge(poly[iv - 1], poly[iv]));
+            if normal == (0, 0) {
+                continue;
+            }
+            let poly1_range = calc_projected_range(poly1, normal);
+            let poly2_range = calc_projected_range(poly2, normal);
+            if !check_overlap(poly1_range, poly2_range) {
+                return None;
+            }
+
+            // Projections are scaled by the (non-normalized) normal's length
+            let overlap = std::cmp::min(poly1_range.1, poly2_range.1)
+                - std::cmp::max(poly1_range.0, poly2_range.0);
+            let (nx, ny) = (Real::from_i32(normal.0), Real::from_i32(normal.1));
+            let len = (nx * nx + ny * ny).sqrt();
+            let depth = Real::from_i32(overlap) / len;
+            if least.is_none_or(|(least_depth, _)| depth < least_depth) {
+                least = Some((depth, (nx / len, ny / len)));
+            }
+        }
+    }
+    let (depth, (nx, ny)) = least?;
+
+    // Point the normal from poly1 towards poly2
+    let (c1, c2) = (centroid(poly1), centroid(poly2));
+    let normal = if (c2.0 - c1.0) * nx + (c2.1 - c1.1) * ny < Real::from_i32(0) {
+        (-nx, -ny)
+    } else {
+        (nx, ny)
+    };
+
+    Some(Contact {
+        depth,
+        normal,
+        point: deepest_point(poly2, normal),
+    })
+}
+
+/// Contact point on a (closed) polygon moving along -`normal` into another shape - its deepest vertex,
+/// or the mid-point of its deepest edge
+fn deepest_point(poly: &[P], normal: (Real, Real)) -> P {
+    let depth_of = |v: &P| -(Real::from_i32(v.0) * normal.0 + Real::from_i32(v.1) * normal.1);
+    let vertices = &poly[1..];
+    let deepest = vertices.iter().map(depth_of).reduce(Real::max).unwrap();
+    let touching: Vec<&P> = vertices
+        .iter()
+        .filter(|v| deepest - depth_of(v) <= Real::from_i32(CONTACT_TOLERANCE))
+        .collect();
+    let n = touching.len() as i32;
+    let (sx, sy) = touching
+        .iter()
+        .fold((0, 0), |(sx, sy), (x, y)| (sx + x, sy + y));
+    (sx / n, sy / n)
+}
+
+/// Like [`contact`], for a polygon and a circle. Separating axis, testing the polygon's edge normals,
+/// and the axis from its closest vertex to the circle's centre.
+pub fn polygon_circle_contact(poly: &[P], center: P, radius: i32) -> Option<Contact> {
+    assert_eq!(poly.first(), poly.last());
+    let (cx, cy) = (Real::from_i32(center.0), Real::from_i32(center.1));
+    let radius = Real::from_i32(radius);
+    let closest = poly[1..]
+        .iter()
+        .min_by_key(|v| {
+            let (dx, dy) = edge(**v, center);
+            dx as i64 * dx as i64 + dy as i64 * dy as i64
+        })
+        .unwrap();
+    let axes = poly
+        .windows(2)
+        .map(|vs| normal(edge(vs[0], vs[1])))
+        .chain(std::iter::once(edge(*closest, center)))
+        .filter(|axis| *axis != (0, 0));
+
+    // (depth, axis) with the least depth so far
+    let mut least: Option<(Real, (Real, Real))> = None;
+    for (ax, ay) in axes {
+        let (ax, ay) = (Real::from_i32(ax), Real::from_i32(ay));
+        let len = (ax * ax + ay * ay).sqrt();
+        let (nx, ny) = (ax / len, ay / len);
+        let projected = poly
+            .iter()
+            .map(|(x, y)| Real::from_i32(*x) * nx + Real::from_i32(*y) * ny);
+        let poly_min = projected.clone().reduce(Real::min).unwrap();
+        let poly_max = projected.reduce(Real::max).unwrap();
+        let projected_center = cx * nx + cy * ny;
+        let (circle_min, circle_max) = (projected_center - radius, projected_center + radius);
+        if poly_max < circle_min || circle_max < poly_min {
+            return None;
+        }
+        let depth = poly_max.min(circle_max) - poly_min.max(circle_min);
+        if least.is_none_or(|(least_depth, _)| depth < least_depth) {
+            least = Some((depth, (nx, ny)));
+        }
+    }
+    let (depth, (nx, ny)) = least?;
+
+    // Point the normal from the polygon towards the circle
+    let (px, py) = centroid(poly);
+    let normal = if (cx - px) * nx + (cy - py) * ny < Real::from_i32(0) {
+        (-nx, -ny)
+    } else {
+        (nx, ny)
+    };
+    Some(Contact {
+        depth,
+        normal,
+        // The circle's deepest point
+        point: (
+            (cx - normal.0 * radius).round().to_i32(),
+            (cy - normal.1 * radius).round().to_i32(),
+        ),
+    })
+}
+
+/// Like [`contact`], for two circles
+pub fn circle_contact(center1: P, radius1: i32, center2: P, radius2: i32) -> Option<Contact> {
+    let (dx, dy) = edge(center1, center2);
+    let (dx, dy) = (Real::from_i32(dx), Real::from_i32(dy));
+    let reach = Real::from_i32(radius1 + radius2);
+    let distance_sqr = dx * dx + dy * dy;
+    if distance_sqr > reach * reach {
+        return None;
+    }
+    let distance = distance_sqr.sqrt();
+    // Concentric circles can be pushed apart any way
+    let normal = if distance > Real::from_i32(0) {
+        (dx / distance, dy / distance)
+    } else {
+        (Real::from_i32(1), Real::from_i32(0))
+    };
+    let radius2 = Real::from_i32(radius2);
+    Some(Contact {
+        depth: reach - distance,
+        normal,
+        // The second circle's deepest point
+        point: (
+            (Real::from_i32(center2.0) - normal.0 * radius2)
+                .round()
+                .to_i32(),
+            (Real::from_i32(center2.1) - normal.1 * radius2)
+                .round()
+                .to_i32(),
+        ),
+    })
+}
+
+/// Whether the point is inside the polygon, or on its edge. The polygon should be convex and closed (see [`is_collision`]).
+pub fn contains_point(poly: &[P], point: P) -> bool {
+    // A point is a (degenerate) polygon too
+    is_collision(poly, &[point, point])
+}
+
+/// Where a ray first hits a polygon
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+    /// Distance along the ray, in world units
+    pub distance: f32,
+    /// Normal (unit length) of the edge hit, pointing out of the polygon
+    pub normal: (f32, f32),
+    /// Point hit, to the nearest world unit
+    pub point: P,
+}
+
+/// The ray's direction, as a unit vector. None if the direction is zero, or it or `max_distance` isn't finite,
+/// as such a ray can't hit anything meaningfully.
+pub fn ray_direction(direction: (f32, f32), max_distance: f32) -> Option<(f32, f32)> {
+    let len = (direction.0.powi(2) + direction.1.powi(2)).sqrt();
+    if len == 0.0 || !len.is_finite() || !max_distance.is_finite() {
+        return None;
+    }
+    Some((direction.0 / len, direction.1 / len))
+}
+
+/// Casts a ray from `origin` along `direction` (of any non-zero length), up to `max_distance` away,
+/// and finds where it enters the polygon - which should be convex and closed (see [`is_collision`]).
+/// A ray that starts inside the polygon hits it straight away, with the normal pointing back along the ray.
+/// Rays with no direction never hit (see [`ray_direction`]).
+pub fn raycast(poly: &[P], origin: P, direction: (f32, f32), max_distance: f32) -> Option<RayHit> {
+    assert_eq!(poly.first(), poly.last());
+    let (dx, dy) = ray_direction(direction, max_distance)?;
+    let (ox, oy) = (origin.0 as f32, origin.1 as f32);
+    let (cx, cy) = centroid(poly);
+    let (cx, cy) = (cx.to_f32(), cy.to_f32());
+
+    // The ray is inside each edge on one side of some distance along it, so clip it edge by edge (Cyrus-Beck).
+    // It's inside the polygon from entering the last edge it crosses going in, until the first it crosses going out.
+    let (mut enter, mut exit) = (0.0, max_distance);
+    let mut hit_normal = (-dx, -dy);
+    for iv in 1..poly.len() {
+        let n = normal(edge(poly[iv - 1], poly[iv]));
+        if n == (0, 0) {
+            continue;
+        }
+        let (vx, vy) = (poly[iv].0 as f32, poly[iv].1 as f32);
+        // Point the normal outwards, away from the centre
+        let (nx, ny) = if n.0 as f32 * (cx - vx) + n.1 as f32 * (cy - vy) > 0.0 {
+            (-n.0 as f32, -n.1 as f32)
+        } else {
+            (n.0 as f32, n.1 as f32)
+        };
+        // How far the origin is outside the edge, and how quickly the ray heads further out (both scaled by the normal)
+        let outside = nx * (ox - vx) + ny * (oy - vy);
+        let outwards = nx * dx + ny * dy;
+        if outwards == 0.0 {
+            // Parallel to the edge, so either always inside it or never
+            if outside > 0.0 {
+                return None;
+            }
+            continue;
+        }
+        let crossing = -outside / outwards;
+        if outwards < 0.0 {
+            if crossing > enter {
+                enter = crossing;
+                let n_len = (nx * nx + ny * ny).sqrt();
+                hit_normal = (nx / n_len, ny / n_len);
+            }
+        } else if crossing < exit {
+            exit = crossing;
+        }
+        if enter > exit {
+            return None;
+        }
+    }
+    Some(RayHit {
+        distance: enter,
+        normal: hit_normal,
+        point: (
+            (ox + dx * enter).round() as i32,
+            (oy + dy * enter).round() as i32,
+        ),
+    })
+}
+
+/// Like [`raycast`], for a circle
+pub fn raycast_circle(
+    center: P,
+    radius: i32,
+    origin: P,
+    direction: (f32, f32),
+    max_distance: f32,
+) -> Option<RayHit> {
+    let (dx, dy) = ray_direction(direction, max_distance)?;
+    let (cx, cy) = (center.0 as f32, center.1 as f32);
+    let radius = radius as f32;
+    // Origin relative to the centre. Solve |from + t * direction| = radius for the distance t.
+    let (fx, fy) = (origin.0 as f32 - cx, origin.1 as f32 - cy);
+    let along = fx * dx + fy * dy;
+    let outside = fx * fx + fy * fy - radius * radius;
+    if outside <= 0.0 {
+        return Some(RayHit {
+            distance: 0.0,
+            normal: (-dx, -dy),
+            point: origin,
+        });
+    }
+    let discriminant = along * along - outside;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let distance = -along - discriminant.sqrt();
+    if distance < 0.0 || distance > max_distance {
+        return None;
+    }
+    let (hx, hy) = (fx + dx * distance, fy + dy * distance);
+    Some(RayHit {
+        distance,
+        normal: (hx / radius, hy / radius),
+        point: ((cx + hx).round() as i32, (cy + hy).round() as i32),
+    })
+}
+
+/// Reflects velocity `vel` about the (unit) `normal`, if it's heading into the surface, i.e. along the normal.
+/// Otherwise, e.g. if it has already bounced, returns it unchanged.
+pub fn reflect(vel: Vector, normal: (Real, Real)) -> Vector {
+    let (vx, vy) = (Real::from_i32(vel.0), Real::from_i32(vel.1));
+    let (nx, ny) = normal;
+    let along = vx * nx + vy * ny;
+    if along <= Real::from_i32(0) {
+        return vel;
+    }
+    let twice = Real::from_i32(2) * along;
+    (
+        (vx - twice * nx).round().to_i32(),
+        (vy - twice * ny).round().to_i32(),
+    )
+}
+
+/// Unit vector for the given direction
 pub fn direction_vector(direction: Direction) -> Vector {
     match direction {
         Direction::Up => (0, -1),
@@ -151,17 +506,299 @@ pub fn direction_vector(direction: Direction) -> Vector {
     }
 }
 
-/// Calculates the square of a box's side length. Assumes square box.
-pub fn box_side_len_sqr(geom: &Geometry) -> i32 {
-    let (x0, y0) = geom[0];
-    let (x1, y1) = geom[1];
-    let dx = x1 - x0;
-    let dy = y1 - y0;
-    dx * dx + dy * dy
+/// Calculates the square of a polygon's diameter, i.e. the greatest distance between two of its vertices.
+/// For a box, that's the diagonal, which is the diameter of the circle it sweeps out when rotating.
+pub fn diameter_sqr(poly: &[P]) -> i32 {
+    poly.iter()
+        .tuple_combinations()
+        .map(|(v1, v2)| {
+            let (dx, dy) = edge(*v1, *v2);
+            dx * dx + dy * dy
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Calculates the convex hull of the given points, as a closed polygon (see [`is_collision`]).
+/// Uses Andrew's monotone chain algorithm.
+fn convex_hull(mut points: Vec<P>) -> Vec<P> {
+    points.sort_unstable();
+    points.dedup();
+    if points.len() < 3 {
+        let first = points[0];
+        points.push(first);
+        return points;
+    }
+    let cross = |o: P, a: P, b: P| {
+        (a.0 - o.0) as i64 * (b.1 - o.1) as i64 - (a.1 - o.1) as i64 * (b.0 - o.0) as i64
+    };
+    // Adds p to the hull, first dropping points that would make a clockwise turn
+    // (but not past `start`, where the current chain began)
+    let add = |hull: &mut Vec<P>, start: usize, p: P| {
+        while hull.len() >= start + 2 && cross(hull[hull.len() - 2], hull[hull.len() - 1], p) <= 0 {
+            hull.pop();
+        }
+        hull.push(p);
+    };
+
+    let mut hull: Vec<P> = vec![];
+    // Lower chain, left to right
+    for p in points.iter() {
+        add(&mut hull, 0, *p);
+    }
+    // Upper chain, right to left - starts from the end of the lower chain, and ends at its start, closing the polygon
+    let start = hull.len() - 1;
+    for p in points.iter().rev().skip(1) {
+        add(&mut hull, start, *p);
+    }
+    hull
+}
+
+/// Calculates the shape swept out by polygon `poly` moving by `displacement`, i.e. the convex hull of its start and end.
+/// `poly` is the end position, and should be convex and closed (see [`is_collision`]).
+pub fn sweep(poly: &[P], displacement: Vector) -> Vec<P> {
+    let (dx, dy) = displacement;
+    let start = poly.iter().map(|(x, y)| (x - dx, y - dy));
+    convex_hull(poly.iter().cloned().chain(start).collect())
+}
+
+/// Moves the polygon by `offset`
+pub fn translate(poly: &[P], offset: Vector) -> Vec<P> {
+    let (dx, dy) = offset;
+    poly.iter().map(|(x, y)| (x + dx, y + dy)).collect()
+}
+
+/// Regular polygon with [`CIRCLE_SIDES`] sides, enclosing the circle (so it's never smaller), closed
+pub fn circle_polygon(center: P, radius: i32) -> Vec<Vertex> {
+    let step = Real::from_f32(2.0 * std::f32::consts::PI) / Real::from_i32(CIRCLE_SIDES as i32);
+    let corner_radius = Real::from_i32(radius) / (step / Real::from_i32(2)).sin_cos().1;
+    // Rounded away from the center, so it stays enclosing
+    let away = |offset: Real| {
+        let magnitude = offset.abs().ceil().to_i32();
+        if offset < Real::from_i32(0) {
+            -magnitude
+        } else {
+            magnitude
+        }
+    };
+    let mut vertices: Vec<Vertex> = (0..CIRCLE_SIDES)
+        .map(|i| {
+            let (sin, cos) = (step * Real::from_i32(i as i32)).sin_cos();
+            (
+                center.0 + away(corner_radius * cos),
+                center.1 + away(corner_radius * sin),
+            )
+        })
+        .collect();
+    vertices.push(vertices[0]);
+    vertices
+}
+
+/// Axis-aligned bounding box. Inclusive, so boxes that touch overlap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Aabb {
+    /// Top left corner (minimum x and y)
+    pub min: P,
+    /// Bottom right corner (maximum x and y)
+    pub max: P,
+}
+
+impl Aabb {
+    /// The bounding box of the polygon
+    pub fn of(poly: &[P]) -> Self {
+        let (min_x, max_x) = poly.iter().map(|v| v.0).minmax().into_option().unwrap();
+        let (min_y, max_y) = poly.iter().map(|v| v.1).minmax().into_option().unwrap();
+        Self {
+            min: (min_x, min_y),
+            max: (max_x, max_y),
+        }
+    }
+
+    /// Whether the boxes overlap (or touch)
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        check_overlap((self.min.0, self.max.0), (other.min.0, other.max.0))
+            && check_overlap((self.min.1, self.max.1), (other.min.1, other.max.1))
+    }
+
+    /// Whether the other box is entirely within this one
+    pub fn contains(&self, other: &Aabb) -> bool {
+        self.min.0 <= other.min.0
+            && self.min.1 <= other.min.1
+            && self.max.0 >= other.max.0
+            && self.max.1 >= other.max.1
+    }
+
+    /// The smallest box containing both
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: (self.min.0.min(other.min.0), self.min.1.min(other.min.1)),
+            max: (self.max.0.max(other.max.0), self.max.1.max(other.max.1)),
+        }
+    }
+
+    /// The box expanded by `margin` on every side
+    pub fn grow(&self, margin: i32) -> Aabb {
+        Aabb {
+            min: (self.min.0 - margin, self.min.1 - margin),
+            max: (self.max.0 + margin, self.max.1 + margin),
+        }
+    }
+
+    /// The box moved by `offset`
+    pub fn translate(&self, offset: Vector) -> Aabb {
+        let (dx, dy) = offset;
+        Aabb {
+            min: (self.min.0 + dx, self.min.1 + dy),
+            max: (self.max.0 + dx, self.max.1 + dy),
+        }
+    }
+
+    /// In a world of the given size that wraps around at its edges: if the box hangs over any edges,
+    /// the offsets to its images at the opposite edges (where the overhanging parts appear). Empty if it doesn't.
+    pub fn wrap_offsets(&self, size: Vector) -> Vec<Vector> {
+        let images = |min: i32, max: i32, bound: i32| {
+            let mut offsets = vec![0];
+            if min < 0 {
+                offsets.push(bound);
+            }
+            if max > bound {
+                offsets.push(-bound);
+            }
+            offsets
+        };
+        images(self.min.0, self.max.0, size.0)
+            .into_iter()
+            .cartesian_product(images(self.min.1, self.max.1, size.1))
+            .filter(|offset| *offset != (0, 0))
+            .collect()
+    }
+
+    /// The box as a polygon, e.g. to test against other geometry
+    pub fn polygon(&self) -> Geometry {
+        let (min, max) = (self.min, self.max);
+        Geometry::Polygon(vec![min, (max.0, min.1), max, (min.0, max.1), min])
+    }
+
+    /// Perimeter - a measure of size, used to decide how to group boxes
+    pub fn perimeter(&self) -> i64 {
+        2 * ((self.max.0 - self.min.0) as i64 + (self.max.1 - self.min.1) as i64)
+    }
+}
+
+impl Geometry {
+    /// Bounding box
+    pub fn aabb(&self) -> Aabb {
+        match self {
+            Geometry::Polygon(vertices) => Aabb::of(vertices),
+            Geometry::Circle { center, radius } => Aabb {
+                min: (center.0 - radius, center.1 - radius),
+                max: (center.0 + radius, center.1 + radius),
+            },
+        }
+    }
+
+    /// Square of the greatest distance across it (see [`diameter_sqr`])
+    pub fn diameter_sqr(&self) -> i32 {
+        match self {
+            Geometry::Polygon(vertices) => diameter_sqr(vertices),
+            Geometry::Circle { radius, .. } => 4 * radius * radius,
+        }
+    }
+
+    /// Whether the geometries intersect (see [`is_collision`])
+    pub fn collides(&self, other: &Geometry) -> bool {
+        match (self, other) {
+            (Geometry::Polygon(poly1), Geometry::Polygon(poly2)) => is_collision(poly1, poly2),
+            _ => self.contact(other).is_some(),
+        }
+    }
+
+    /// Contact manifold, if the geometries intersect (see [`contact`]).
+    /// The normal points from this geometry towards the other.
+    pub fn contact(&self, other: &Geometry) -> Option<Contact> {
+        match (self, other) {
+            (Geometry::Polygon(poly1), Geometry::Polygon(poly2)) => contact(poly1, poly2),
+            (Geometry::Polygon(poly), Geometry::Circle { center, radius }) => {
+                polygon_circle_contact(poly, *center, *radius)
+            }
+            (Geometry::Circle { center, radius }, Geometry::Polygon(poly)) => {
+                polygon_circle_contact(poly, *center, *radius).map(|contact| {
+                    let normal = (-contact.normal.0, -contact.normal.1);
+                    Contact {
+                        depth: contact.depth,
+                        normal,
+                        point: deepest_point(poly, normal),
+                    }
+                })
+            }
+            (
+                Geometry::Circle {
+                    center: center1,
+                    radius: radius1,
+                },
+                Geometry::Circle {
+                    center: center2,
+                    radius: radius2,
+                },
+            ) => circle_contact(*center1, *radius1, *center2, *radius2),
+        }
+    }
+
+    /// Whether the point is inside, or on the edge
+    pub fn contains_point(&self, point: P) -> bool {
+        match self {
+            Geometry::Polygon(vertices) => contains_point(vertices, point),
+            Geometry::Circle { center, radius } => {
+                let (dx, dy) = edge(*center, point);
+                dx as i64 * dx as i64 + dy as i64 * dy as i64 <= *radius as i64 * *radius as i64
+            }
+        }
+    }
+
+    /// Casts a ray at it (see [`raycast`])
+    pub fn raycast(&self, origin: P, direction: (f32, f32), max_distance: f32) -> Option<RayHit> {
+        match self {
+            Geometry::Polygon(vertices) => raycast(vertices, origin, direction, max_distance),
+            Geometry::Circle { center, radius } => {
+                raycast_circle(*center, *radius, origin, direction, max_distance)
+            }
+        }
+    }
+
+    /// The geometry moved by `offset`
+    pub fn translate(&self, offset: Vector) -> Geometry {
+        match self {
+            Geometry::Polygon(vertices) => Geometry::Polygon(translate(vertices, offset)),
+            Geometry::Circle { center, radius } => Geometry::Circle {
+                center: (center.0 + offset.0, center.1 + offset.1),
+                radius: *radius,
+            },
+        }
+    }
+
+    /// The shape swept out moving by `displacement`, to here (see [`sweep`]). Circles are swept as polygons.
+    pub fn sweep(&self, displacement: Vector) -> Geometry {
+        Geometry::Polygon(sweep(&self.to_polygon(), displacement))
+    }
+
+    /// As a closed polygon - circles as a polygon enclosing them (see [`circle_polygon`]), e.g. for drawing
+    pub fn to_polygon(&self) -> Vec<Vertex> {
+        match self {
+            Geometry::Polygon(vertices) => vertices.clone(),
+            Geometry::Circle { center, radius } => circle_polygon(*center, *radius),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::fixed::{Real, Scalar};
+
+    /// In the physics number type, to compare with results
+    fn real(value: f32) -> Real {
+        Real::from_f32(value)
+    }
+
     #[test]
     fn geom_edge_simple() {
         // Arrange
@@ -202,11 +839,9 @@ mod tests {
         let input = [5, 1, 2];
         let expected = (1, 5);
 
-        let actual = input
-            .iter()
-            .fold((std::i32::MAX, std::i32::MIN), |acc, projected| {
-                super::build_range(acc, *projected)
-            });
+        let actual = input.iter().fold((i32::MAX, i32::MIN), |acc, projected| {
+            super::build_range(acc, *projected)
+        });
 
         assert_eq!(actual, expected);
     }
@@ -317,4 +952,271 @@ mod tests {
         // Assert
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn contact_axis_aligned() {
+        // Arrange - poly2 overlaps poly1's right edge by 1
+        let poly1 = [(0, 0), (4, 0), (4, 4), (0, 4), (0, 0)];
+        let poly2 = [(3, 1), (7, 1), (7, 3), (3, 3), (3, 1)];
+
+        // Act
+        let contact = super::contact(&poly1, &poly2).unwrap();
+
+        // Assert
+        assert_eq!(contact.depth, real(1.0));
+        assert_eq!(contact.normal, (real(1.0), real(0.0)));
+        assert_eq!(contact.point, (3, 2));
+    }
+
+    #[test]
+    fn contact_normal_points_from_first_to_second() {
+        let poly1 = [(0, 0), (4, 0), (4, 4), (0, 4), (0, 0)];
+        let poly2 = [(1, -3), (3, -3), (3, 1), (1, 1), (1, -3)];
+
+        let contact = super::contact(&poly1, &poly2).unwrap();
+
+        assert_eq!(contact.depth, real(1.0));
+        assert_eq!(contact.normal, (real(0.0), real(-1.0)));
+    }
+
+    #[test]
+    fn contact_vertex_into_edge() {
+        // Arrange - diamond with its bottom vertex 10 units into the top of the box
+        let poly1 = [(0, 0), (100, 0), (100, 100), (0, 100), (0, 0)];
+        let poly2 = [(50, 10), (90, -30), (50, -70), (10, -30), (50, 10)];
+
+        // Act
+        let contact = super::contact(&poly1, &poly2).unwrap();
+
+        // Assert
+        assert_eq!(contact.depth, real(10.0));
+        assert_eq!(contact.normal, (real(0.0), real(-1.0)));
+        assert_eq!(contact.point, (50, 10));
+    }
+
+    #[test]
+    fn contact_none_when_apart() {
+        let poly1 = [(1, 1), (3, 1), (3, 3), (1, 3), (1, 1)];
+        let poly2 = [(4, 4), (6, 4), (6, 6), (4, 6), (4, 4)];
+
+        assert_eq!(super::contact(&poly1, &poly2), None);
+    }
+
+    #[test]
+    fn reflect_glancing() {
+        // Heading right and down into a surface facing left => bounces left, still heading down
+        assert_eq!(
+            super::reflect((1000, 500), (real(1.0), real(0.0))),
+            (-1000, 500)
+        );
+        // Already heading away
+        assert_eq!(
+            super::reflect((-1000, 500), (real(1.0), real(0.0))),
+            (-1000, 500)
+        );
+    }
+
+    #[test]
+    fn normalize_any_direction() {
+        assert_eq!(super::normalize((-1, 0), 1000), (-1000, 0));
+        assert_eq!(super::normalize((30, -40), 1000), (600, -800));
+        assert_eq!(super::normalize((1, 1), 1000), (707, 707));
+        assert_eq!(super::normalize((0, 0), 1000), (0, 0));
+    }
+
+    #[test]
+    fn sweep_box_diagonal() {
+        // Arrange - unit box, moved by (2, 2)
+        let poly = [(2, 2), (3, 2), (3, 3), (2, 3), (2, 2)];
+
+        // Act
+        let swept = super::sweep(&poly, (2, 2));
+
+        // Assert - hexagon, with the corners along the direction of travel dropped
+        assert_eq!(swept.first(), swept.last());
+        assert_eq!(swept.len(), 7);
+        for corner in [(0, 0), (1, 0), (3, 2), (3, 3), (2, 3), (0, 1)].iter() {
+            assert!(swept.contains(corner));
+        }
+    }
+
+    #[test]
+    fn sweep_detects_tunnelling() {
+        // Arrange - box ends up past a thin wall, without touching it
+        let poly = [(10, 0), (12, 0), (12, 2), (10, 2), (10, 0)];
+        let wall = [(5, -5), (6, -5), (6, 5), (5, 5), (5, -5)];
+        assert!(!super::is_collision(&poly, &wall));
+
+        // Act
+        let swept = super::sweep(&poly, (10, 0));
+
+        // Assert
+        assert!(super::is_collision(&swept, &wall));
+    }
+
+    #[test]
+    fn aabb_overlaps_and_contains() {
+        // Arrange - diamond, and boxes touching, inside and outside its bounds
+        let diamond = [(5, 0), (10, 5), (5, 10), (0, 5), (5, 0)];
+        let aabb = super::Aabb::of(&diamond);
+        let touching = super::Aabb::of(&[(10, 10), (20, 20)]);
+        let inside = super::Aabb::of(&[(2, 2), (8, 8)]);
+        let outside = super::Aabb::of(&[(11, 0), (20, 5)]);
+
+        // Assert
+        assert_eq!(aabb.min, (0, 0));
+        assert_eq!(aabb.max, (10, 10));
+        assert!(aabb.overlaps(&touching) && touching.overlaps(&aabb));
+        assert!(!aabb.overlaps(&outside));
+        assert!(aabb.contains(&inside) && !inside.contains(&aabb));
+        assert!(!aabb.contains(&touching));
+        assert_eq!(aabb.union(&outside).max, (20, 10));
+        assert_eq!(inside.grow(2), aabb);
+        assert_eq!(aabb.perimeter(), 40);
+    }
+
+    #[test]
+    fn aabb_wrap_offsets() {
+        // Arrange - world 100 x 50, box hanging over the right edge, one over the top left corner, and one inside
+        let size = (100, 50);
+        let right = super::Aabb {
+            min: (90, 20),
+            max: (110, 30),
+        };
+        let corner = super::Aabb {
+            min: (-5, -5),
+            max: (5, 5),
+        };
+        let inside = super::Aabb {
+            min: (0, 0),
+            max: (100, 50),
+        };
+
+        // Act
+        let right_offsets = right.wrap_offsets(size);
+        let corner_offsets = corner.wrap_offsets(size);
+        let inside_offsets = inside.wrap_offsets(size);
+
+        // Assert - overhanging parts appear at the opposite edges (and corner)
+        assert_eq!(right_offsets, vec![(-100, 0)]);
+        assert_eq!(corner_offsets, vec![(0, 50), (100, 0), (100, 50)]);
+        assert!(inside_offsets.is_empty());
+        assert_eq!(right.translate(right_offsets[0]).min, (-10, 20));
+    }
+
+    #[test]
+    fn raycast_box() {
+        // Arrange - box from (10, 10) to (20, 20)
+        let poly = [(10, 10), (20, 10), (20, 20), (10, 20), (10, 10)];
+
+        // Act
+        let head_on = super::raycast(&poly, (0, 15), (2.0, 0.0), 100.0);
+        let diagonal = super::raycast(&poly, (30, 0), (-1.0, 1.0), 100.0);
+        let short = super::raycast(&poly, (0, 15), (1.0, 0.0), 5.0);
+        let miss = super::raycast(&poly, (0, 0), (1.0, 0.0), 100.0);
+        let away = super::raycast(&poly, (0, 15), (-1.0, 0.0), 100.0);
+        let inside = super::raycast(&poly, (15, 15), (0.0, 1.0), 100.0);
+        let no_direction = super::raycast(&poly, (15, 15), (0.0, 0.0), 100.0);
+        let infinite = super::raycast(&poly, (0, 15), (1.0, 0.0), f32::INFINITY);
+
+        // Assert - hits the near edge, with the normal facing back out
+        let head_on = head_on.unwrap();
+        assert_eq!(head_on.distance, 10.0);
+        assert_eq!(head_on.normal, (-1.0, 0.0));
+        assert_eq!(head_on.point, (10, 15));
+        let diagonal = diagonal.unwrap();
+        assert!((diagonal.distance - 200f32.sqrt()).abs() < 0.01);
+        assert_eq!(diagonal.point, (20, 10));
+        assert!(short.is_none());
+        assert!(miss.is_none());
+        assert!(away.is_none());
+        let inside = inside.unwrap();
+        assert_eq!(inside.distance, 0.0);
+        assert_eq!(inside.normal, (-0.0, -1.0));
+        assert!(no_direction.is_none());
+        assert!(infinite.is_none());
+    }
+
+    #[test]
+    fn contains_point_box() {
+        let poly = super::Aabb {
+            min: (10, 10),
+            max: (20, 20),
+        }
+        .polygon();
+
+        assert!(poly.contains_point((15, 15)));
+        assert!(poly.contains_point((20, 15)));
+        assert!(!poly.contains_point((21, 15)));
+        assert!(!poly.contains_point((0, 0)));
+    }
+
+    #[test]
+    fn circle_contacts() {
+        // Arrange - box from (0, 0) to (100, 100), circles of radius 20 overlapping its right edge and corner,
+        // one just clear of the corner (but within its bounding box), and one overlapping the first circle
+        use super::Geometry;
+        let square = super::Aabb {
+            min: (0, 0),
+            max: (100, 100),
+        }
+        .polygon();
+        let circle = |center| Geometry::Circle { center, radius: 20 };
+        let right = circle((110, 50));
+        let corner = circle((110, 110));
+        let clear = circle((115, 115));
+        let other = circle((140, 50));
+
+        // Act
+        let square_right = square.contact(&right).unwrap();
+        let right_square = right.contact(&square).unwrap();
+        let circles = right.contact(&other).unwrap();
+
+        // Assert - normals point from the first towards the second
+        assert_eq!(square_right.normal, (real(1.0), real(0.0)));
+        assert_eq!(square_right.depth, real(10.0));
+        assert_eq!(square_right.point, (90, 50));
+        assert_eq!(right_square.normal, (real(-1.0), real(0.0)));
+        assert_eq!(right_square.depth, real(10.0));
+        assert_eq!(right_square.point.0, 100);
+        let corner = square.contact(&corner).unwrap();
+        assert!(
+            (corner.normal.0 - corner.normal.1).abs() < real(0.001) && corner.normal.0 > real(0.0)
+        );
+        assert!(!square.collides(&clear) && !clear.collides(&square));
+        assert_eq!(circles.normal, (real(1.0), real(0.0)));
+        assert_eq!(circles.depth, real(10.0));
+        assert_eq!(circles.point, (120, 50));
+        assert!(!other.collides(&square));
+    }
+
+    #[test]
+    fn circle_raycast_and_bounds() {
+        use super::Geometry;
+        let circle = Geometry::Circle {
+            center: (100, 100),
+            radius: 10,
+        };
+
+        let hit = circle.raycast((0, 100), (1.0, 0.0), 1000.0).unwrap();
+        let inside = circle.raycast((105, 100), (0.0, 1.0), 1000.0).unwrap();
+
+        assert_eq!(hit.distance, 90.0);
+        assert_eq!(hit.normal, (-1.0, 0.0));
+        assert_eq!(hit.point, (90, 100));
+        assert_eq!(inside.distance, 0.0);
+        assert!(circle.raycast((0, 100), (1.0, 0.0), 50.0).is_none());
+        assert!(circle.raycast((0, 111), (1.0, 0.0), 1000.0).is_none());
+        // No direction, even from inside
+        assert!(circle.raycast((105, 100), (0.0, 0.0), 1000.0).is_none());
+        assert!(circle.raycast((0, 100), (1.0, 0.0), f32::NAN).is_none());
+        assert!(circle.contains_point((107, 107)) && !circle.contains_point((108, 108)));
+        assert_eq!(circle.aabb().min, (90, 90));
+        assert_eq!(circle.diameter_sqr(), 400);
+        // Stand-in polygon encloses the circle
+        let polygon = Geometry::Polygon(circle.to_polygon());
+        assert!([(110, 100), (100, 90), (93, 93)]
+            .iter()
+            .all(|p| polygon.contains_point(*p)));
+    }
 }
@@ -0,0 +1,344 @@
+//! # Headless simulation
+//! Runs the game logic without SDL (no window, no event pump), driven by a scripted input stream.
+//! Intended for soak-testing levels and collision logic, e.g. on CI servers without a display.
+//!
+//! Script format - one input per line, `<tick> <action> <direction>`, e.g.
+//! ```text
+//! # comments and blank lines are ignored
+//! 10 fire left
+//! 25 move up
+//! 30 aim 300,-400
+//! 31 fire 300,-400
+//! ```
+//! Actions are `move`, `fire` and `aim`. All take a direction, `up`, `down`, `left` or `right`;
+//! `fire` and `aim` also take any direction as a vector, `<x>,<y>`.
+
+use crate::entity::EntityKind;
+use crate::game_logic::{player_health, LevelState};
+use crate::geometry::{direction_vector, Direction, Vector};
+use crate::levels::{LevelId, LevelSet, Seed};
+use crate::simulation::{validate_dt, Simulation};
+use std::fmt;
+
+pub use crate::simulation::Input;
+
+/// Inputs to apply, as (tick, input), in tick order.
+pub type InputScript = Vec<(u32, Input)>;
+
+/// Headless run options
+pub struct Options {
+    /// Level to play
+    pub level: LevelId,
+    /// Overrides the level's seed, if given
+    pub seed: Option<Seed>,
+    /// Max number of ticks to simulate. The run stops early if the level ends.
+    pub ticks: u32,
+    /// Time step per tick, in ms
+    pub dt: i32,
+    /// Inputs to apply during the run
+    pub script: InputScript,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            level: 1,
+            seed: None,
+            ticks: 1000,
+            dt: 16,
+            script: InputScript::new(),
+        }
+    }
+}
+
+/// State of the world at the end of a headless run
+#[derive(Debug)]
+pub struct Summary {
+    /// Level played
+    pub level: LevelId,
+    /// Seed the level was generated with
+    pub seed: Seed,
+    /// Number of ticks simulated
+    pub ticks_run: u32,
+    /// State of the level after the last tick
+    pub level_state: LevelState,
+    /// Number of baddies remaining
+    pub baddies: usize,
+    /// Number of walls remaining
+    pub walls: usize,
+    /// Number of bullets in flight
+    pub bullets: usize,
+    /// Player health, if there's a player in the world
+    pub player_health: Option<i32>,
+}
+
+impl Summary {
+    fn new(sim: &Simulation, level_state: LevelState) -> Self {
+        let world = sim.world();
+        let count = |kind: EntityKind| world.count(kind);
+        Self {
+            level: sim.level(),
+            seed: sim.seed(),
+            ticks_run: sim.tick(),
+            level_state,
+            baddies: count(EntityKind::Baddie),
+            walls: count(EntityKind::Wall),
+            bullets: count(EntityKind::Bullet),
+            player_health: player_health(world),
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "level: {}", self.level)?;
+        writeln!(f, "seed: {}", self.seed)?;
+        writeln!(f, "ticks run: {}", self.ticks_run)?;
+        writeln!(f, "level state: {:?}", self.level_state)?;
+        writeln!(f, "baddies: {}", self.baddies)?;
+        writeln!(f, "walls: {}", self.walls)?;
+        writeln!(f, "bullets: {}", self.bullets)?;
+        match self.player_health {
+            Some(health) => write!(f, "player health: {}", health),
+            None => write!(f, "player health: n/a"),
+        }
+    }
+}
+
+fn parse_direction(s: &str) -> Result<Direction, String> {
+    match s {
+        "up" => Ok(Direction::Up),
+        "down" => Ok(Direction::Down),
+        "left" => Ok(Direction::Left),
+        "right" => Ok(Direction::Right),
+        _ => Err(format!("unknown direction '{}'", s)),
+    }
+}
+
+fn format_direction(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Up => "up",
+        Direction::Down => "down",
+        Direction::Left => "left",
+        Direction::Right => "right",
+    }
+}
+
+const DIRECTIONS: [Direction; 4] = [
+    Direction::Up,
+    Direction::Down,
+    Direction::Left,
+    Direction::Right,
+];
+
+/// Parses a direction vector - a named direction, or `<x>,<y>`
+fn parse_vector(s: &str) -> Result<Vector, String> {
+    if let Ok(direction) = parse_direction(s) {
+        return Ok(direction_vector(direction));
+    }
+    let parts: Vec<&str> = s.split(',').collect();
+    match parts[..] {
+        [x, y] => match (x.parse::<i32>(), y.parse::<i32>()) {
+            (Ok(x), Ok(y)) => Ok((x, y)),
+            _ => Err(format!("bad vector '{}'", s)),
+        },
+        _ => Err(format!("unknown direction '{}'", s)),
+    }
+}
+
+/// Formats a direction vector, by name if it's one of the named directions
+fn format_vector(vector: Vector) -> String {
+    match DIRECTIONS
+        .iter()
+        .find(|direction| direction_vector(**direction) == vector)
+    {
+        Some(direction) => format_direction(*direction).to_string(),
+        None => format!("{},{}", vector.0, vector.1),
+    }
+}
+
+/// Parses a single scripted input, `<tick> <action> <direction>`
+pub fn parse_input(line: &str) -> Result<(u32, Input), String> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.len() != 3 {
+        return Err("expected '<tick> <action> <direction>'".to_string());
+    }
+    let tick = parts[0]
+        .parse::<u32>()
+        .map_err(|e| format!("bad tick '{}': {}", parts[0], e))?;
+    let input = match parts[1] {
+        "fire" => Input::Fire(parse_vector(parts[2])?),
+        "aim" => Input::Aim(parse_vector(parts[2])?),
+        "move" => Input::Move(parse_direction(parts[2])?),
+        other => return Err(format!("unknown action '{}'", other)),
+    };
+    Ok((tick, input))
+}
+
+/// Formats a scripted input, as accepted by [`parse_input`]
+pub fn format_input(tick: u32, input: Input) -> String {
+    let (action, direction) = match input {
+        Input::Fire(aim) => ("fire", format_vector(aim)),
+        Input::Aim(aim) => ("aim", format_vector(aim)),
+        Input::Move(direction) => ("move", format_direction(direction).to_string()),
+    };
+    format!("{} {} {}", tick, action, direction)
+}
+
+/// Parses an input script (see module docs for the format)
+pub fn parse_script(text: &str) -> Result<InputScript, String> {
+    let mut script = InputScript::new();
+    for (line_no, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let input = parse_input(line).map_err(|e| format!("line {}: {}", line_no + 1, e))?;
+        script.push(input);
+    }
+    script.sort_by_key(|(tick, _)| *tick);
+    Ok(script)
+}
+
+/// Runs the level for up to `options.ticks` ticks of `options.dt` ms, applying the scripted inputs.
+/// Inputs for tick `t` are applied after `t` ticks have been simulated.
+pub fn run(levels: &LevelSet, options: &Options) -> Summary {
+    let mut sim = Simulation::new(levels, options.level, options.seed, options.dt);
+    let mut script = options.script.iter().peekable();
+    let mut level_state = LevelState::InProgress;
+
+    while sim.tick() < options.ticks {
+        while let Some((_, input)) = script.next_if(|(t, _)| *t <= sim.tick()) {
+            sim.apply(*input);
+        }
+        level_state = sim.step();
+        if level_state != LevelState::InProgress {
+            break;
+        }
+    }
+
+    Summary::new(&sim, level_state)
+}
+
+fn parse_arg<T: std::str::FromStr>(name: &str, value: Option<&String>) -> Result<T, String> {
+    let value = value.ok_or(format!("Missing value for {}", name))?;
+    value
+        .parse::<T>()
+        .map_err(|_| format!("Invalid value for {}: '{}'", name, value))
+}
+
+/// Parses command line arguments for headless mode:
+/// `--headless [--level N] [--seed N] [--ticks N] [--dt MS] [--script PATH]`
+pub fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--headless" => (),
+            "--level" => options.level = parse_arg("--level", args.next())?,
+            "--seed" => options.seed = Some(parse_arg("--seed", args.next())?),
+            "--ticks" => options.ticks = parse_arg("--ticks", args.next())?,
+            "--dt" => options.dt = validate_dt(parse_arg("--dt", args.next())?)?,
+            "--script" => {
+                let path: String = parse_arg("--script", args.next())?;
+                let text = std::fs::read_to_string(&path)
+                    .map_err(|e| format!("Can't read script '{}': {}", path, e))?;
+                options.script = parse_script(&text)?;
+            }
+            other => return Err(format!("Unknown argument '{}'", other)),
+        }
+    }
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world;
+
+    #[test]
+    fn parse_script_simple() {
+        let text = "# comment\n\n20 move up\n10 fire left\n";
+        let expected = vec![
+            (10, Input::Fire(direction_vector(Direction::Left))),
+            (20, Input::Move(Direction::Up)),
+        ];
+
+        let actual = parse_script(text).unwrap();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn parse_script_invalid() {
+        assert!(parse_script("10 jump left").is_err());
+        assert!(parse_script("10 fire sideways").is_err());
+        assert!(parse_script("ten fire left").is_err());
+        assert!(parse_script("10 fire").is_err());
+        assert!(parse_script("10 fire 1,x").is_err());
+        assert!(parse_script("10 move 1,0").is_err());
+    }
+
+    #[test]
+    fn format_input_roundtrip() {
+        let inputs = [
+            (42, Input::Move(Direction::Down)),
+            (43, Input::Fire(direction_vector(Direction::Up))),
+            (44, Input::Aim((300, -400))),
+            (45, Input::Fire((-1, 7))),
+        ];
+
+        for input in inputs.iter() {
+            let actual = parse_input(&format_input(input.0, input.1)).unwrap();
+
+            assert_eq!(actual, *input);
+        }
+        assert_eq!(format_input(43, inputs[1].1), "43 fire up");
+    }
+
+    #[test]
+    fn parse_args_simple() {
+        let args: Vec<String> = vec!["--headless", "--level", "0", "--ticks", "50", "--dt", "20"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        let options = parse_args(&args).unwrap();
+
+        assert_eq!(options.level, 0);
+        assert_eq!(options.ticks, 50);
+        assert_eq!(options.dt, 20);
+    }
+
+    #[test]
+    fn parse_args_invalid_dt() {
+        for dt in ["0", "-10", "1001", "100000000"].iter() {
+            let args: Vec<String> =
+                vec!["--headless".to_string(), "--dt".to_string(), dt.to_string()];
+
+            assert!(parse_args(&args).is_err(), "dt {}", dt);
+        }
+    }
+
+    #[test]
+    fn run_level0_with_fire() {
+        // Arrange - hardcoded level, fire once to the left
+        let options = Options {
+            level: 0,
+            seed: None,
+            ticks: 10,
+            dt: 20,
+            script: vec![(1, Input::Fire(direction_vector(Direction::Left)))],
+        };
+
+        // Act
+        let summary = run(&LevelSet::builtin(), &options);
+
+        // Assert
+        assert_eq!(summary.ticks_run, 10);
+        assert_eq!(summary.walls, 4);
+        assert_eq!(summary.baddies, 6);
+        assert_eq!(summary.bullets, 1);
+        assert_eq!(summary.player_health, Some(world::PLAYER_HEALTH_MAX));
+    }
+}
@@ -1,10 +1,28 @@
 //! Fundamental helper functions
 
-use std::{collections::HashSet, hash::Hash};
+use rayon::prelude::*;
+use std::{collections::HashSet, hash::Hash, iter::FromIterator};
+
+/// Flat-maps the items and collects the results - spread across rayon's threads if `parallel`,
+/// otherwise on the calling thread (cheaper when there's only a little work).
+pub fn flat_map_collect<C, F, I, R>(items: C, parallel: bool, f: F) -> R
+where
+    C: IntoParallelIterator + IntoIterator<Item = <C as IntoParallelIterator>::Item>,
+    F: Fn(<C as IntoParallelIterator>::Item) -> I + Sync + Send,
+    I: IntoIterator,
+    I::Item: Send,
+    R: FromParallelIterator<I::Item> + FromIterator<I::Item>,
+{
+    if parallel {
+        items.into_par_iter().flat_map_iter(f).collect()
+    } else {
+        items.into_iter().flat_map(f).collect()
+    }
+}
 
 /// Removes multiple elements from a vector, given a collection of indices to remove.
 #[allow(unused)]
-pub fn remove_multiple<T>(vector: &mut Vec<T>, to_remove: &Vec<usize>) {
+pub fn remove_multiple<T>(vector: &mut Vec<T>, to_remove: &[usize]) {
     // As items are removed, their indices will change, so we have to keep track of the new indices.
     let mut to_remove = to_remove.to_vec();
 
@@ -15,14 +33,8 @@ pub fn remove_multiple<T>(vector: &mut Vec<T>, to_remove: &Vec<usize>) {
         vector.swap_remove(tr);
 
         // Find and swap any corresponding to_remove entry*
-        let mut to_swap: Option<usize> = None;
-        for j_tr in 0..to_remove.len() {
-            // *i.e. any index pointing to the previously last item
-            if to_remove[j_tr] == index_last {
-                to_swap = Some(j_tr);
-                break;
-            }
-        }
+        // *i.e. any index pointing to the previously last item
+        let to_swap = to_remove.iter().position(|j_tr| *j_tr == index_last);
         if let Some(to_swap) = to_swap {
             to_remove[to_swap] = tr;
         }
@@ -134,6 +146,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::useless_vec)]
     fn set_eq_test() {
         assert!(set_eq(
             &vec!["foo", "bar", "baz", "beh"],
@@ -1,24 +1,254 @@
-use crate::world::{create_world, GameObject, ObjectFactory, World, GRID_HEIGHT, GRID_WIDTH};
-use rand::{Rng, SeedableRng, StdRng};
+//! Level definitions and procedural level generation
+//!
+//! Levels are either built in, or loaded from TOML files in a level directory (see [`LevelSet::load_dir`]),
+//! so new levels can be added without recompiling. A level file describes either procedural generation parameters:
+//! ```toml
+//! id = 5
+//!
+//! [procedural]
+//! seed = 42  # optional
+//! base_size = 1000
+//! sparsity = 10
+//! wall_pc = 50
+//! baddie_speed = 800
+//! ```
+//! or explicit object placements, with sizes relative to `base_size`:
+//! ```toml
+//! id = 6
+//! base_size = 1000
+//!
+//! [[objects]]
+//! kind = "cannon"
+//! center = [5000, 5000]
+//!
+//! [[objects]]
+//! kind = "baddie"
+//! center = [1000, 1000]
+//! vel = [100, 200]  # units/sec, optional
+//! spin = 0.5        # radians/sec, optional
+//!
+//! [[objects]]
+//! kind = "wall"
+//! center = [5000, 2000]
+//! shape = { rect = { width = 6000, height = 300 } }  # optional, in world units - see `Outline`
+//! ```
+//! Either kind can also choose how collisions are found, e.g. `broad_phase = "aabb_tree"`
+//! (see [`BroadPhaseKind`] - the default is `"grid"`).
+
+use crate::broad_phase::BroadPhaseKind;
+use crate::geometry::{Vector, P};
+use crate::rng::Pcg32;
+use crate::shape::Outline;
+use crate::world::{
+    create_world, set_outline, GameObject, ObjectFactory, World, GRID_HEIGHT, GRID_WIDTH,
+};
+use serde::Deserialize;
 use std::collections::HashMap;
+use std::path::Path;
+
+/// Level number. Some special values: 0 is hardcoded, 99 and -1 are stress testing levels.
+pub type LevelId = i32;
+
+/// Seed for procedural level generation
+pub type Seed = u64;
+
+/// Seed used when a level doesn't specify one
+pub const DEFAULT_SEED: Seed = 1234;
+
+/// Directory scanned for level files at startup
+pub const LEVELS_DIR: &str = "./levels";
 
-struct LevelParams {
+/// Max `base_size * sparsity` a level can have, i.e. spacing between generated objects.
+/// Beyond the world's width, rows are increasingly empty - the built-in levels go up to ~4 widths.
+pub const MAX_SPACING: u32 = GRID_WIDTH * 10;
+
+/// Max `baddie_speed` a level can have, in units per second: enough to cross the world in a second
+pub const MAX_BADDIE_SPEED: u32 = GRID_WIDTH;
+
+/// Parameters for procedurally generating a level
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LevelParams {
+    /// Seed for generating the layout. Can be overridden when starting the level.
+    #[serde(default = "default_seed")]
+    pub seed: Seed,
     /// Base size for the level's objects. 1000 is a good amount
-    base_size: u32,
-    /// Sparsity of generated objects. Valid range from 1 (most dense) to 10 (least dense)  
-    sparsity: u32,
+    pub base_size: u32,
+    /// Sparsity of generated objects. From 1 (most dense) upwards, while `base_size * sparsity` is at most [`MAX_SPACING`].
+    pub sparsity: u32,
     /// % of generated entities that are walls (the rest will be baddies).  
-    wall_pc: u32,
+    pub wall_pc: u32,
 
-    /// Max baddie speed, in units per second. 1000 is a good amount.
-    baddie_speed: u32,
+    /// Max baddie speed, in units per second, up to [`MAX_BADDIE_SPEED`]. 1000 is a good amount.
+    pub baddie_speed: u32,
 
     /// Whether this is a test level (see usages for what effects this has)
-    test: bool
+    #[serde(default)]
+    pub test: bool,
+
+    /// How collisions are found. Given at the top level of level files, rather than in `[procedural]`.
+    #[serde(skip)]
+    pub broad_phase: BroadPhaseKind,
+}
+
+fn default_seed() -> Seed {
+    DEFAULT_SEED
+}
+
+/// Kind of an explicitly placed object
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlacementKind {
+    /// The player. Levels need exactly one.
+    Cannon,
+    /// A wall
+    Wall,
+    /// A baddie
+    Baddie,
+}
+
+/// An explicitly placed object
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Placement {
+    /// What to place
+    pub kind: PlacementKind,
+    /// Where to place it
+    pub center: P,
+    /// Velocity, in units per second (baddies only)
+    #[serde(default)]
+    pub vel: Vector,
+    /// Rotational speed, in radians per second (baddies only)
+    #[serde(default)]
+    pub spin: f32,
+    /// Outline, in world units. By default, a square sized relative to the level's base size, as for its kind.
+    #[serde(default)]
+    pub shape: Option<Outline>,
+}
+
+/// How a level is built
+#[derive(Clone, Debug, PartialEq)]
+pub enum LevelDef {
+    /// Generated from parameters, and a seed
+    Procedural(LevelParams),
+    /// Explicitly placed objects
+    Explicit {
+        /// Base size for the level's objects
+        base_size: u32,
+        /// The objects
+        objects: Vec<Placement>,
+        /// How collisions are found
+        broad_phase: BroadPhaseKind,
+    },
+}
+
+impl LevelDef {
+    /// How collisions are found in the level
+    pub fn broad_phase(&self) -> BroadPhaseKind {
+        match self {
+            LevelDef::Procedural(level_params) => level_params.broad_phase,
+            LevelDef::Explicit { broad_phase, .. } => *broad_phase,
+        }
+    }
+}
+
+/// Level file contents, prior to validation
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LevelFile {
+    id: LevelId,
+    procedural: Option<LevelParams>,
+    base_size: Option<u32>,
+    #[serde(default)]
+    objects: Vec<Placement>,
+    #[serde(default)]
+    broad_phase: BroadPhaseKind,
+}
+
+fn validate_params(params: &LevelParams) -> Result<(), String> {
+    if params.base_size == 0 || params.base_size > GRID_WIDTH {
+        return Err(format!("base_size must be in 1..={}", GRID_WIDTH));
+    }
+    // Objects are spaced up to base_size * sparsity apart
+    let max_spacing = params.base_size as u64 * params.sparsity as u64;
+    if params.sparsity == 0 || max_spacing > MAX_SPACING as u64 {
+        return Err(format!(
+            "sparsity must be in 1..={} (base_size * sparsity must be at most {})",
+            MAX_SPACING / params.base_size,
+            MAX_SPACING
+        ));
+    }
+    if params.wall_pc > 100 {
+        return Err("wall_pc must be in 0..=100".to_string());
+    }
+    if params.baddie_speed > MAX_BADDIE_SPEED {
+        return Err(format!("baddie_speed must be in 0..={}", MAX_BADDIE_SPEED));
+    }
+    Ok(())
+}
+
+fn validate_objects(base_size: u32, objects: &[Placement]) -> Result<(), String> {
+    if base_size == 0 || base_size > GRID_WIDTH {
+        return Err(format!("base_size must be in 1..={}", GRID_WIDTH));
+    }
+    let cannons = objects
+        .iter()
+        .filter(|o| o.kind == PlacementKind::Cannon)
+        .count();
+    if cannons != 1 {
+        return Err(format!("expected exactly 1 cannon, found {}", cannons));
+    }
+    for (i, object) in objects.iter().enumerate() {
+        let (x, y) = object.center;
+        if x < 0 || x > GRID_WIDTH as i32 || y < 0 || y > GRID_HEIGHT as i32 {
+            return Err(format!(
+                "object {} is outside the world: {:?}",
+                i + 1,
+                object.center
+            ));
+        }
+        if object.kind != PlacementKind::Baddie && (object.vel != (0, 0) || object.spin != 0.0) {
+            return Err(format!("object {}: only baddies can move", i + 1));
+        }
+        if let Some(shape) = &object.shape {
+            shape
+                .validate()
+                .map_err(|e| format!("object {}: {}", i + 1, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Parses and validates a level file (see module docs for the format)
+pub fn parse_level(text: &str) -> Result<(LevelId, LevelDef), String> {
+    let file: LevelFile = toml::from_str(text).map_err(|e| e.to_string())?;
+    let def = match (file.procedural, file.base_size, file.objects.is_empty()) {
+        (Some(params), None, true) => {
+            validate_params(&params)?;
+            LevelDef::Procedural(LevelParams {
+                broad_phase: file.broad_phase,
+                ..params
+            })
+        }
+        (None, Some(base_size), false) => {
+            validate_objects(base_size, &file.objects)?;
+            LevelDef::Explicit {
+                base_size,
+                objects: file.objects,
+                broad_phase: file.broad_phase,
+            }
+        }
+        _ => {
+            return Err(
+                "expected either a [procedural] table, or base_size and [[objects]]".to_string(),
+            )
+        }
+    };
+    Ok((file.id, def))
 }
 
 /// Procedurally generates level data.
-fn build_level(obj_factory: &ObjectFactory, level_params: &LevelParams) -> World {
+fn build_level(obj_factory: &ObjectFactory, level_params: &LevelParams, seed: Seed) -> World {
     const MAX_SPIN: i32 = 120;
     let base_size = level_params.base_size as i32;
     let sparsity = level_params.sparsity as i32;
@@ -26,131 +256,483 @@ fn build_level(obj_factory: &ObjectFactory, level_params: &LevelParams) -> World
     let baddie_speed = level_params.baddie_speed as i32;
 
     let mut level_data = Vec::<GameObject>::new();
-    let seed: &[_] = &[1, 2, 3, 4];
-    let mut rng: StdRng = SeedableRng::from_seed(seed);
-    let mut next_random = |lower, upper| rng.gen_range(lower, upper + 1);
+    let mut rng = Pcg32::new(seed);
     let mut cannon = obj_factory.make_cannon((GRID_WIDTH as i32 / 2, GRID_HEIGHT as i32 / 2));
     if level_params.test {
         cannon.3 = Some(1000);
     }
     level_data.push(cannon);
 
+    // Walls, and baddies with their velocity and spin
+    let mut placements = Vec::<(P, Option<(Vector, f32)>)>::new();
+    let random_motion = |rng: &mut Pcg32| {
+        (
+            (
+                rng.gen_range(-baddie_speed, baddie_speed),
+                rng.gen_range(-baddie_speed, baddie_speed),
+            ),
+            rng.gen_range(-MAX_SPIN, MAX_SPIN) as f32 / 100.0,
+        )
+    };
     let mut curr_y = 0;
     while curr_y < GRID_HEIGHT {
         let y_inc = base_size as u32;
         curr_y += y_inc;
         let mut curr_x = 0;
         while curr_x < GRID_WIDTH {
-            let x_inc = next_random(base_size / 2, base_size * sparsity);
+            let x_inc = rng.gen_range(base_size / 2, base_size * sparsity);
             curr_x += x_inc as u32;
-            if next_random(0, 100) < wall_pc {
-                level_data.push(obj_factory.make_wall((curr_x as i32, curr_y as i32)));
+            let center = (curr_x as i32, curr_y as i32);
+            if rng.gen_range(0, 100) < wall_pc {
+                placements.push((center, None));
             } else {
-                level_data.push(obj_factory.make_baddie(
-                    (curr_x as i32, curr_y as i32),
-                    (
-                        next_random(-baddie_speed, baddie_speed),
-                        next_random(-baddie_speed, baddie_speed),
-                    ),
-                    next_random(-MAX_SPIN, MAX_SPIN) as f32 / 100.0,
-                ));
+                placements.push((center, Some(random_motion(&mut rng))));
             }
         }
     }
-    create_world(level_data)
+    // Without baddies, the level would be complete straight away, so make the last wall one instead
+    if placements.iter().all(|(_, motion)| motion.is_none()) {
+        if let Some(last) = placements.last_mut() {
+            last.1 = Some(random_motion(&mut rng));
+        }
+    }
+
+    for (center, motion) in placements {
+        level_data.push(match motion {
+            Some((vel, spin)) => obj_factory.make_baddie(center, vel, spin),
+            None => obj_factory.make_wall(center),
+        });
+    }
+    create_world(obj_factory, level_data)
+}
+
+/// Builds a level from explicit placements
+fn build_explicit(obj_factory: &ObjectFactory, objects: &[Placement]) -> World {
+    let level_data: Vec<GameObject> = objects
+        .iter()
+        .map(|object| {
+            let mut game_obj = match object.kind {
+                PlacementKind::Cannon => obj_factory.make_cannon(object.center),
+                PlacementKind::Wall => obj_factory.make_wall(object.center),
+                PlacementKind::Baddie => {
+                    obj_factory.make_baddie(object.center, object.vel, object.spin)
+                }
+            };
+            if let Some(shape) = &object.shape {
+                set_outline(&mut game_obj, shape.clone());
+            }
+            game_obj
+        })
+        .collect();
+    create_world(obj_factory, level_data)
 }
 
 /// Hardcoded alternative first level
-fn build_level0(obj_factory: &ObjectFactory) -> World {
-    let level_data: Vec<GameObject> = vec![
-        obj_factory.make_cannon((GRID_WIDTH as i32 / 2, GRID_HEIGHT as i32 / 2)),
-        obj_factory.make_wall((2500, 2500)),
-        obj_factory.make_wall((7500, 2500)),
-        obj_factory.make_wall((7500, 7500)),
-        obj_factory.make_wall((2500, 7500)),
-        obj_factory.make_baddie((1000, 1000), (100, 200), 0.5),
-        obj_factory.make_baddie((4000, 2000), (-200, 100), 0.5),
-        obj_factory.make_baddie((6000, 500), (200, 75), 0.5),
-        obj_factory.make_baddie((2000, 6000), (100, -200), 0.5),
-        obj_factory.make_baddie((1500, 9000), (200, 0), 0.5),
-        obj_factory.make_baddie((6500, 7500), (50, -200), 0.5),
-    ];
-
-    create_world(level_data)
-}
-
-pub fn init(level: i32) -> (World, ObjectFactory) {
-    let level_params: HashMap<i32, LevelParams> = vec![
-        (
-            1,
-            LevelParams {
-                base_size: 1500,
-                sparsity: 25,
-                wall_pc: 90,
-                baddie_speed: 600,
-                test: false
-            },
-        ),
-        (
-            2,
-            LevelParams {
-                base_size: 1500,
-                sparsity: 20,
-                wall_pc: 80,
-                baddie_speed: 600,
-                test: false,
-            },
-        ),
-        (
-            3,
-            LevelParams {
-                base_size: 1200,
-                sparsity: 20,
-                wall_pc: 80,
-                baddie_speed: 600,
-                test: false,
-            },
-        ),
-        (
-            4,
-            LevelParams {
-                base_size: 800,
-                sparsity: 8,
-                wall_pc: 25,
-                baddie_speed: 600,
-                test: false,
-            },
-        ),
-        (
-            99,
-            LevelParams {
-                base_size: 100,
-                sparsity: 5,
-                wall_pc: 20,
-                baddie_speed: 600,
-                test: true,
-            },
-        ),
-        (
-            -1,
-            LevelParams {
-                base_size: 20,
-                sparsity: 5,
-                wall_pc: 20,
-                baddie_speed: 600,
-                test: true,
-            },
-        ),
-    ]
-    .into_iter()
-    .collect();
-
-    let default_params = level_params.get(&1).unwrap();
-    let level_params = level_params.get(&level).unwrap_or(default_params);
-    let obj_factory = ObjectFactory::new(level_params.base_size);
-    let world = match level {
-        0 => build_level0(&obj_factory),
-        _ => build_level(&obj_factory, &level_params),
+fn level0() -> LevelDef {
+    let cannon = |center| Placement {
+        kind: PlacementKind::Cannon,
+        center,
+        vel: (0, 0),
+        spin: 0.0,
+        shape: None,
     };
-    (world, obj_factory)
+    let wall = |center| Placement {
+        kind: PlacementKind::Wall,
+        center,
+        vel: (0, 0),
+        spin: 0.0,
+        shape: None,
+    };
+    let baddie = |center, vel, spin| Placement {
+        kind: PlacementKind::Baddie,
+        center,
+        vel,
+        spin,
+        shape: None,
+    };
+    LevelDef::Explicit {
+        base_size: 1500,
+        objects: vec![
+            cannon((GRID_WIDTH as i32 / 2, GRID_HEIGHT as i32 / 2)),
+            wall((2500, 2500)),
+            wall((7500, 2500)),
+            wall((7500, 7500)),
+            wall((2500, 7500)),
+            baddie((1000, 1000), (100, 200), 0.5),
+            baddie((4000, 2000), (-200, 100), 0.5),
+            baddie((6000, 500), (200, 75), 0.5),
+            baddie((2000, 6000), (100, -200), 0.5),
+            baddie((1500, 9000), (200, 0), 0.5),
+            baddie((6500, 7500), (50, -200), 0.5),
+        ],
+        broad_phase: BroadPhaseKind::default(),
+    }
+}
+
+/// The levels available to play
+pub struct LevelSet {
+    levels: HashMap<LevelId, LevelDef>,
+}
+
+impl LevelSet {
+    /// The built-in levels
+    pub fn builtin() -> Self {
+        let procedural = |seed, base_size, sparsity, wall_pc, test| {
+            LevelDef::Procedural(LevelParams {
+                seed,
+                base_size,
+                sparsity,
+                wall_pc,
+                baddie_speed: 600,
+                test,
+                broad_phase: BroadPhaseKind::default(),
+            })
+        };
+        let levels = vec![
+            (0, level0()),
+            (1, procedural(1001, 1500, 25, 90, false)),
+            (2, procedural(2002, 1500, 20, 80, false)),
+            (3, procedural(3003, 1200, 20, 80, false)),
+            (4, procedural(4004, 800, 8, 25, false)),
+            (99, procedural(DEFAULT_SEED, 100, 5, 20, true)),
+            (-1, procedural(DEFAULT_SEED, 20, 5, 20, true)),
+        ]
+        .into_iter()
+        .collect();
+        Self { levels }
+    }
+
+    /// Adds (or replaces) a level
+    pub fn insert(&mut self, level: LevelId, def: LevelDef) {
+        self.levels.insert(level, def);
+    }
+
+    /// Loads all `*.toml` level files in the given directory, replacing any existing levels with the same IDs.
+    /// Invalid files are skipped, and returned as error messages. A missing directory isn't an error.
+    pub fn load_dir(&mut self, dir: &Path) -> Vec<String> {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return vec![],
+        };
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("toml"))
+            .collect();
+        paths.sort();
+
+        let mut errors = vec![];
+        let mut loaded = HashMap::<LevelId, String>::new();
+        for path in paths {
+            let name = path.display().to_string();
+            let result = std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|text| parse_level(&text));
+            match result {
+                Ok((id, _)) if loaded.contains_key(&id) => errors.push(format!(
+                    "{}: level {} is already defined in {}",
+                    name, id, loaded[&id]
+                )),
+                Ok((id, def)) => {
+                    self.insert(id, def);
+                    loaded.insert(id, name);
+                }
+                Err(e) => errors.push(format!("{}: {}", name, e)),
+            }
+        }
+        errors
+    }
+
+    /// Gets a level's definition
+    pub fn get(&self, level: LevelId) -> Option<&LevelDef> {
+        self.levels.get(&level)
+    }
+
+    /// Gets the level that follows the given one, or `None` if it's the last.
+    /// Levels are played in ID order, so the next level is the next ID - if it's defined.
+    pub fn next_level(&self, level: LevelId) -> Option<LevelId> {
+        let next = level + 1;
+        if self.levels.contains_key(&next) {
+            Some(next)
+        } else {
+            None
+        }
+    }
+
+    /// Gets the definition to play for the given level - unknown levels use the definition of level 1.
+    fn get_or_default(&self, level: LevelId) -> &LevelDef {
+        self.levels
+            .get(&level)
+            .unwrap_or_else(|| self.levels.get(&1).unwrap())
+    }
+
+    /// Gets the level's own seed (or [`DEFAULT_SEED`] if it isn't procedurally generated)
+    pub fn seed(&self, level: LevelId) -> Seed {
+        match self.get_or_default(level) {
+            LevelDef::Procedural(level_params) => level_params.seed,
+            LevelDef::Explicit { .. } => DEFAULT_SEED,
+        }
+    }
+
+    /// Builds the world for the given level, along with a factory for creating further objects in it.
+    /// `seed` is used for procedural generation, in place of the level's own (see [`LevelSet::seed`]).
+    /// Unknown levels use the definition of level 1.
+    pub fn init(&self, level: LevelId, seed: Seed) -> (World, ObjectFactory) {
+        let def = self.get_or_default(level);
+        let (mut world, obj_factory) = match def {
+            LevelDef::Procedural(level_params) => {
+                let obj_factory = ObjectFactory::new(level_params.base_size);
+                let world = build_level(&obj_factory, level_params, seed);
+                (world, obj_factory)
+            }
+            LevelDef::Explicit {
+                base_size, objects, ..
+            } => {
+                let obj_factory = ObjectFactory::new(*base_size);
+                (build_explicit(&obj_factory, objects), obj_factory)
+            }
+        };
+        world.broad_phase = def.broad_phase().create();
+        (world, obj_factory)
+    }
+}
+
+/// Builds the world for the given built-in level, along with a factory for creating further objects in it.
+/// Unknown levels use the parameters of level 1.
+pub fn init(level: LevelId) -> (World, ObjectFactory) {
+    let levels = LevelSet::builtin();
+    levels.init(level, levels.seed(level))
+}
+
+/// As [`init`], but with the given seed for procedural generation
+pub fn init_with_seed(level: LevelId, seed: Seed) -> (World, ObjectFactory) {
+    LevelSet::builtin().init(level, seed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::EntityKind;
+    use crate::geometry::Aabb;
+    use crate::replay::world_hash;
+
+    #[test]
+    fn parse_level_procedural() {
+        let text = "id = 5\n[procedural]\nbase_size = 1000\nsparsity = 10\nwall_pc = 50\nbaddie_speed = 800\n";
+        let expected = LevelDef::Procedural(LevelParams {
+            seed: DEFAULT_SEED,
+            base_size: 1000,
+            sparsity: 10,
+            wall_pc: 50,
+            baddie_speed: 800,
+            test: false,
+            broad_phase: BroadPhaseKind::Grid,
+        });
+
+        let actual = parse_level(text).unwrap();
+
+        assert_eq!(actual, (5, expected));
+    }
+
+    #[test]
+    fn parse_level_explicit() {
+        // Arrange
+        let text = r#"
+            id = 6
+            base_size = 1000
+            objects = [
+                { kind = "cannon", center = [5000, 5000] },
+                { kind = "wall", center = [2000, 2000] },
+                { kind = "baddie", center = [1000, 1000], vel = [100, 200], spin = 0.5 },
+            ]
+        "#;
+
+        // Act
+        let (id, def) = parse_level(text).unwrap();
+        let (world, _) = {
+            let mut levels = LevelSet::builtin();
+            levels.insert(id, def);
+            levels.init(id, DEFAULT_SEED)
+        };
+
+        // Assert
+        assert_eq!(id, 6);
+        assert_eq!(world.entities.len(), 3);
+    }
+
+    #[test]
+    fn parse_level_broad_phase() {
+        // Arrange
+        let procedural = "id = 5\nbroad_phase = \"aabb_tree\"\n[procedural]\nbase_size = 1000\nsparsity = 10\nwall_pc = 50\nbaddie_speed = 800\n";
+        let explicit = "id = 6\nbroad_phase = \"sweep_and_prune\"\nbase_size = 1000\nobjects = [{ kind = \"cannon\", center = [5000, 5000] }]";
+        let unknown = explicit.replace("sweep_and_prune", "octree");
+        let misplaced = "id = 5\n[procedural]\nbroad_phase = \"grid\"\nbase_size = 1000\nsparsity = 10\nwall_pc = 50\nbaddie_speed = 800\n";
+
+        // Act
+        let (_, procedural) = parse_level(procedural).unwrap();
+        let (_, explicit) = parse_level(explicit).unwrap();
+
+        // Assert
+        assert_eq!(procedural.broad_phase(), BroadPhaseKind::AabbTree);
+        assert_eq!(explicit.broad_phase(), BroadPhaseKind::SweepAndPrune);
+        assert!(parse_level(&unknown).is_err());
+        assert!(parse_level(misplaced).is_err());
+    }
+
+    #[test]
+    fn parse_level_shapes() {
+        // Arrange
+        let text = r#"
+            id = 6
+            base_size = 1000
+            objects = [
+                { kind = "cannon", center = [5000, 5000] },
+                { kind = "wall", center = [5000, 2000], shape = { rect = { width = 6000, height = 300 } } },
+                { kind = "baddie", center = [1000, 1000], shape = { polygon = [[0, -300], [300, 300], [-300, 300]] } },
+                { kind = "baddie", center = [9000, 1000], shape = { circle = { radius = 200 } } },
+            ]
+        "#;
+        let concave = text.replace(
+            "[[0, -300], [300, 300], [-300, 300]]",
+            "[[0, 0], [300, 300], [0, 100], [-300, 300]]",
+        );
+        let empty = text.replace("radius = 200", "radius = 0");
+
+        // Act
+        let (id, def) = parse_level(text).unwrap();
+        let (world, _) = {
+            let mut levels = LevelSet::builtin();
+            levels.insert(id, def);
+            levels.init(id, DEFAULT_SEED)
+        };
+
+        // Assert
+        // From left to right - the triangle, the wall, the cannon, then the circle
+        let mut aabbs: Vec<Aabb> = world.geometries.values().map(|geom| geom.aabb()).collect();
+        aabbs.sort_by_key(|aabb| aabb.min);
+        assert_eq!(aabbs[0].min, (700, 700));
+        assert_eq!(aabbs[0].max, (1300, 1300));
+        assert_eq!(aabbs[1].min, (2000, 1850));
+        assert_eq!(aabbs[1].max, (8000, 2150));
+        assert_eq!(aabbs[3].min, (8800, 800));
+        assert!(parse_level(&concave).is_err());
+        assert!(parse_level(&empty).is_err());
+    }
+
+    #[test]
+    fn builtin_params_valid() {
+        for (id, def) in LevelSet::builtin().levels.iter() {
+            if let LevelDef::Procedural(params) = def {
+                assert_eq!(validate_params(params), Ok(()), "level {}", id);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_level_invalid() {
+        let procedural =
+            "[procedural]\nbase_size = 1000\nsparsity = 10\nwall_pc = 50\nbaddie_speed = 800\n";
+        // Missing id
+        assert!(parse_level(procedural).is_err());
+        // Out of range
+        assert!(parse_level(&format!("id = 5\n{}", procedural.replace("50", "150"))).is_err());
+        // Spacing too big
+        let text = procedural.replace("sparsity = 10", "sparsity = 10000000");
+        assert!(parse_level(&format!("id = 5\n{}", text)).is_err());
+        // Too fast
+        let text = procedural.replace("baddie_speed = 800", "baddie_speed = 3000000000");
+        assert!(parse_level(&format!("id = 5\n{}", text)).is_err());
+        // Unknown field
+        assert!(parse_level(&format!("id = 5\nfoo = 1\n{}", procedural)).is_err());
+        // No cannon
+        let text = "id = 6\nbase_size = 1000\nobjects = [{ kind = \"wall\", center = [1, 1] }]";
+        assert!(parse_level(text).is_err());
+        // Outside world
+        let text = "id = 6\nbase_size = 1000\nobjects = [{ kind = \"cannon\", center = [-1, 1] }]";
+        assert!(parse_level(text).is_err());
+        // Neither procedural nor explicit
+        assert!(parse_level("id = 6").is_err());
+    }
+
+    #[test]
+    fn load_dir_skips_invalid() {
+        // Arrange
+        let dir = std::env::temp_dir().join(format!("bwb-levels-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let valid = "id = 42\n[procedural]\nbase_size = 1000\nsparsity = 10\nwall_pc = 50\nbaddie_speed = 800\n";
+        std::fs::write(dir.join("a.toml"), valid).unwrap();
+        std::fs::write(dir.join("b.toml"), "id = 43\n").unwrap();
+        std::fs::write(dir.join("c.txt"), "not a level").unwrap();
+        let mut levels = LevelSet::builtin();
+
+        // Act
+        let errors = levels.load_dir(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Assert
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("b.toml"));
+        assert!(levels.get(42).is_some());
+        assert!(levels.get(43).is_none());
+    }
+
+    /// Golden test - pins the layout generated from a seed, which should be identical on every platform.
+    /// If this fails after an intentional change to generation, existing replays will no longer verify.
+    #[test]
+    fn seeded_layout_golden() {
+        let levels = LevelSet::builtin();
+
+        let (world, _) = levels.init(2, 2002);
+
+        let count = |kind| world.count(kind);
+        assert_eq!(count(EntityKind::Wall), 4);
+        assert_eq!(count(EntityKind::Baddie), 3);
+        // Rotations are hashed in the physics number type (see `fixed`)
+        #[cfg(not(feature = "fixed-point"))]
+        assert_eq!(world_hash(&world), 0x9c28_9735_12ab_97f3);
+        #[cfg(feature = "fixed-point")]
+        assert_eq!(world_hash(&world), 0x93b9_f06d_149e_40a6);
+    }
+
+    #[test]
+    fn builtin_levels_have_baddies() {
+        let levels = LevelSet::builtin();
+
+        for id in levels.levels.keys() {
+            let (world, _) = levels.init(*id, levels.seed(*id));
+
+            assert!(world.count(EntityKind::Baddie) > 0, "level {}", id);
+        }
+        // Even with a seed that only generates walls
+        let (world, _) = levels.init(1, 1001);
+        assert_eq!(world.count(EntityKind::Baddie), 1);
+    }
+
+    #[test]
+    fn different_seeds_different_layouts() {
+        let levels = LevelSet::builtin();
+
+        let (world1, _) = levels.init(2, 1);
+        let (world2, _) = levels.init(2, 2);
+
+        assert_ne!(world_hash(&world1), world_hash(&world2));
+    }
+
+    #[test]
+    fn next_level_stops_at_last() {
+        let levels = LevelSet::builtin();
+
+        assert_eq!(levels.next_level(0), Some(1));
+        assert_eq!(levels.next_level(3), Some(4));
+        assert_eq!(levels.next_level(4), None);
+        assert_eq!(levels.next_level(99), None);
+    }
+
+    /// The level files shipped in the repo should all be valid
+    #[test]
+    fn shipped_levels_valid() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(LEVELS_DIR);
+        let errors = LevelSet::builtin().load_dir(&dir);
+
+        assert_eq!(errors, Vec::<String>::new());
+    }
 }
@@ -0,0 +1,43 @@
+//! # Bullets, Walls and Baddies
+//! Game simulation library: world state, game rules, collision detection and level generation.
+//!
+//! The SDL front end lives in the `bwb` binary (behind the `sdl` cargo feature), so the simulation
+//! can be reused by tools, tests and the headless runner without a display or SDL installed.
+//!
+//! The main entry points are re-exported at the crate root:
+//! * [`levels::init`] builds a [`World`] and its [`ObjectFactory`] for a level
+//! * [`update_world`] advances the simulation by a time-step, by running the game's [`Systems`]
+//! * [`CollisionSystem`] detects collisions between object geometries, using one of several [`BroadPhase`]s
+//! * [`spatial_query`] adds ray casts, and point and box queries, to the [`World`]
+//! * [`geometry`] has the underlying primitives, e.g. [`is_collision`]
+//! * [`fixed`] has the physics' number type, [`Real`] - `f32`, or fixed-point with the `fixed-point` feature
+#![warn(missing_docs)]
+
+pub mod broad_phase;
+pub mod collision_system;
+pub mod entity;
+pub mod fixed;
+pub mod game_logic;
+pub mod geometry;
+pub mod headless;
+mod helpers;
+pub mod levels;
+pub mod replay;
+pub mod rng;
+pub mod shape;
+pub mod simulation;
+pub mod spatial_query;
+pub mod timestep;
+pub mod world;
+
+pub use broad_phase::{BroadPhase, BroadPhaseKind};
+pub use collision_system::{CollisionDebug, CollisionEvent, CollisionKind, CollisionSystem};
+pub use entity::{Entity, EntityAllocator, EntityId, EntityKind};
+pub use fixed::{Fixed, Real, Scalar};
+pub use game_logic::{aim_cannon, move_cannon, try_fire, update_world, LevelState};
+pub use geometry::{is_collision, rotate, scale, Direction, Geometry, Vector, Vertex, P};
+pub use levels::LevelId;
+pub use shape::{Outline, Shape};
+pub use world::{
+    Component, GameObject, ObjectFactory, Query, Systems, World, GRID_HEIGHT, GRID_WIDTH,
+};
@@ -1,22 +1,81 @@
 //! # Bullets, Walls and Baddies v1  
-extern crate sdl2;
-extern crate rayon;
-extern crate itertools;
-
-mod collision_system;
+//! Front end. The SDL game is behind the `sdl` feature; headless and replay modes are always available.
+#[cfg(feature = "sdl")]
 mod engine;
-mod entity;
-mod game_logic;
-mod geometry;
-mod helpers;
-mod levels;
+#[cfg(feature = "sdl")]
 mod render;
-mod shape;
+#[cfg(feature = "sdl")]
 mod text;
-mod world;
+
+use bwb::headless;
+use bwb::levels::{LevelSet, LEVELS_DIR};
+use bwb::replay::{self, Replay};
+use std::path::Path;
 
 pub fn main() {
     // single threaded for debugging
     //rayon::ThreadPoolBuilder::new().num_threads(1).build_global().unwrap();
-    engine::run();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let levels = load_levels();
+    if args.iter().any(|arg| arg == "--headless") {
+        match headless::parse_args(&args) {
+            Ok(options) => println!("{}", headless::run(&levels, &options)),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    } else if let Some(i) = args.iter().position(|arg| arg == "--replay") {
+        run_replay(&levels, args.get(i + 1));
+    } else {
+        run_game(levels, &args);
+    }
+}
+
+/// Loads the built-in levels, plus any in the level directory
+fn load_levels() -> LevelSet {
+    let mut levels = LevelSet::builtin();
+    for error in levels.load_dir(Path::new(LEVELS_DIR)) {
+        eprintln!("Skipping level file {}", error);
+    }
+    levels
+}
+
+/// Verifies a replay file, reporting the first tick at which it diverges.
+fn run_replay(levels: &LevelSet, path: Option<&String>) {
+    let replay = match path.map(|path| Replay::load(path)) {
+        Some(Ok(replay)) => replay,
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        None => {
+            eprintln!("Missing value for --replay");
+            std::process::exit(1);
+        }
+    };
+    match replay::verify(levels, &replay) {
+        Ok(ticks) => println!("Replay OK: {} ticks verified", ticks),
+        Err(divergence) => {
+            eprintln!("Replay {}", divergence);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(feature = "sdl")]
+fn run_game(levels: LevelSet, args: &[String]) {
+    match engine::parse_args(args) {
+        Ok(options) => engine::run(levels, options),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(feature = "sdl"))]
+fn run_game(_levels: LevelSet, _args: &[String]) {
+    eprintln!("Built without the `sdl` feature - only --headless mode is available.");
+    std::process::exit(1);
 }
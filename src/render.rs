@@ -1,22 +1,34 @@
 use crate::text::Font;
 use sdl2::pixels::Color;
 use sdl2::rect::{Point, Rect};
-use sdl2::render;
+use sdl2::render::{self, BlendMode};
 
 use std::collections::HashMap;
 
-use crate::entity::EntityKind;
-use crate::geometry::Vertex;
+use bwb::collision_system::{CollisionDebug, CollisionEvent};
+use bwb::entity::EntityKind;
+use bwb::geometry::{translate, Vertex};
+use bwb::world::{Entities, Geometries, Healths, GRID_HEIGHT, GRID_WIDTH, PLAYER_HEALTH_MAX};
+
 use crate::text;
-use crate::world::{Entities, Geometries, Healths, GRID_HEIGHT, GRID_WIDTH, PLAYER_HEALTH_MAX};
 
-// Screen coordinate bounds.
+// Screen coordinate bounds. Same aspect ratio as the world, so it isn't stretched.
 const WIN_WIDTH: u32 = 600;
-const WIN_HEIGHT: u32 = 600;
+const WIN_HEIGHT: u32 = (WIN_WIDTH as u64 * GRID_HEIGHT as u64 / GRID_WIDTH as u64) as u32;
 
 // TODO: Parameterize
 const TEXT_COLOR: Color = Color::RGBA(255, 80, 255, 255);
 const TEXT_LINE_PADDING: u32 = 30;
+const TEXT_MARGIN: u32 = 20;
+// Opacity of the overlay drawn by `dim`
+const DIM_ALPHA: u8 = 160;
+
+// Debug overlay colors. Bins get more opaque the more objects they hold, up to the max.
+const GRID_LINE_COLOR: Color = Color::RGB(50, 50, 50);
+const BIN_COLOR: (u8, u8, u8) = (0, 140, 255);
+const BIN_ALPHA_PER_OBJECT: usize = 24;
+const BIN_ALPHA_MAX: usize = 160;
+const COLLIDING_COLOR: Color = Color::WHITE;
 
 type Canvas = sdl2::render::Canvas<sdl2::video::Window>;
 
@@ -31,11 +43,19 @@ fn world_to_screen(coords: &(i32, i32)) -> (i32, i32) {
     (sx as i32, sy as i32)
 }
 
+/// Converts screen coordinates (e.g. of the mouse) to world coordinates
+pub fn screen_to_world(coords: &(i32, i32)) -> (i32, i32) {
+    let (sx, sy) = *coords;
+    let wx = sx as f32 * GRID_WIDTH as f32 / WIN_WIDTH as f32;
+    let wy = sy as f32 * GRID_HEIGHT as f32 / WIN_HEIGHT as f32;
+    (wx as i32, wy as i32)
+}
+
 fn render_box(canvas: &mut render::WindowCanvas, box_geometry: &[Vertex], color: Color) {
     // COULDDO: Way to avoid reallocating here? (E.g. re-use existing render vec)
     let points: Vec<Point> = box_geometry
         .iter()
-        .map(|p| world_to_screen(p))
+        .map(world_to_screen)
         .map(|p| Point::new(p.0, p.1))
         .collect();
 
@@ -78,6 +98,8 @@ fn v_center(height: u32) -> i32 {
 pub struct Renderer<'ttf_context> {
     canvas: Canvas,
     font: Font<'ttf_context>,
+    /// Whether to draw the collision debug overlay, see `render_collision_debug`
+    debug_overlay: bool,
 }
 
 impl<'ttf_context> Renderer<'ttf_context> {
@@ -92,13 +114,13 @@ impl<'ttf_context> Renderer<'ttf_context> {
         Renderer {
             canvas: window.into_canvas().build().unwrap(),
             font,
+            debug_overlay: false,
         }
     }
 
     /// Render the scene described by the objects.
     pub fn render(&mut self, entities: &Entities, geometries: &Geometries, healths: &Healths) {
-        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
-        self.canvas.clear();
+        self.clear();
         let colors: HashMap<EntityKind, Color> = [
             (EntityKind::Bullet, Color::RGB(74, 143, 255)),
             (EntityKind::Wall, Color::RGB(232, 225, 81)),
@@ -108,12 +130,17 @@ impl<'ttf_context> Renderer<'ttf_context> {
         .iter()
         .cloned()
         .collect();
+        let world_size = (GRID_WIDTH as i32, GRID_HEIGHT as i32);
         for entity in entities {
-            render_box(
-                &mut self.canvas,
-                geometries.get(&entity.get_id()).unwrap(),
-                *colors.get(entity.get_kind()).unwrap(),
-            );
+            let geometry = geometries.get(&entity.get_id()).unwrap();
+            let color = *colors.get(entity.get_kind()).unwrap();
+            // Circles are drawn as polygons
+            let polygon = geometry.to_polygon();
+            render_box(&mut self.canvas, &polygon, color);
+            // The world wraps around, so anything hanging over an edge also appears at the opposite edge
+            for offset in geometry.aabb().wrap_offsets(world_size) {
+                render_box(&mut self.canvas, &translate(&polygon, offset), color);
+            }
         }
         let health = healths.iter().last();
         if let Some((_, health)) = health {
@@ -121,14 +148,108 @@ impl<'ttf_context> Renderer<'ttf_context> {
         }
     }
 
+    /// Whether the collision debug overlay is showing
+    pub fn debug_overlay(&self) -> bool {
+        self.debug_overlay
+    }
+
+    pub fn toggle_debug_overlay(&mut self) {
+        self.debug_overlay = !self.debug_overlay;
+    }
+
+    /// Draws what the collision system found over the scene: the grid's bins, shaded by how many objects are in them,
+    /// the objects colliding this frame, and the number of candidate and colliding pairs of each kind.
+    pub fn render_collision_debug(
+        &mut self,
+        debug: &CollisionDebug,
+        geometries: &Geometries,
+        collisions: &[CollisionEvent],
+    ) {
+        if let Some(grid) = &debug.grid {
+            let bin_size = grid.bin_size;
+            self.canvas.set_draw_color(GRID_LINE_COLOR);
+            for x in (0..=GRID_WIDTH as i32).step_by(bin_size as usize) {
+                let (top, bottom) = (
+                    world_to_screen(&(x, 0)),
+                    world_to_screen(&(x, GRID_HEIGHT as i32)),
+                );
+                self.canvas.draw_line(top, bottom).unwrap();
+            }
+            for y in (0..=GRID_HEIGHT as i32).step_by(bin_size as usize) {
+                let (left, right) = (
+                    world_to_screen(&(0, y)),
+                    world_to_screen(&(GRID_WIDTH as i32, y)),
+                );
+                self.canvas.draw_line(left, right).unwrap();
+            }
+
+            self.canvas.set_blend_mode(BlendMode::Blend);
+            let (r, g, b) = BIN_COLOR;
+            for ((column, row), count) in grid.counts.iter() {
+                let alpha = (count * BIN_ALPHA_PER_OBJECT).min(BIN_ALPHA_MAX) as u8;
+                let min = world_to_screen(&(column * bin_size, row * bin_size));
+                let max = world_to_screen(&((column + 1) * bin_size, (row + 1) * bin_size));
+                self.canvas.set_draw_color(Color::RGBA(r, g, b, alpha));
+                self.canvas
+                    .fill_rect(Rect::new(
+                        min.0,
+                        min.1,
+                        (max.0 - min.0) as u32,
+                        (max.1 - min.1) as u32,
+                    ))
+                    .unwrap();
+            }
+            self.canvas.set_blend_mode(BlendMode::None);
+        }
+
+        // Objects may have been removed in response (e.g. hit baddies), so are no longer drawn
+        let colliding = collisions
+            .iter()
+            .flat_map(|event| [event.left, event.right]);
+        for id in colliding {
+            if let Some(geometry) = geometries.get(&id) {
+                render_box(&mut self.canvas, &geometry.to_polygon(), COLLIDING_COLOR);
+            }
+        }
+
+        let lines: Vec<String> = debug
+            .pairs
+            .iter()
+            .map(|pairs| {
+                format!(
+                    "{:?}-{:?}: {} candidates, {} colliding",
+                    pairs.kind.0, pairs.kind.1, pairs.candidates, pairs.collisions
+                )
+            })
+            .collect();
+        let lines: Vec<text::Line> = lines
+            .iter()
+            .map(|line| (line.as_str(), text::Size::Small))
+            .collect();
+        self.draw_text_n(&lines, text::Position::BottomLeft);
+    }
+
+    /// Clears the screen, e.g. before drawing text-only screens
+    pub fn clear(&mut self) {
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.clear();
+    }
+
+    /// Darkens whatever has been drawn so far, e.g. to show a menu over the scene
+    pub fn dim(&mut self) {
+        self.canvas.set_blend_mode(BlendMode::Blend);
+        self.canvas.set_draw_color(Color::RGBA(0, 0, 0, DIM_ALPHA));
+        self.canvas.fill_rect(None).unwrap();
+        self.canvas.set_blend_mode(BlendMode::None);
+    }
+
     pub fn present(&mut self) {
         self.canvas.present();
     }
 
-    pub fn draw_text_n(&mut self, lines: &Vec<text::Line>, _position: text::Position) {
+    pub fn draw_text_n(&mut self, lines: &Vec<text::Line>, position: text::Position) {
         // Would be good to extract this, but we can't reference it, as the return type is private.
         // Also holds references captured by closure.
-        // TODO: handling of position param
         let texture_creator = self.canvas.texture_creator();
         let mut textures: Vec<(sdl2::render::Texture, u32, u32)> = vec![];
         for (text, size) in lines {
@@ -150,14 +271,22 @@ impl<'ttf_context> Renderer<'ttf_context> {
             + TEXT_LINE_PADDING * textures.len() as u32
             - 1;
 
-        let mut curr_y = v_center(total_height) as u32; // TODO: choose based on parameter
+        let mut curr_y = match position {
+            text::Position::CenterScreen => v_center(total_height) as u32,
+            text::Position::TopRight => TEXT_MARGIN,
+            text::Position::BottomLeft => WIN_HEIGHT - TEXT_MARGIN - total_height,
+        };
 
         for (texture, width, height) in textures {
-            let x = h_center(width); // TODO: choose based on parameter
+            let x = match position {
+                text::Position::CenterScreen => h_center(width),
+                text::Position::TopRight => (WIN_WIDTH - TEXT_MARGIN) as i32 - width as i32,
+                text::Position::BottomLeft => TEXT_MARGIN as i32,
+            };
             let y = curr_y;
             curr_y += height + TEXT_LINE_PADDING;
             let target = Rect::new(x, y as i32, width, height);
-            &self.canvas.copy(&texture, None, Some(target)).unwrap();
+            self.canvas.copy(&texture, None, Some(target)).unwrap();
         }
     }
 }
@@ -0,0 +1,410 @@
+//! # Record and replay
+//! A replay captures everything needed to reproduce a level exactly: the level, the seed it was generated with,
+//! the tick length, the physics mode, and the player inputs at each tick. It also stores a hash of the world after each tick, so that
+//! playback can be verified, pinpointing the first tick at which the simulation diverged.
+//!
+//! File format (text, line based):
+//! ```text
+//! bwb-replay 2
+//! level 1
+//! seed 1234
+//! dt 10
+//! physics float
+//! input 25 fire left
+//! hash 1 9f2a6c0e4b1d3357
+//! hash 2 ...
+//! ```
+//! Inputs use the headless script syntax (`<tick> <action> <direction>`); hashes are listed for every tick, in order.
+//! Physics is `float`, or `fixed` with the `fixed-point` feature. World hashes differ between the two, so a replay
+//! only loads in a build with the physics it was recorded with.
+
+use crate::entity::EntityKind;
+use crate::headless::{format_input, parse_input, InputScript};
+use crate::levels::{LevelId, LevelSet, Seed};
+use crate::simulation::{validate_dt, Input, Simulation};
+use crate::world::World;
+use std::fmt;
+
+/// Replay file format version. Bump on incompatible changes to the file format or to the world hash.
+pub const FORMAT_VERSION: u32 = 2;
+
+const HEADER: &str = "bwb-replay";
+
+/// This build's physics mode, as recorded in replays (see `fixed`)
+pub const PHYSICS: &str = if cfg!(feature = "fixed-point") {
+    "fixed"
+} else {
+    "float"
+};
+
+/// A recorded session of a single level
+#[derive(Debug, PartialEq)]
+pub struct Replay {
+    /// Level played
+    pub level: LevelId,
+    /// Seed the level was generated with
+    pub seed: Seed,
+    /// Tick length, in ms
+    pub dt: i32,
+    /// Inputs, by tick
+    pub inputs: InputScript,
+    /// World hash after each tick (the first entry is after tick 1)
+    pub hashes: Vec<u64>,
+}
+
+/// Where playback first differed from the recording
+#[derive(Debug, PartialEq)]
+pub struct Divergence {
+    /// Number of ticks simulated when the world hash differed
+    pub tick: u32,
+    /// Recorded hash
+    pub expected: u64,
+    /// Hash on playback
+    pub actual: u64,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "diverged at tick {} (expected hash {:016x}, got {:016x})",
+            self.tick, self.expected, self.actual
+        )
+    }
+}
+
+impl Replay {
+    /// Creates an empty replay, to record into
+    pub fn new(level: LevelId, seed: Seed, dt: i32) -> Self {
+        Self {
+            level,
+            seed,
+            dt,
+            inputs: InputScript::new(),
+            hashes: Vec::new(),
+        }
+    }
+
+    /// Serializes to the replay file format (see module docs)
+    pub fn serialize(&self) -> String {
+        let mut lines = vec![
+            format!("{} {}", HEADER, FORMAT_VERSION),
+            format!("level {}", self.level),
+            format!("seed {}", self.seed),
+            format!("dt {}", self.dt),
+            format!("physics {}", PHYSICS),
+        ];
+        for (tick, input) in &self.inputs {
+            lines.push(format!("input {}", format_input(*tick, *input)));
+        }
+        for (i, hash) in self.hashes.iter().enumerate() {
+            lines.push(format!("hash {} {:016x}", i + 1, hash));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    /// Parses the replay file format (see module docs)
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().enumerate();
+        match lines
+            .next()
+            .map(|(_, line)| line.split_whitespace().collect::<Vec<_>>())
+        {
+            Some(header) if header.len() == 2 && header[0] == HEADER => {
+                if header[1] != FORMAT_VERSION.to_string() {
+                    return Err(format!(
+                        "Unsupported replay version {} (expected {})",
+                        header[1], FORMAT_VERSION
+                    ));
+                }
+            }
+            _ => return Err("Not a replay file".to_string()),
+        }
+
+        let mut level = None;
+        let mut seed = None;
+        let mut dt = None;
+        let mut physics = None;
+        let mut replay = Replay::new(0, 0, 0);
+        for (line_no, line) in lines {
+            let err = |msg: String| format!("line {}: {}", line_no + 1, msg);
+            let (key, value) = line.split_at(line.find(' ').unwrap_or(line.len()));
+            let value = value.trim();
+            match key {
+                "level" => {
+                    level = Some(
+                        value
+                            .parse()
+                            .map_err(|_| err(format!("bad level '{}'", value)))?,
+                    )
+                }
+                "seed" => {
+                    seed = Some(
+                        value
+                            .parse()
+                            .map_err(|_| err(format!("bad seed '{}'", value)))?,
+                    )
+                }
+                "dt" => {
+                    let value = value
+                        .parse()
+                        .map_err(|_| err(format!("bad dt '{}'", value)))?;
+                    dt = Some(validate_dt(value).map_err(err)?)
+                }
+                "physics" => match value {
+                    "float" | "fixed" if value != PHYSICS => {
+                        return Err(err(format!(
+                            "recorded with {} physics, but this build has {} physics (see the `fixed-point` feature)",
+                            value, PHYSICS
+                        )))
+                    }
+                    "float" | "fixed" => physics = Some(value),
+                    _ => return Err(err(format!("bad physics '{}'", value))),
+                },
+                "input" => replay.inputs.push(parse_input(value).map_err(err)?),
+                "hash" => {
+                    let parts: Vec<&str> = value.split_whitespace().collect();
+                    let expected_tick = replay.hashes.len() + 1;
+                    if parts.len() != 2 || parts[0] != expected_tick.to_string() {
+                        return Err(err(format!("expected 'hash {} <hex>'", expected_tick)));
+                    }
+                    let hash = u64::from_str_radix(parts[1], 16)
+                        .map_err(|_| err(format!("bad hash '{}'", parts[1])))?;
+                    replay.hashes.push(hash);
+                }
+                "" => (),
+                other => return Err(err(format!("unknown entry '{}'", other))),
+            }
+        }
+        replay.level = level.ok_or("Missing level")?;
+        replay.seed = seed.ok_or("Missing seed")?;
+        replay.dt = dt.ok_or("Missing dt")?;
+        physics.ok_or("Missing physics")?;
+        replay.inputs.sort_by_key(|(tick, _)| *tick);
+        Ok(replay)
+    }
+
+    /// Writes the replay to a file
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        std::fs::write(path, self.serialize())
+            .map_err(|e| format!("Can't write replay '{}': {}", path, e))
+    }
+
+    /// Reads a replay from a file
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Can't read replay '{}': {}", path, e))?;
+        Self::parse(&text)
+    }
+
+    /// Records an input applied at the given tick
+    pub fn record_input(&mut self, tick: u32, input: Input) {
+        self.inputs.push((tick, input));
+    }
+
+    /// Records the world state after a tick
+    pub fn record_tick(&mut self, world: &World) {
+        self.hashes.push(world_hash(world));
+    }
+}
+
+/// Plays back the replay, checking the world hash after every tick.
+/// Returns the number of ticks verified, or where the playback diverged.
+/// `levels` should define the level as it was when recorded.
+pub fn verify(levels: &LevelSet, replay: &Replay) -> Result<u32, Divergence> {
+    let mut sim = Simulation::new(levels, replay.level, Some(replay.seed), replay.dt);
+    let mut inputs = replay.inputs.iter().peekable();
+    for expected in &replay.hashes {
+        while let Some((_, input)) = inputs.next_if(|(t, _)| *t <= sim.tick()) {
+            sim.apply(*input);
+        }
+        sim.step();
+        let actual = world_hash(sim.world());
+        if actual != *expected {
+            return Err(Divergence {
+                tick: sim.tick(),
+                expected: *expected,
+                actual,
+            });
+        }
+    }
+    Ok(sim.tick())
+}
+
+fn kind_code(kind: &EntityKind) -> u8 {
+    match kind {
+        EntityKind::Baddie => 1,
+        EntityKind::Wall => 2,
+        EntityKind::Bullet => 3,
+        EntityKind::Cannon => 4,
+        EntityKind::UNDEFINED => 0,
+    }
+}
+
+/// 64-bit FNV-1a. Used rather than `DefaultHasher`, whose output may change between Rust versions.
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Hashes the world state, independently of iteration order.
+/// Entity IDs are excluded, as they're an implementation detail of the allocator (freed slots get reused).
+pub fn world_hash(world: &World) -> u64 {
+    let World {
+        entities,
+        shapes,
+        healths,
+        ..
+    } = world;
+    let mut records: Vec<_> = entities
+        .iter()
+        .map(|entity| {
+            let id = entity.get_id();
+            let shape = shapes.get(&id).unwrap();
+            let (cx, cy) = *shape.get_center();
+            let (vx, vy) = *shape.get_vel();
+            let health = healths.get(&id).cloned().unwrap_or(i32::MIN);
+            let rotation = shape.get_rotation().to_bits();
+            (
+                kind_code(entity.get_kind()),
+                cx,
+                cy,
+                vx,
+                vy,
+                rotation,
+                health,
+            )
+        })
+        .collect();
+    records.sort_unstable();
+
+    records.iter().fold(0xcbf2_9ce4_8422_2325, |hash, record| {
+        let (kind, cx, cy, vx, vy, rotation, health) = *record;
+        let hash = fnv1a(hash, &[kind]);
+        [cx, cy, vx, vy, health]
+            .iter()
+            .fold(fnv1a(hash, &rotation.to_le_bytes()), |hash, v| {
+                fnv1a(hash, &v.to_le_bytes())
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{direction_vector, Direction};
+
+    fn record(level: LevelId, ticks: u32, inputs: &[(u32, Input)]) -> Replay {
+        let mut sim = Simulation::new(&LevelSet::builtin(), level, Some(42), 10);
+        sim.record();
+        let mut inputs = inputs.iter().peekable();
+        while sim.tick() < ticks {
+            while let Some((_, input)) = inputs.next_if(|(t, _)| *t <= sim.tick()) {
+                sim.apply(*input);
+            }
+            sim.step();
+        }
+        sim.take_recording().unwrap()
+    }
+
+    /// Golden test - pins a fixed-point simulation, with spinning, bouncing baddies and bullets, which should be
+    /// bit-identical on every platform. If this fails after an intentional change to the physics, existing replays
+    /// will no longer verify.
+    #[cfg(feature = "fixed-point")]
+    #[test]
+    fn fixed_point_simulation_golden() {
+        let inputs = [
+            (10, Input::Fire(direction_vector(Direction::Left))),
+            (50, Input::Move(Direction::Up)),
+            (120, Input::Aim((700, 300))),
+            (200, Input::Fire(direction_vector(Direction::Down))),
+            (400, Input::Fire((-3, 4))),
+        ];
+
+        let replay = record(1, 1000, &inputs);
+
+        assert_eq!(replay.hashes.last(), Some(&0x7837_6a8e_8c20_bde3));
+    }
+
+    #[test]
+    fn serialize_roundtrip() {
+        let replay = record(
+            0,
+            20,
+            &[(3, Input::Fire(direction_vector(Direction::Left)))],
+        );
+
+        let actual = Replay::parse(&replay.serialize()).unwrap();
+
+        assert_eq!(actual, replay);
+    }
+
+    #[test]
+    fn parse_rejects_other_version() {
+        let text = "bwb-replay 999\nlevel 1\nseed 1\ndt 10\n";
+
+        assert!(Replay::parse(text).is_err());
+    }
+
+    #[test]
+    fn parse_rejects_invalid_dt() {
+        let replay = record(0, 20, &[]);
+
+        for dt in ["0", "-10", "100000000"].iter() {
+            let text = replay.serialize().replace("dt 10", &format!("dt {}", dt));
+
+            assert!(Replay::parse(&text).is_err(), "dt {}", dt);
+        }
+    }
+
+    #[test]
+    fn parse_rejects_other_physics() {
+        let replay = record(0, 20, &[]);
+        let other = if PHYSICS == "float" { "fixed" } else { "float" };
+        let text = replay.serialize().replace(
+            &format!("physics {}", PHYSICS),
+            &format!("physics {}", other),
+        );
+
+        let actual = Replay::parse(&text);
+
+        assert_eq!(
+            actual,
+            Err(format!(
+                "line 5: recorded with {} physics, but this build has {} physics (see the `fixed-point` feature)",
+                other, PHYSICS
+            ))
+        );
+        // Replays from before physics was recorded are an older version
+        let old = "bwb-replay 1\nlevel 1\nseed 1\ndt 10\n";
+        assert!(Replay::parse(old).is_err());
+    }
+
+    #[test]
+    fn verify_matching_replay() {
+        let inputs = [
+            (5, Input::Move(Direction::Up)),
+            (10, Input::Fire(direction_vector(Direction::Left))),
+            (150, Input::Fire(direction_vector(Direction::Right))),
+        ];
+        let replay = record(1, 200, &inputs);
+
+        let result = verify(&LevelSet::builtin(), &replay);
+
+        assert_eq!(result, Ok(200));
+    }
+
+    #[test]
+    fn verify_reports_first_divergent_tick() {
+        // Arrange - tamper with the input log, so the cannon moves later than recorded
+        let mut replay = record(1, 50, &[(5, Input::Move(Direction::Up))]);
+        replay.inputs[0].0 = 20;
+
+        // Act
+        let result = verify(&LevelSet::builtin(), &replay);
+
+        // Assert - cannon starts moving on tick 6 when recorded
+        assert_eq!(result.unwrap_err().tick, 6);
+    }
+}
@@ -0,0 +1,95 @@
+//! Portable pseudo-random number generation, for procedural levels.
+//!
+//! Uses PCG32 (specifically PCG-XSH-RR with 64-bit state, as `pcg32` in the reference C implementation,
+//! see https://www.pcg-random.org). It only uses wrapping integer arithmetic, so a given seed produces the same
+//! sequence on every platform and compiler - and therefore the same levels.
+
+/// Stream used by [`Pcg32::new`]. Any value works, but changing it changes every generated level.
+const DEFAULT_STREAM: u64 = 0xda3e_39cb_94b9_5bdb;
+
+const MULTIPLIER: u64 = 6_364_136_223_846_793_005;
+
+/// PCG32 random number generator
+pub struct Pcg32 {
+    state: u64,
+    inc: u64,
+}
+
+impl Pcg32 {
+    /// Creates a generator from a seed, on the default stream
+    pub fn new(seed: u64) -> Self {
+        Self::with_stream(seed, DEFAULT_STREAM)
+    }
+
+    /// Creates a generator from a seed and stream (as `pcg32_srandom_r` in the reference implementation)
+    pub fn with_stream(seed: u64, stream: u64) -> Self {
+        let mut rng = Self {
+            state: 0,
+            inc: (stream << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    /// Generates a uniformly distributed `u32`
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(MULTIPLIER).wrapping_add(self.inc);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    /// Generates a uniformly distributed integer in `[lower, upper]` (inclusive)
+    pub fn gen_range(&mut self, lower: i32, upper: i32) -> i32 {
+        assert!(lower <= upper);
+        let range = (upper as i64 - lower as i64 + 1) as u64;
+        if range > u32::MAX as u64 {
+            return self.next_u32() as i32;
+        }
+        // Reject the low values that would make the modulo biased (as `pcg32_boundedrand_r`)
+        let threshold = ((1u64 << 32) - range) % range;
+        loop {
+            let r = self.next_u32() as u64;
+            if r >= threshold {
+                return (lower as i64 + (r % range) as i64) as i32;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Output of the reference implementation's demo (pcg32-demo.c), seeded with (42, 54)
+    #[test]
+    fn matches_reference_implementation() {
+        let mut rng = Pcg32::with_stream(42, 54);
+        let expected = [
+            0xa15c_02b7,
+            0x7b47_f409,
+            0xba1d_3330,
+            0x83d2_f293,
+            0xbfa4_784b,
+            0xcbed_606e,
+        ];
+
+        let actual: Vec<u32> = (0..6).map(|_| rng.next_u32()).collect();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn gen_range_inclusive() {
+        let mut rng = Pcg32::new(1);
+
+        let values: Vec<i32> = (0..1000).map(|_| rng.gen_range(-2, 2)).collect();
+
+        assert!(values.iter().all(|v| (-2..=2).contains(v)));
+        assert!(values.contains(&-2));
+        assert!(values.contains(&2));
+    }
+}
@@ -1,70 +1,213 @@
-use crate::geometry::{direction_vector, scale, Direction, Vector, P};
+//! Spatial state of objects
 
-/// Shape (currently, a square of side `size`) spatial/world-state
+use crate::fixed::{Real, Scalar};
+use crate::geometry::{direction_vector, reflect, scale, Direction, Vector, P};
+use serde::Deserialize;
+
+/// Outline of a shape, about its centre (before rotation), in world units.
+/// In level files, e.g. `shape = { rect = { width = 4000, height = 500 } }`, `shape = { circle = { radius = 50 } }`,
+/// or `shape = { polygon = [[0, -400], [350, 200], [-350, 200]] }`.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outline {
+    /// Convex polygon, given by its vertices relative to the centre, in order (either way round)
+    Polygon(Vec<Vector>),
+    /// Rectangle
+    Rect {
+        /// Size along the x axis
+        width: u32,
+        /// Size along the y axis
+        height: u32,
+    },
+    /// Circle
+    Circle {
+        /// Radius
+        radius: u32,
+    },
+}
+
+impl Outline {
+    /// A square of side `size`
+    pub fn square(size: u32) -> Self {
+        Outline::Rect {
+            width: size,
+            height: size,
+        }
+    }
+
+    /// Checks the outline has some size, and that polygons have at least 3 vertices and are convex
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Outline::Polygon(vertices) => {
+                if vertices.len() < 3 {
+                    return Err("polygon needs at least 3 vertices".to_string());
+                }
+                // Every turn is the same way round (or straight on), and not all straight on
+                let turns: Vec<i64> = (0..vertices.len())
+                    .map(|i| {
+                        let (a, b, c) = (
+                            vertices[i],
+                            vertices[(i + 1) % vertices.len()],
+                            vertices[(i + 2) % vertices.len()],
+                        );
+                        let (e1, e2) = ((b.0 - a.0, b.1 - a.1), (c.0 - b.0, c.1 - b.1));
+                        e1.0 as i64 * e2.1 as i64 - e1.1 as i64 * e2.0 as i64
+                    })
+                    .collect();
+                let convex =
+                    turns.iter().all(|turn| *turn >= 0) || turns.iter().all(|turn| *turn <= 0);
+                if !convex || turns.iter().all(|turn| *turn == 0) {
+                    return Err("polygon must be convex".to_string());
+                }
+                Ok(())
+            }
+            Outline::Rect { width, height } if *width == 0 || *height == 0 => {
+                Err("rect must have non-zero width and height".to_string())
+            }
+            Outline::Circle { radius: 0 } => Err("circle must have a non-zero radius".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Shape - outline, and spatial/world-state
 pub struct Shape {
     center: P,
     center_prev: P,
-    size: u32,
+    outline: Outline,
     /// Velocity, in units per second
     vel: Vector,
+    /// Sub-unit movement carried over between steps, in thousandths of a unit
+    vel_remainder: Vector,
     /// Current rotation about centre, in radians
-    rotation: f32,
-    rotation_prev: f32,
+    rotation: Real,
+    rotation_prev: Real,
     /// Rotational speed, in radians/sec
-    angular_velocity: f32,
+    angular_velocity: Real,
 }
 
 impl Shape {
+    /// Creates a new shape, at rest at `center` (i.e. previous state = current state).
+    /// Panics if the outline isn't valid (see [`Outline::validate`]).
+    /// The rotation and its speed are converted to the physics number type, [`Real`].
     pub fn new(
         center: (i32, i32),
-        size: u32,
+        outline: Outline,
         vel: (i32, i32),
         rotation: f32,
         angular_velocity: f32,
     ) -> Self {
-        assert!(size > 0);
+        outline.validate().unwrap();
         Self {
             center,
             center_prev: center,
-            size,
+            outline,
             vel,
-            rotation,
-            rotation_prev: rotation,
-            angular_velocity,
+            vel_remainder: (0, 0),
+            rotation: Real::from_f32(rotation),
+            rotation_prev: Real::from_f32(rotation),
+            angular_velocity: Real::from_f32(angular_velocity),
         }
     }
 
-    pub fn get_size(&self) -> &u32 {
-        &self.size
+    /// Returns the outline
+    pub fn get_outline(&self) -> &Outline {
+        &self.outline
     }
 
+    /// Changes the outline. Panics if it isn't valid (see [`Outline::validate`]).
+    pub fn set_outline(&mut self, outline: Outline) {
+        outline.validate().unwrap();
+        self.outline = outline;
+    }
+
+    /// Returns the centre point
     pub fn get_center(&self) -> &P {
         &self.center
     }
 
+    /// Moves the centre point, remembering the previous one
     pub fn set_center(&mut self, new_center: P) {
         self.center_prev = self.center;
         self.center = new_center;
     }
 
-    pub fn get_rotation(&self) -> &f32 {
+    /// Returns the current rotation, in radians
+    pub fn get_rotation(&self) -> &Real {
         &self.rotation
     }
 
+    /// Returns how far the centre moved in the last step, i.e. from the previous centre to the current one
+    pub fn get_displacement(&self) -> Vector {
+        (
+            self.center.0 - self.center_prev.0,
+            self.center.1 - self.center_prev.1,
+        )
+    }
+
+    /// Returns the velocity, in units per second
     pub fn get_vel(&self) -> &Vector {
         &self.vel
     }
 
+    /// Calculates the displacement over time-step `dt` (ms).
+    /// Any sub-unit remainder is carried over to the next step, so slow objects don't stall at small time-steps.
+    pub fn calc_step(&mut self, dt: i32) -> Vector {
+        let (vx, vy) = self.vel;
+        let (rx, ry) = self.vel_remainder;
+        let total_x = vx * dt + rx;
+        let total_y = vy * dt + ry;
+        self.vel_remainder = (total_x % 1000, total_y % 1000);
+        (total_x / 1000, total_y / 1000)
+    }
+
+    /// Returns the centre and rotation, interpolated between the previous and current state.
+    /// `alpha`: 0 => previous, 1 => current.
+    pub fn interpolate(&self, alpha: f32) -> (P, Real) {
+        let lerp = |prev: i32, curr: i32| prev + ((curr - prev) as f32 * alpha) as i32;
+        let (px, py) = self.center_prev;
+        let (cx, cy) = self.center;
+        let rotation =
+            self.rotation_prev + (self.rotation - self.rotation_prev) * Real::from_f32(alpha);
+        ((lerp(px, cx), lerp(py, cy)), rotation)
+    }
+
     /// Sets the velocity vector according to the given direction
     pub fn set_movement(&mut self, direction: Direction) {
         self.vel = scale(direction_vector(direction), 1000); // COULDDO: const/parameterise
     }
 
-    /// Updates shape rotation, given a time-step (seconds)
-    pub fn rotate(&mut self, dt_s: f32) {
-        // SHOULDDO: Wrap this to [-2*PI, +2*PI], otherwise there might be jumps on overflow
+    /// Turns the shape to the given rotation (radians) straight away, i.e. without interpolating from the previous one
+    pub fn set_rotation(&mut self, rotation: Real) {
+        self.rotation = rotation;
+        self.rotation_prev = rotation;
+    }
+
+    /// Updates shape rotation, given a time-step (ms).
+    /// Wrapped to within a turn either way, so that it doesn't grow without bound (losing precision, in `f32`).
+    pub fn rotate(&mut self, dt: i32) {
         self.rotation_prev = self.rotation;
-        self.rotation += self.angular_velocity * dt_s;
+        self.rotation += self.angular_velocity * (Real::from_i32(dt) / Real::from_i32(1000));
+        // The previous rotation is wrapped by the same amount, so interpolating between them doesn't jump
+        while self.rotation >= Real::TAU {
+            self.rotation -= Real::TAU;
+            self.rotation_prev -= Real::TAU;
+        }
+        while self.rotation <= -Real::TAU {
+            self.rotation += Real::TAU;
+            self.rotation_prev += Real::TAU;
+        }
+    }
+
+    /// Bounces off a surface with the given (unit) normal, pointing into the surface.
+    /// See [`reflect`]
+    pub fn reflect(&mut self, normal: (Real, Real)) {
+        self.vel = reflect(self.vel, normal);
+    }
+
+    /// Moves the shape by `offset`, e.g. to resolve penetration, without affecting the previous position
+    pub fn translate(&mut self, offset: Vector) {
+        self.center = (self.center.0 + offset.0, self.center.1 + offset.1);
     }
 
     /// Reverses the velocity vector
@@ -0,0 +1,310 @@
+//! Fixed-step simulation of a level, driven by player inputs.
+//! Shared by the SDL engine, headless mode and replays, so that given the same level, seed, tick length and
+//! inputs, the world evolves identically. In particular, time is counted in ticks rather than wall-clock time.
+
+use crate::entity::{EntityId, EntityKind};
+use crate::game_logic::{
+    self, aim_cannon, level_state, move_cannon, player_health, try_fire, LevelState, RELOAD_TIME,
+};
+use crate::geometry::{Direction, Vector};
+use crate::levels::{LevelId, LevelSet, Seed};
+use crate::replay::Replay;
+use crate::world::{ObjectFactory, Systems, World};
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
+
+/// Longest tick length, in ms - the engine's tick rate is likewise 1 to 1000 ticks per second
+pub const MAX_DT: i32 = 1000;
+
+/// Checks a tick length (ms) is in `1..=MAX_DT`. Longer ticks can overflow positions; zero or negative ones would
+/// freeze the world or run it backwards.
+pub fn validate_dt(dt: i32) -> Result<i32, String> {
+    if !(1..=MAX_DT).contains(&dt) {
+        return Err(format!("dt must be in 1..={} ms, got {}", MAX_DT, dt));
+    }
+    Ok(dt)
+}
+
+/// A player input, as passed to `try_fire`, `aim_cannon` or `move_cannon`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Input {
+    /// Fire the cannon along the given vector (any length), e.g. [`crate::geometry::direction_vector`]
+    Fire(Vector),
+    /// Turn the cannon to face along the given vector (any length), e.g. from the cannon to the mouse
+    Aim(Vector),
+    /// Move the cannon in the given direction
+    Move(Direction),
+}
+
+/// Player performance in a level
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LevelStats {
+    /// Time played, in ms (simulated time)
+    pub time_ms: u64,
+    /// Number of bullets fired
+    pub shots_fired: u32,
+    /// Number of baddies shot
+    pub hits: u32,
+    /// Player health, if there's a player in the world
+    pub health: Option<i32>,
+}
+
+/// A level in play
+pub struct Simulation {
+    level: LevelId,
+    seed: Seed,
+    world: World,
+    obj_factory: ObjectFactory,
+    /// Systems run on each tick
+    systems: Systems,
+    /// Tick length, in ms
+    dt: i32,
+    /// Number of ticks simulated so far
+    tick: u32,
+    /// Simulated clock origin. Only ever offset by whole ticks.
+    start: Instant,
+    prev_fire_time: Instant,
+    shots_fired: u32,
+    hits: u32,
+    /// Inputs and per-tick world hashes, if recording
+    recording: Option<Replay>,
+}
+
+impl Simulation {
+    /// Starts the given level, to be simulated with ticks of `dt` ms.
+    /// `seed` overrides the level's own seed, if given.
+    pub fn new(levels: &LevelSet, level: LevelId, seed: Option<Seed>, dt: i32) -> Self {
+        let seed = seed.unwrap_or_else(|| levels.seed(level));
+        let (world, obj_factory) = levels.init(level, seed);
+        let start = Instant::now();
+        Self {
+            level,
+            seed,
+            world,
+            obj_factory,
+            systems: game_logic::systems(),
+            dt,
+            tick: 0,
+            start,
+            // So that the cannon can fire straight away
+            prev_fire_time: start - 2 * RELOAD_TIME,
+            shots_fired: 0,
+            hits: 0,
+            recording: None,
+        }
+    }
+
+    /// Starts recording inputs and world hashes, for replay (see [`crate::replay`])
+    pub fn record(&mut self) {
+        assert_eq!(self.tick, 0, "Replays start from the beginning of a level");
+        self.recording = Some(Replay::new(self.level, self.seed, self.dt));
+    }
+
+    /// Stops recording, and returns the recording made, if any
+    pub fn take_recording(&mut self) -> Option<Replay> {
+        self.recording.take()
+    }
+
+    /// Applies a player input, at the current tick
+    pub fn apply(&mut self, input: Input) {
+        if let Some(recording) = &mut self.recording {
+            recording.record_input(self.tick, input);
+        }
+        match input {
+            Input::Fire(aim) => {
+                let now = self.start + Duration::from_millis(self.tick as u64 * self.dt as u64);
+                let prev_fire_time = self.prev_fire_time;
+                self.prev_fire_time =
+                    try_fire(now, prev_fire_time, &mut self.world, aim, &self.obj_factory);
+                if self.prev_fire_time != prev_fire_time {
+                    self.shots_fired += 1;
+                }
+            }
+            Input::Aim(aim) => aim_cannon(&mut self.world, aim),
+            Input::Move(direction) => move_cannon(&mut self.world, direction),
+        }
+    }
+
+    /// Advances the world by one tick
+    pub fn step(&mut self) -> LevelState {
+        self.systems.run(&mut self.world, self.dt);
+        let level_state = level_state(&self.world);
+
+        // Baddies shot this tick - each once, even if hit by more than one bullet
+        let shot: BTreeSet<EntityId> = self
+            .world
+            .collisions
+            .iter()
+            .filter(|event| event.kind == (EntityKind::Bullet, EntityKind::Baddie))
+            .map(|event| event.right)
+            .collect();
+        self.hits += shot.len() as u32;
+
+        self.tick += 1;
+        if let Some(recording) = &mut self.recording {
+            recording.record_tick(&self.world);
+        }
+        level_state
+    }
+
+    /// Level being played
+    pub fn level(&self) -> LevelId {
+        self.level
+    }
+
+    /// Seed the level was generated with
+    pub fn seed(&self) -> Seed {
+        self.seed
+    }
+
+    /// Tick length, in ms
+    pub fn dt(&self) -> i32 {
+        self.dt
+    }
+
+    /// Number of ticks simulated so far
+    pub fn tick(&self) -> u32 {
+        self.tick
+    }
+
+    /// The current world state
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    /// Systems run on each tick, for registering more (e.g. for new components)
+    pub fn systems_mut(&mut self) -> &mut Systems {
+        &mut self.systems
+    }
+
+    /// Player performance so far
+    pub fn stats(&self) -> LevelStats {
+        LevelStats {
+            time_ms: self.tick as u64 * self.dt as u64,
+            shots_fired: self.shots_fired,
+            hits: self.hits,
+            health: player_health(&self.world),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixed::Scalar;
+    use crate::geometry::direction_vector;
+    use crate::world::{create_world, ObjectFactory};
+
+    /// Builds a simulation of the given objects, rather than a predefined level
+    fn simulate(obj_factory: ObjectFactory, objects: Vec<crate::world::GameObject>) -> Simulation {
+        let mut sim = Simulation::new(&LevelSet::builtin(), 0, None, 10);
+        sim.world = create_world(&obj_factory, objects);
+        sim.obj_factory = obj_factory;
+        sim
+    }
+
+    #[test]
+    fn stats_count_shots_and_hits() {
+        // Arrange - baddies to the left and right, and one approaching the cannon from below (arriving after ~2s)
+        let obj_factory = ObjectFactory::new(1000);
+        let objects = vec![
+            obj_factory.make_cannon((5000, 5000)),
+            obj_factory.make_baddie((4000, 5000), (0, 0), 0.0),
+            obj_factory.make_baddie((6000, 5000), (0, 0), 0.0),
+            obj_factory.make_baddie((5000, 7500), (0, -1000), 0.0),
+        ];
+        let mut sim = simulate(obj_factory, objects);
+
+        // Act - fire left, then right too soon (throttled), then right after reloading
+        sim.apply(Input::Fire(direction_vector(Direction::Left)));
+        sim.apply(Input::Fire(direction_vector(Direction::Right)));
+        for _ in 0..120 {
+            sim.step();
+        }
+        sim.apply(Input::Fire(direction_vector(Direction::Right)));
+        for _ in 0..180 {
+            sim.step();
+        }
+
+        // Assert
+        let stats = sim.stats();
+        assert_eq!(stats.time_ms, 3000);
+        assert_eq!(stats.shots_fired, 2);
+        assert_eq!(stats.hits, 2);
+        assert_eq!(stats.health, Some(crate::world::PLAYER_HEALTH_MAX - 1));
+    }
+
+    #[test]
+    fn aimed_fire_any_direction() {
+        // Arrange - a baddie diagonally down and right of the cannon
+        let obj_factory = ObjectFactory::new(1000);
+        let objects = vec![
+            obj_factory.make_cannon((5000, 5000)),
+            obj_factory.make_baddie((6500, 6500), (0, 0), 0.0),
+        ];
+        let cannon_id = objects[0].0.get_id();
+        let mut sim = simulate(obj_factory, objects);
+
+        // Act - aim, then fire, at the baddie
+        sim.apply(Input::Aim((3, 3)));
+        let rotation = *sim.world().shapes.get(&cannon_id).unwrap().get_rotation();
+        sim.apply(Input::Fire((1500, 1500)));
+
+        // Assert - the cannon faces the baddie, and the bullet leaves its muzzle at full speed towards it
+        assert!((rotation.to_f32() - std::f32::consts::FRAC_PI_2).abs() < 0.001);
+        let world = sim.world();
+        let bullet = world
+            .query()
+            .of_kind(EntityKind::Bullet)
+            .iter()
+            .next()
+            .unwrap()
+            .get_id();
+        let shape = world.shapes.get(&bullet).unwrap();
+        assert_eq!(*shape.get_vel(), (707, 707));
+        // The cannon is 200 wide, so its corner is ~141 from the centre
+        assert_eq!(*shape.get_center(), (5100, 5100));
+
+        // Act - let it reach the baddie
+        for _ in 0..200 {
+            sim.step();
+        }
+
+        // Assert
+        assert_eq!(sim.stats().hits, 1);
+        assert_eq!(sim.world().count(EntityKind::Baddie), 0);
+    }
+
+    #[test]
+    fn removed_entity_ids_reused() {
+        // Arrange - a baddie to the left of the cannon
+        let obj_factory = ObjectFactory::new(1000);
+        let objects = vec![
+            obj_factory.make_cannon((5000, 5000)),
+            obj_factory.make_baddie((4000, 5000), (0, 0), 0.0),
+        ];
+        let baddie_id = objects[1].0.get_id();
+        let mut sim = simulate(obj_factory, objects);
+
+        // Act - shoot the baddie, then fire again once reloaded
+        sim.apply(Input::Fire(direction_vector(Direction::Left)));
+        for _ in 0..120 {
+            sim.step();
+        }
+        sim.apply(Input::Fire(direction_vector(Direction::Right)));
+
+        // Assert - the new bullet reuses a freed slot, and the baddie's ID is stale
+        assert!(!sim.obj_factory.is_alive(baddie_id));
+        assert_eq!(sim.world().entities.len(), 2);
+        let bullet = sim
+            .world()
+            .query()
+            .of_kind(EntityKind::Bullet)
+            .iter()
+            .next()
+            .unwrap();
+        assert!(bullet.get_id().index() < 3);
+        assert!(bullet.get_id().generation() > 0);
+        assert!(!sim.world().shapes.contains_key(&baddie_id));
+    }
+}
@@ -0,0 +1,191 @@
+//! Spatial queries against the world - ray casts, and the entities at a point or overlapping a box.
+//! E.g. for baddies' line of sight, laser weapons, or inspecting whatever is under the mouse.
+//!
+//! Candidates come from the world's broad phase, then are tested against their current geometry (narrow phase).
+//! The broad phase is brought up to date by the collision system on each world update - or on demand with
+//! [`World::update_broad_phase`], e.g. after adding entities. Entities that have moved out of their place in the
+//! broad phase since might be missed.
+//!
+//! Queries are in world coordinates, i.e. they don't wrap around the world edges.
+
+use crate::entity::{EntityId, EntityKind};
+use crate::geometry::{ray_direction, Aabb, Geometry, RayHit, P};
+use crate::world::{layer_geoms, World};
+use itertools::Itertools;
+
+impl World {
+    /// Brings the broad phase up to date with the current geometries, so that queries see the world as it is now
+    pub fn update_broad_phase(&mut self) {
+        let layers = layer_geoms(&self.entities, &self.geometries);
+        self.broad_phase.update(&layers);
+    }
+
+    /// Entities of the given kinds that might overlap the box, with their geometries, in ID order
+    fn candidates(&self, aabb: &Aabb, kinds: &[EntityKind]) -> Vec<(EntityId, &Geometry)> {
+        kinds
+            .iter()
+            .unique()
+            .flat_map(|kind| self.broad_phase.query(*kind, aabb))
+            // Might have been removed since the last update
+            .filter_map(|id| self.geometries.get(&id).map(|geometry| (id, geometry)))
+            .sorted_by_key(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Casts a ray from `origin` along `direction`, up to `max_distance` away, and finds the first entity of the
+    /// given kinds that it hits (see [`raycast`]). Of entities hit at the same distance, the one with the lowest ID.
+    /// Rays with no direction never hit (see [`ray_direction`]).
+    pub fn raycast(
+        &self,
+        origin: P,
+        direction: (f32, f32),
+        max_distance: f32,
+        kinds: &[EntityKind],
+    ) -> Option<(EntityId, RayHit)> {
+        let (dx, dy) = ray_direction(direction, max_distance)?;
+        let end = (
+            origin.0 + (dx * max_distance).round() as i32,
+            origin.1 + (dy * max_distance).round() as i32,
+        );
+        // Grown to allow for the rounding
+        let aabb = Aabb::of(&[origin, end]).grow(1);
+        self.candidates(&aabb, kinds)
+            .into_iter()
+            .filter_map(|(id, geometry)| {
+                geometry
+                    .raycast(origin, direction, max_distance)
+                    .map(|hit| (id, hit))
+            })
+            .min_by(|(_, hit1), (_, hit2)| hit1.distance.total_cmp(&hit2.distance))
+    }
+
+    /// Entities of the given kinds that contain the point (including on their edges), in ID order
+    pub fn entities_at(&self, point: P, kinds: &[EntityKind]) -> Vec<EntityId> {
+        let aabb = Aabb {
+            min: point,
+            max: point,
+        };
+        self.candidates(&aabb, kinds)
+            .into_iter()
+            .filter(|(_, geometry)| geometry.contains_point(point))
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    /// Entities of the given kinds that overlap the box (including touching it), in ID order
+    pub fn entities_overlapping(&self, aabb: &Aabb, kinds: &[EntityKind]) -> Vec<EntityId> {
+        let polygon = aabb.polygon();
+        self.candidates(aabb, kinds)
+            .into_iter()
+            .filter(|(_, geometry)| geometry.collides(&polygon))
+            .map(|(id, _)| id)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::{EntityId, EntityKind};
+    use crate::geometry::Aabb;
+    use crate::world::{create_world, ObjectFactory, World};
+
+    /// Walls at x = 3000 and 6000, with a baddie in front of the second, and a bullet between the walls.
+    /// Returns the world, and the IDs of the walls, baddie, and bullet.
+    fn walls_world() -> (World, [EntityId; 4]) {
+        let obj_factory = ObjectFactory::new(1000);
+        let near_wall = obj_factory.make_wall((3000, 5000));
+        let far_wall = obj_factory.make_wall((6000, 5000));
+        let baddie = obj_factory.make_baddie((5000, 5000), (0, 0), 0.0);
+        let bullet = obj_factory.make_bullet((4000, 5200), (1, 0));
+        let ids = [
+            near_wall.0.get_id(),
+            far_wall.0.get_id(),
+            baddie.0.get_id(),
+            bullet.0.get_id(),
+        ];
+        let mut world = create_world(&obj_factory, vec![near_wall, far_wall, baddie, bullet]);
+        world.update_broad_phase();
+        (world, ids)
+    }
+
+    #[test]
+    fn raycast_finds_nearest_of_kinds() {
+        // Arrange - walls are 1000 wide, so the near one's left edge is at x = 2500, and baddies 750 wide
+        let (world, [near_wall, far_wall, baddie, _]) = walls_world();
+
+        // Act
+        let from_left = world.raycast((0, 5000), (1.0, 0.0), 10000.0, &[EntityKind::Wall]);
+        let from_right = world.raycast(
+            (9000, 5000),
+            (-1.0, 0.0),
+            10000.0,
+            &[EntityKind::Wall, EntityKind::Baddie],
+        );
+        let walls_only = world.raycast((9000, 5000), (-1.0, 0.0), 10000.0, &[EntityKind::Wall]);
+        let too_short = world.raycast((0, 5000), (1.0, 0.0), 2000.0, &[EntityKind::Wall]);
+        let past = world.raycast((0, 9000), (1.0, 0.0), 10000.0, &[EntityKind::Wall]);
+        let no_direction = world.raycast((3000, 5000), (0.0, 0.0), 10000.0, &[EntityKind::Wall]);
+
+        // Assert
+        let (id, hit) = from_left.unwrap();
+        assert_eq!(id, near_wall);
+        assert_eq!(hit.distance, 2500.0);
+        assert_eq!(hit.normal, (-1.0, 0.0));
+        assert_eq!(from_right.unwrap().0, far_wall);
+        // Baddie is behind the far wall from the right, so hidden
+        let (id, hit) = walls_only.unwrap();
+        assert_eq!(id, far_wall);
+        assert_eq!(hit.point, (6500, 5000));
+        assert_ne!(id, baddie);
+        assert!(too_short.is_none());
+        assert!(past.is_none());
+        // Even from inside a wall
+        assert!(no_direction.is_none());
+    }
+
+    #[test]
+    fn line_of_sight_between_walls() {
+        // Arrange
+        let (world, [_, _, baddie, _]) = walls_world();
+
+        // Act - from between the walls, looking right
+        let hit = world.raycast(
+            (4000, 5000),
+            (1.0, 0.0),
+            3000.0,
+            &[EntityKind::Wall, EntityKind::Baddie],
+        );
+
+        // Assert - baddie's left edge is at 5000 - 375
+        let (id, hit) = hit.unwrap();
+        assert_eq!(id, baddie);
+        assert_eq!(hit.point, (4625, 5000));
+    }
+
+    #[test]
+    fn point_and_box_queries() {
+        // Arrange
+        let (mut world, [near_wall, far_wall, baddie, bullet]) = walls_world();
+        let all = [EntityKind::Wall, EntityKind::Baddie, EntityKind::Bullet];
+
+        // Act
+        let at_wall = world.entities_at((3000, 5400), &all);
+        let at_nothing = world.entities_at((4000, 1000), &all);
+        let middle = Aabb {
+            min: (3400, 4900),
+            max: (5000, 5300),
+        };
+        let in_middle = world.entities_overlapping(&middle, &all);
+        let walls_in_middle = world.entities_overlapping(&middle, &[EntityKind::Wall]);
+        world.remove(bullet);
+        let in_middle_removed = world.entities_overlapping(&middle, &all);
+
+        // Assert
+        assert_eq!(at_wall, vec![near_wall]);
+        assert!(at_nothing.is_empty());
+        assert_eq!(in_middle, vec![near_wall, baddie, bullet]);
+        assert_eq!(walls_in_middle, vec![near_wall]);
+        assert_eq!(in_middle_removed, vec![near_wall, baddie]);
+        assert!(!in_middle.contains(&far_wall));
+    }
+}
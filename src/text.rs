@@ -17,10 +17,12 @@ pub enum Size {
 pub type Line<'a> = (&'a str, Size);
 
 pub enum Position {
-    CenterScreen
+    CenterScreen,
+    TopRight,
+    BottomLeft,
 }
 
-pub fn load_font(ttf_context: &ttf::Sdl2TtfContext) -> Font {
+pub fn load_font(ttf_context: &ttf::Sdl2TtfContext) -> Font<'_> {
     let mut fs = Font::new();
     fs.insert(Size::Small, ttf_context.load_font(FONT_PATH, 18).unwrap());
     fs.insert(Size::Medium, ttf_context.load_font(FONT_PATH, 36).unwrap());
@@ -0,0 +1,73 @@
+//! Fixed time-step, decoupling the simulation rate from the rendering rate.
+//! Based on https://gafferongames.com/post/fix_your_timestep/
+
+use std::time::Duration;
+
+/// Upper limit on the frame time fed into the accumulator, so that a long stall (e.g. dragging the window)
+/// doesn't result in a burst of catch-up ticks.
+const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+/// Accumulates real (frame) time and dispenses it as whole, fixed-size simulation ticks.
+pub struct FixedTimestep {
+    dt: Duration,
+    accumulator: Duration,
+}
+
+impl FixedTimestep {
+    /// Creates a time-step for the given tick rate (ticks per second).
+    /// Note: the tick length is rounded down to whole ms, as that's what `update_world` takes.
+    pub fn new(tick_rate: u32) -> Self {
+        assert!(tick_rate > 0 && tick_rate <= 1000);
+        Self {
+            dt: Duration::from_millis(1000 / tick_rate as u64),
+            accumulator: Duration::from_millis(0),
+        }
+    }
+
+    /// Tick length, in ms
+    pub fn dt_ms(&self) -> i32 {
+        self.dt.as_millis() as i32
+    }
+
+    /// Adds the elapsed frame time, and returns how many ticks should now be simulated.
+    pub fn advance(&mut self, frame_time: Duration) -> u32 {
+        self.accumulator += std::cmp::min(frame_time, MAX_FRAME_TIME);
+        let mut ticks = 0;
+        while self.accumulator >= self.dt {
+            self.accumulator -= self.dt;
+            ticks += 1;
+        }
+        ticks
+    }
+
+    /// How far between the previous and the current tick we are, in [0, 1) - for render interpolation.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator.as_secs_f32() / self.dt.as_secs_f32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advance_accumulates_partial_ticks() {
+        // Arrange - 100Hz => 10ms ticks
+        let mut timestep = FixedTimestep::new(100);
+
+        // Act & Assert
+        assert_eq!(timestep.advance(Duration::from_millis(25)), 2);
+        assert!((timestep.alpha() - 0.5).abs() < 0.001);
+        assert_eq!(timestep.advance(Duration::from_millis(5)), 1);
+        assert!(timestep.alpha().abs() < 0.001);
+    }
+
+    #[test]
+    fn advance_clamps_long_frames() {
+        let mut timestep = FixedTimestep::new(100);
+
+        let ticks = timestep.advance(Duration::from_secs(5));
+
+        assert_eq!(ticks, 25);
+    }
+}
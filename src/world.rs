@@ -1,145 +1,425 @@
-use crate::entity::{Entity, EntityId, EntityKind};
-use crate::geometry::{rotate, scale, Geometry, Vector, Vertex, P};
-use crate::shape::Shape;
+//! World state - the game objects and their components
+
+use crate::broad_phase::{BroadPhase, BroadPhaseKind};
+use crate::collision_system::CollisionEvent;
+use crate::entity::{Entity, EntityAllocator, EntityId, EntityKind};
+use crate::fixed::Real;
+use crate::geometry::{normalize, rotate, Geometry, Vector, P};
+use crate::shape::{Outline, Shape};
+use std::any::{Any, TypeId};
 use std::collections::{HashMap, HashSet};
 use std::f32::consts::PI;
+use std::sync::{Arc, Mutex};
 
 // World coordinate bounds
+/// World width, in world units
 pub const GRID_WIDTH: u32 = 10000;
+/// World height, in world units
 pub const GRID_HEIGHT: u32 = 10000;
 
+/// Player health at the start of a level
 pub const PLAYER_HEALTH_MAX: i32 = 3;
 
+/// Health points
 pub type Health = i32;
 
-/// Aggregate of entity and associated data.
+/// Aggregate of entity and associated data, for adding to the world.
 /// Is a tuple so that each component can be borrowed independently
 pub type GameObject = (Entity, Shape, Geometry, Option<Health>);
 
+/// All entities in the world
 pub type Entities = HashSet<Entity>;
-pub type Shapes = HashMap<EntityId, Shape>;
-pub type Geometries = HashMap<EntityId, Geometry>;
-pub type Healths = HashMap<EntityId, Health>;
+/// Storage of a component, by entity
+pub type Storage<C> = HashMap<EntityId, C>;
+/// Shape component, by entity
+pub type Shapes = Storage<Shape>;
+/// Geometry component, by entity
+pub type Geometries = Storage<Geometry>;
+/// Health component, by entity (only for entities that have health)
+pub type Healths = Storage<Health>;
 
-/// Map of EntityId to Geometry reference
+/// Map of EntityId to geometry reference. Usually an entity's own [`Geometry`], but may be any other,
+/// e.g. the shape swept out by a moving entity.
 pub type GeomRefMap<'a> = HashMap<EntityId, &'a Geometry>;
+/// Geometry references, by entity kind (i.e. collision layer)
+pub type GeomLayers<'a> = HashMap<EntityKind, GeomRefMap<'a>>;
+
+/// Data attached to entities, held in a [`Storage`] in the [`World`].
+/// The core components have a field each, so that systems can borrow them independently.
+/// Any other type can be made a component with an empty impl, e.g. `impl Component for Lifetime {}`,
+/// in which case its storage is created on first insert.
+pub trait Component: Sized + Send + Sync + 'static {
+    /// The world's storage for this component, if it has one yet
+    fn storage(world: &World) -> Option<&Storage<Self>> {
+        world
+            .extra
+            .get(&TypeId::of::<Self>())
+            .map(|storage| storage.as_any().downcast_ref().unwrap())
+    }
+
+    /// The world's storage for this component, created if needed
+    fn storage_mut(world: &mut World) -> &mut Storage<Self> {
+        world
+            .extra
+            .entry(TypeId::of::<Self>())
+            .or_insert_with(|| Box::new(Storage::<Self>::new()))
+            .as_any_mut()
+            .downcast_mut()
+            .unwrap()
+    }
+}
+
+impl Component for Shape {
+    fn storage(world: &World) -> Option<&Storage<Self>> {
+        Some(&world.shapes)
+    }
+    fn storage_mut(world: &mut World) -> &mut Storage<Self> {
+        &mut world.shapes
+    }
+}
+
+impl Component for Geometry {
+    fn storage(world: &World) -> Option<&Storage<Self>> {
+        Some(&world.geometries)
+    }
+    fn storage_mut(world: &mut World) -> &mut Storage<Self> {
+        &mut world.geometries
+    }
+}
+
+impl Component for Health {
+    fn storage(world: &World) -> Option<&Storage<Self>> {
+        Some(&world.healths)
+    }
+    fn storage_mut(world: &mut World) -> &mut Storage<Self> {
+        &mut world.healths
+    }
+}
+
+/// Type-erased storage of non-core components, so they can be removed along with their entity
+trait AnyStorage: Send + Sync {
+    fn remove(&mut self, id: EntityId);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<C: Component> AnyStorage for Storage<C> {
+    fn remove(&mut self, id: EntityId) {
+        HashMap::remove(self, &id);
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Entity kinds that use continuous collision detection by default (see [`World::ccd_kinds`])
+pub const DEFAULT_CCD_KINDS: [EntityKind; 1] = [EntityKind::Bullet];
+
+/// The world state - entities, and their components
+pub struct World {
+    /// All entities
+    pub entities: Entities,
+    /// Shape of every entity
+    pub shapes: Shapes,
+    /// Geometry of every entity
+    pub geometries: Geometries,
+    /// Health of entities that have it
+    pub healths: Healths,
+    /// Kinds of entity whose collisions are tested along their whole path in each step (continuous collision detection),
+    /// rather than just at the end, so that small fast objects don't tunnel through others
+    pub ccd_kinds: HashSet<EntityKind>,
+    /// Finds potential collisions. Kept between steps, so it only needs updating for entities that moved.
+    pub broad_phase: Box<dyn BroadPhase>,
+    /// Collisions detected in the latest step, in order (see [`crate::collision_system::CollisionSystem::process`]), for systems to respond to
+    pub collisions: Vec<CollisionEvent>,
+    /// Any other components, by type
+    extra: HashMap<TypeId, Box<dyn AnyStorage>>,
+    /// Allocates the entities' IDs, shared with the [`ObjectFactory`] creating them. Removed entities' IDs are freed.
+    allocator: SharedAllocator,
+}
+
+/// An entity allocator shared by a world and the [`ObjectFactory`] creating its objects
+type SharedAllocator = Arc<Mutex<EntityAllocator>>;
+
+impl Default for World {
+    fn default() -> Self {
+        Self {
+            entities: Entities::new(),
+            shapes: Shapes::new(),
+            geometries: Geometries::new(),
+            healths: Healths::new(),
+            ccd_kinds: DEFAULT_CCD_KINDS.iter().cloned().collect(),
+            broad_phase: BroadPhaseKind::default().create(),
+            collisions: Vec::new(),
+            extra: HashMap::new(),
+            allocator: SharedAllocator::default(),
+        }
+    }
+}
+
+impl World {
+    /// Creates an empty world
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-/// Aggregates of world data components.
-/// Are tuples so that each component can be borrowed independently.
-pub type GameObjects = (Entities, Shapes, Geometries, Healths);
-pub type World = GameObjects; // May want to add state here
-
-pub fn create_world(level_data: Vec<GameObject>) -> World {
-    let mut entities = HashSet::<Entity>::new();
-    let mut shapes = HashMap::<EntityId, Shape>::new();
-    let mut geometries = Geometries::new();
-    let mut healths = Healths::new();
-
-    for (entity, shape, geometry, health) in level_data {
-        entities.insert(entity);
-        shapes.insert(entity.get_id(), shape);
-        geometries.insert(entity.get_id(), geometry);
+    /// Adds the provided game object to the world
+    pub fn add(&mut self, game_obj: GameObject) {
+        let (entity, shape, geometry, health) = game_obj;
+        let id = entity.get_id();
+        self.entities.insert(entity);
+        self.shapes.insert(id, shape);
+        self.geometries.insert(id, geometry);
         if let Some(health) = health {
-            healths.insert(entity.get_id(), health);
+            self.healths.insert(id, health);
+        }
+    }
+
+    /// Removes the given entity and all of its components from the world, freeing its ID for reuse
+    pub fn remove(&mut self, id: EntityId) {
+        self.geometries.remove(&id);
+        self.shapes.remove(&id);
+        self.healths.remove(&id);
+        for storage in self.extra.values_mut() {
+            storage.remove(id);
+        }
+        if self.entities.remove(&Entity::from_id(id)) {
+            self.allocator.lock().unwrap().free(id);
+        }
+    }
+
+    /// Gets the entity with the given ID, if it's in the world
+    pub fn entity(&self, id: EntityId) -> Option<&Entity> {
+        self.entities.get(&Entity::from_id(id))
+    }
+
+    /// Gets an entity's component, if it has one
+    pub fn get<C: Component>(&self, id: EntityId) -> Option<&C> {
+        C::storage(self)?.get(&id)
+    }
+
+    /// Gets an entity's component mutably, if it has one
+    pub fn get_mut<C: Component>(&mut self, id: EntityId) -> Option<&mut C> {
+        C::storage_mut(self).get_mut(&id)
+    }
+
+    /// Attaches a component to an entity, replacing any existing one of the same type
+    pub fn insert<C: Component>(&mut self, id: EntityId, component: C) {
+        C::storage_mut(self).insert(id, component);
+    }
+
+    /// Starts a query over all entities; narrow it down with [`Query::of_kind`] and [`Query::with`]
+    pub fn query(&self) -> Query<'_> {
+        Query {
+            world: self,
+            kind: None,
+            filters: vec![],
         }
     }
 
-    (entities, shapes, geometries, healths)
+    /// Number of entities of the given kind
+    pub fn count(&self, kind: EntityKind) -> usize {
+        self.query().of_kind(kind).count()
+    }
+
+    /// Gets the cannon
+    pub fn cannon(&self) -> Option<&Entity> {
+        self.query().of_kind(EntityKind::Cannon).iter().next()
+    }
+}
+
+/// A query for the entities of the world that match all the given criteria,
+/// e.g. `world.query().of_kind(EntityKind::Baddie).with::<Shape>().with::<Health>()`
+pub struct Query<'w> {
+    world: &'w World,
+    kind: Option<EntityKind>,
+    filters: Vec<fn(&World, EntityId) -> bool>,
 }
 
-/// Adds the provided game object to the world
-pub fn add(game_objects: &mut GameObjects, game_obj: GameObject) {
-    let (entities, shapes, geometries, healths) = game_objects;
-    let (entity, shape, geometry, health) = game_obj;
-    entities.insert(entity);
-    shapes.insert(entity.get_id(), shape);
-    geometries.insert(entity.get_id(), geometry);
-    if let Some(health) = health {
-        healths.insert(entity.get_id(), health);
+impl<'w> Query<'w> {
+    /// Only matches entities of the given kind
+    pub fn of_kind(mut self, kind: EntityKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Only matches entities that have a `C` component
+    pub fn with<C: Component>(mut self) -> Self {
+        self.filters
+            .push(|world, id| C::storage(world).is_some_and(|storage| storage.contains_key(&id)));
+        self
+    }
+
+    /// Iterates over the matching entities, in arbitrary order
+    pub fn iter(&self) -> impl Iterator<Item = &'w Entity> + '_ {
+        let world = self.world;
+        world.entities.iter().filter(move |entity| {
+            self.kind.is_none_or(|kind| *entity.get_kind() == kind)
+                && self
+                    .filters
+                    .iter()
+                    .all(|filter| filter(world, entity.get_id()))
+        })
+    }
+
+    /// IDs of the matching entities, in arbitrary order
+    pub fn ids(&self) -> Vec<EntityId> {
+        self.iter().map(|entity| entity.get_id()).collect()
+    }
+
+    /// Number of matching entities
+    pub fn count(&self) -> usize {
+        self.iter().count()
     }
 }
 
-/// Removes the given entity from the world
-pub fn remove(game_objects: &mut GameObjects, id: EntityId) {
-    let (entities, shapes, geometries, healths) = game_objects;
-    geometries.remove(&id);
-    shapes.remove(&id);
-    healths.remove(&id); // TODO: check - any effect if item isn't in there?
-    entities.remove(&Entity::from_id(id));
+/// A system - a stage of the world update, given the tick length `dt` (ms)
+pub type System = fn(&mut World, i32);
+
+/// Systems to run on each world update, in registration order
+#[derive(Default)]
+pub struct Systems {
+    systems: Vec<(&'static str, System)>,
 }
 
-pub fn get_entity(entities: &Entities, id: EntityId) -> &Entity {
-    entities.get(&Entity::from_id(id)).unwrap()
+impl Systems {
+    /// Creates an empty set of systems
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a system, to be run after those already registered
+    pub fn register(&mut self, name: &'static str, system: System) -> &mut Self {
+        self.systems.push((name, system));
+        self
+    }
+
+    /// Names of the registered systems, in order
+    pub fn names(&self) -> Vec<&'static str> {
+        self.systems.iter().map(|(name, _)| *name).collect()
+    }
+
+    /// Runs all systems on the world, in order
+    pub fn run(&self, world: &mut World, dt: i32) {
+        for (_, system) in &self.systems {
+            system(world, dt);
+        }
+    }
 }
 
-/// Gets the cannon
-pub fn get_cannon(game_objects: &GameObjects) -> Option<&Entity> {
-    game_objects
-        .0
-        .iter()
-        .find(|e| *e.get_kind() == EntityKind::Cannon)
+/// Creates a world from the given objects, made by the given factory (whose entity allocator the world shares)
+pub fn create_world(obj_factory: &ObjectFactory, level_data: Vec<GameObject>) -> World {
+    let mut world = World {
+        allocator: Arc::clone(&obj_factory.allocator),
+        ..World::default()
+    };
+    for game_obj in level_data {
+        world.add(game_obj);
+    }
+    world
+}
+
+/// Gets the entity with the given ID. Panics if it doesn't exist.
+pub fn get_entity(entities: &Entities, id: EntityId) -> &Entity {
+    entities.get(&Entity::from_id(id)).unwrap()
 }
 
-/// Separates geometry collection by entity kind.
+/// Groups geometries by entity kind, i.e. into collision layers.
 /// Note: Allocates separate collections (of references)
-pub fn destructure_geom<'a>(
-    entities: &'a Entities,
-    geometries: &'a Geometries,
-) -> (
-    GeomRefMap<'a>,
-    GeomRefMap<'a>,
-    GeomRefMap<'a>,
-    GeomRefMap<'a>,
-) {
-    let mut wall_geoms = HashMap::<EntityId, &Geometry>::new();
-    let mut baddie_geoms = HashMap::<EntityId, &Geometry>::new();
-    let mut bullet_geoms = HashMap::<EntityId, &Geometry>::new();
-    let mut cannon_geoms = HashMap::<EntityId, &Geometry>::new();
+pub fn layer_geoms<'a>(entities: &'a Entities, geometries: &'a Geometries) -> GeomLayers<'a> {
+    let mut layers = GeomLayers::new();
     for (entity_id, geom) in geometries.iter() {
-        let entity_id = *entity_id;
-        let e = get_entity(entities, entity_id);
-        match e.get_kind() {
-            EntityKind::Wall => {
-                wall_geoms.insert(entity_id, geom);
-            }
-            EntityKind::Baddie => {
-                baddie_geoms.insert(entity_id, geom);
-            }
-            EntityKind::Bullet => {
-                bullet_geoms.insert(entity_id, geom);
-            }
-            EntityKind::Cannon => {
-                cannon_geoms.insert(entity_id, geom);
-            }
-            _ => (),
-        }
+        let kind = *get_entity(entities, *entity_id).get_kind();
+        layers.entry(kind).or_default().insert(*entity_id, geom);
     }
-    (wall_geoms, baddie_geoms, bullet_geoms, cannon_geoms)
+    layers
+}
+
+/// Updates geometry according to its shape's state
+pub fn update_geometry(geometry: &mut Geometry, shape: &Shape) {
+    build_geometry(
+        geometry,
+        shape.get_outline(),
+        shape.get_center(),
+        *shape.get_rotation(),
+    );
 }
 
-/// Updates box geometry according to its state
-pub fn update_geometry(box_geometry: &mut [Vertex], box_state: &Shape) {
-    let (cx, cy) = box_state.get_center();
-    let delta = (box_state.get_size() / 2) as i32;
-    let vs = box_geometry;
-    vs[0] = (cx - delta, cy - delta);
-    vs[1] = (cx + delta, cy - delta);
-    vs[2] = (cx + delta, cy + delta);
-    vs[3] = (cx - delta, cy + delta);
+/// Builds geometry for rendering, interpolated between the shape's previous and current state.
+/// `alpha`: 0 => previous, 1 => current.
+/// Objects that wrapped around the world edge in the last step aren't interpolated, to avoid sweeping across the world.
+pub fn interpolate_geometry(shape: &Shape, alpha: f32) -> Geometry {
+    let (center, rotation) = shape.interpolate(alpha);
+    let (cx, cy) = shape.get_center();
+    let wrapped = (center.0 - cx).abs() > GRID_WIDTH as i32 / 2
+        || (center.1 - cy).abs() > GRID_HEIGHT as i32 / 2;
+    let mut geometry = Geometry::Polygon(vec![]);
+    if wrapped {
+        update_geometry(&mut geometry, shape);
+    } else {
+        build_geometry(&mut geometry, shape.get_outline(), &center, rotation);
+    }
+    geometry
+}
 
-    // Repeat first to close shape - just an implementation detail, could be reworked.
-    vs[4] = (cx - delta, cy - delta);
+/// Builds interpolated geometry (see [`interpolate_geometry`]) for all shapes
+pub fn interpolate_geometries(shapes: &Shapes, alpha: f32) -> Geometries {
+    shapes
+        .iter()
+        .map(|(id, shape)| (*id, interpolate_geometry(shape, alpha)))
+        .collect()
+}
 
-    for v in vs.iter_mut() {
-        rotate(v, &box_state.get_center(), *box_state.get_rotation())
+/// Builds the geometry of the outline, centred on `center` and rotated by `rotation`.
+/// Reuses the existing vertices' allocation, as it's done for every object on every step.
+fn build_geometry(geometry: &mut Geometry, outline: &Outline, center: &P, rotation: Real) {
+    let corners;
+    let offsets: &[Vector] = match outline {
+        Outline::Circle { radius } => {
+            // Rotating makes no difference
+            *geometry = Geometry::Circle {
+                center: *center,
+                radius: *radius as i32,
+            };
+            return;
+        }
+        Outline::Rect { width, height } => {
+            let (dx, dy) = ((width / 2) as i32, (height / 2) as i32);
+            corners = [(-dx, -dy), (dx, -dy), (dx, dy), (-dx, dy)];
+            &corners
+        }
+        Outline::Polygon(offsets) => offsets,
+    };
+    match geometry {
+        Geometry::Polygon(vertices) => vertices.clear(),
+        _ => *geometry = Geometry::Polygon(Vec::with_capacity(offsets.len() + 1)),
+    }
+    if let Geometry::Polygon(vertices) = geometry {
+        let (cx, cy) = *center;
+        vertices.extend(offsets.iter().map(|(dx, dy)| (cx + dx, cy + dy)));
+        // Repeat first to close shape - just an implementation detail, could be reworked.
+        vertices.push(vertices[0]);
+        for v in vertices.iter_mut() {
+            rotate(v, center, rotation)
+        }
     }
 }
 
-/// Builds the box geometry, given its initial state
-fn build_box_geometry(box_state: &Shape) -> Geometry {
-    let mut vertices = [(0, 0); 5];
-    update_geometry(&mut vertices, box_state);
-    vertices
+/// Builds the geometry, given its initial state
+fn build_shape_geometry(shape: &Shape) -> Geometry {
+    let mut geometry = Geometry::Polygon(vec![]);
+    update_geometry(&mut geometry, shape);
+    geometry
+}
+
+/// Gives the object a different outline, rebuilding its geometry. Panics if it isn't valid (see [`Outline::validate`]).
+pub fn set_outline(game_obj: &mut GameObject, outline: Outline) {
+    let (_, shape, geometry, _) = game_obj;
+    shape.set_outline(outline);
+    update_geometry(geometry, shape);
 }
 
 const BADDIE_SIZE: f32 = 0.75;
@@ -148,55 +428,193 @@ const BULLET_SIZE: f32 = 0.1;
 const CANNON_SIZE: f32 = 0.2;
 const BULLET_SPEED: i32 = 1000;
 
-/// Factory for creating the various kinds of game objects
+/// Factory for creating the various kinds of game objects.
+/// Allocates their IDs from the entity allocator of the world they're created for (see [`create_world`]).
 pub struct ObjectFactory {
     base_size: u32,
+    allocator: SharedAllocator,
 }
 
 impl ObjectFactory {
-    /// Creates a new `ObjectFactory` with the given base size.
+    /// Creates a new `ObjectFactory` with the given base size, and a fresh entity allocator.
     pub fn new(base_size: u32) -> Self {
-        Self { base_size }
+        Self::with_allocator(base_size, EntityAllocator::new())
+    }
+
+    /// Creates a new `ObjectFactory` with the given base size, allocating entity IDs from `allocator`.
+    pub fn with_allocator(base_size: u32, allocator: EntityAllocator) -> Self {
+        Self {
+            base_size,
+            allocator: Arc::new(Mutex::new(allocator)),
+        }
+    }
+
+    /// Whether the ID refers to a live entity, rather than a removed (stale) one
+    pub fn is_alive(&self, id: EntityId) -> bool {
+        self.allocator.lock().unwrap().is_alive(id)
+    }
+
+    fn make_entity(&self, kind: EntityKind) -> Entity {
+        Entity::new(&mut self.allocator.lock().unwrap(), kind)
     }
 
     /// Creates a cannon
     pub fn make_cannon(&self, center: P) -> GameObject {
-        let shape = Shape::new(center, self.calc_size(CANNON_SIZE), (0, 0), PI / 4.0, 0.0);
-        let geom = build_box_geometry(&shape);
+        let shape = Shape::new(
+            center,
+            Outline::square(self.calc_size(CANNON_SIZE)),
+            (0, 0),
+            PI / 4.0,
+            0.0,
+        );
+        let geom = build_shape_geometry(&shape);
         (
-            Entity::new(EntityKind::Cannon),
+            self.make_entity(EntityKind::Cannon),
             shape,
             geom,
             Some(PLAYER_HEALTH_MAX),
         )
     }
 
-    /// Creates a bullet
+    /// Creates a bullet, travelling in the given direction - any vector, normalised to the bullet speed
     pub fn make_bullet(&self, center: P, direction: Vector) -> GameObject {
         let shape = Shape::new(
             center,
-            self.calc_size(BULLET_SIZE),
-            scale(direction, BULLET_SPEED),
+            Outline::Circle {
+                radius: self.calc_size(BULLET_SIZE) / 2,
+            },
+            normalize(direction, BULLET_SPEED),
             0.0,
             0.0,
         );
-        let geom = build_box_geometry(&shape);
-        (Entity::new(EntityKind::Bullet), shape, geom, None)
+        let geom = build_shape_geometry(&shape);
+        (self.make_entity(EntityKind::Bullet), shape, geom, None)
     }
 
+    /// Creates a baddie
     pub fn make_baddie(&self, start: P, vel: Vector, rotation_speed: f32) -> GameObject {
-        let shape = Shape::new(start, self.calc_size(BADDIE_SIZE), vel, 0.0, rotation_speed);
-        let geom = build_box_geometry(&shape);
-        (Entity::new(EntityKind::Baddie), shape, geom, None)
+        let shape = Shape::new(
+            start,
+            Outline::square(self.calc_size(BADDIE_SIZE)),
+            vel,
+            0.0,
+            rotation_speed,
+        );
+        let geom = build_shape_geometry(&shape);
+        (self.make_entity(EntityKind::Baddie), shape, geom, None)
     }
 
+    /// Creates a wall
     pub fn make_wall(&self, center: P) -> GameObject {
-        let shape = Shape::new(center, self.calc_size(WALL_SIZE), (0, 0), 0.0, 0.0);
-        let geom = build_box_geometry(&shape);
-        (Entity::new(EntityKind::Wall), shape, geom, None)
+        let shape = Shape::new(
+            center,
+            Outline::square(self.calc_size(WALL_SIZE)),
+            (0, 0),
+            0.0,
+            0.0,
+        );
+        let geom = build_shape_geometry(&shape);
+        (self.make_entity(EntityKind::Wall), shape, geom, None)
     }
 
     fn calc_size(&self, obj_size: f32) -> u32 {
         (self.base_size as f32 * obj_size) as u32
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A component that isn't part of the core set
+    #[derive(Debug, PartialEq)]
+    struct Lifetime(u32);
+    impl Component for Lifetime {}
+
+    #[test]
+    fn query_by_kind_and_components() {
+        // Arrange - 2 baddies, one with a lifetime, and a cannon (which has health)
+        let obj_factory = ObjectFactory::new(1000);
+        let baddie1 = obj_factory.make_baddie((1000, 1000), (0, 0), 0.0);
+        let baddie2 = obj_factory.make_baddie((3000, 1000), (0, 0), 0.0);
+        let baddie1_id = baddie1.0.get_id();
+        let mut world = create_world(
+            &obj_factory,
+            vec![baddie1, baddie2, obj_factory.make_cannon((5000, 5000))],
+        );
+        world.insert(baddie1_id, Lifetime(10));
+
+        // Act
+        let baddies = world.query().of_kind(EntityKind::Baddie).count();
+        let with_lifetime = world
+            .query()
+            .of_kind(EntityKind::Baddie)
+            .with::<Lifetime>()
+            .ids();
+        let with_health = world.query().with::<Shape>().with::<Health>().count();
+
+        // Assert
+        assert_eq!(baddies, 2);
+        assert_eq!(with_lifetime, vec![baddie1_id]);
+        assert_eq!(with_health, 1);
+    }
+
+    #[test]
+    fn remove_drops_all_components() {
+        // Arrange
+        let obj_factory = ObjectFactory::new(1000);
+        let baddie = obj_factory.make_baddie((1000, 1000), (0, 0), 0.0);
+        let id = baddie.0.get_id();
+        let mut world = create_world(&obj_factory, vec![baddie]);
+        world.insert(id, Lifetime(10));
+        world.get_mut::<Lifetime>(id).unwrap().0 -= 1;
+        assert_eq!(world.get::<Lifetime>(id), Some(&Lifetime(9)));
+
+        // Act
+        world.remove(id);
+
+        // Assert
+        assert!(world.entity(id).is_none());
+        assert!(world.get::<Shape>(id).is_none());
+        assert!(world.get::<Lifetime>(id).is_none());
+        // ID freed, with its slot reused by the next object made for the world
+        assert!(!obj_factory.is_alive(id));
+        let wall_id = obj_factory.make_wall((3000, 3000)).0.get_id();
+        assert_eq!(wall_id.index(), id.index());
+        assert_ne!(wall_id, id);
+    }
+
+    #[test]
+    fn systems_run_in_order() {
+        // Arrange - a system that ages every lifetime, and one that removes expired entities
+        let obj_factory = ObjectFactory::new(1000);
+        let baddie = obj_factory.make_baddie((1000, 1000), (0, 0), 0.0);
+        let id = baddie.0.get_id();
+        let mut world = create_world(&obj_factory, vec![baddie]);
+        world.insert(id, Lifetime(2));
+        let mut systems = Systems::new();
+        systems
+            .register("age", |world, _| {
+                for lifetime in Lifetime::storage_mut(world).values_mut() {
+                    lifetime.0 -= 1;
+                }
+            })
+            .register("expire", |world, _| {
+                let expired: Vec<EntityId> = Lifetime::storage_mut(world)
+                    .iter()
+                    .filter(|(_, lifetime)| lifetime.0 == 0)
+                    .map(|(id, _)| *id)
+                    .collect();
+                for id in expired {
+                    world.remove(id);
+                }
+            });
+
+        // Act & Assert
+        systems.run(&mut world, 10);
+        assert!(world.entity(id).is_some());
+        systems.run(&mut world, 10);
+        assert!(world.entity(id).is_none());
+        assert_eq!(systems.names(), vec!["age", "expire"]);
+    }
+}